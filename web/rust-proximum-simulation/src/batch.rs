@@ -0,0 +1,224 @@
+use crate::simulation::random_seed;
+use crate::types::{BatchResult, ErrorBands, PercentileBand, Simulation, SimulationConfig, Stats};
+use log::info;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+pub const DEFAULT_PERCENTILES: [f64; 4] = [5.0, 25.0, 75.0, 95.0];
+
+// Run independent replicas of the same config (replica i uses seed + i) and summarize the per-epoch error metrics.
+// Replicas run in parallel on native targets if requested.
+pub fn run_batch(
+    config: &SimulationConfig,
+    n_replicas: usize,
+    parallel: bool,
+    percentiles: &[f64],
+) -> Result<BatchResult, Box<dyn Error>> {
+    if n_replicas == 0 {
+        return Err("a batch needs at least one replica".into());
+    }
+    if let Some(p) = percentiles.iter().find(|p| !(0.0..=100.0).contains(*p)) {
+        return Err(format!("percentile {} is outside [0, 100]", p).into());
+    }
+
+    let base_seed = config.seed.unwrap_or_else(random_seed);
+    let seeds: Vec<u64> = (0..n_replicas as u64)
+        .map(|i| base_seed.wrapping_add(i))
+        .collect();
+    info!("running {} replicas from seed {}", n_replicas, base_seed);
+
+    let jobs: Vec<(SimulationConfig, u64)> =
+        seeds.iter().map(|&seed| (config.clone(), seed)).collect();
+    let stats = run_simulations(&jobs, parallel)?;
+
+    let assertion_replicas: Vec<&Vec<f64>> = stats.iter().map(|s| &s.assertion_rms_error).collect();
+
+    // every replica has the same number of dishonest nodes, so either all or none of them break errors down by honesty
+    let group_assertion_bands = |group: fn(&Stats) -> &Vec<f64>| {
+        let replicas: Vec<&Vec<f64>> = stats.iter().map(group).collect();
+        (!replicas[0].is_empty()).then(|| ErrorBands::new(&replicas, percentiles))
+    };
+
+    Ok(BatchResult {
+        config: config.clone(),
+        seeds,
+        estimation_rms_error: estimation_bands(&stats, |s| &s.estimation_rms_error, percentiles),
+        assertion_rms_error: ErrorBands::new(&assertion_replicas, percentiles),
+        honest_estimation_rms_error: estimation_bands(
+            &stats,
+            |s| &s.honest_estimation_rms_error,
+            percentiles,
+        ),
+        dishonest_estimation_rms_error: estimation_bands(
+            &stats,
+            |s| &s.dishonest_estimation_rms_error,
+            percentiles,
+        ),
+        honest_assertion_rms_error: group_assertion_bands(|s| &s.honest_assertion_rms_error),
+        dishonest_assertion_rms_error: group_assertion_bands(|s| &s.dishonest_assertion_rms_error),
+        dishonest_undetected_fraction: estimation_bands(
+            &stats,
+            |s| &s.dishonest_undetected_fraction,
+            percentiles,
+        ),
+        detection_true_positive_rate: estimation_bands(
+            &stats,
+            |s| &s.detection_true_positive_rate,
+            percentiles,
+        ),
+        detection_false_positive_rate: estimation_bands(
+            &stats,
+            |s| &s.detection_false_positive_rate,
+            percentiles,
+        ),
+        detection_auc: estimation_bands(&stats, |s| &s.detection_auc, percentiles),
+        sprt_true_positive_rate: group_assertion_bands(|s| &s.sprt_true_positive_rate),
+        sprt_false_positive_rate: group_assertion_bands(|s| &s.sprt_false_positive_rate),
+        sprt_decided_fraction: group_assertion_bands(|s| &s.sprt_decided_fraction),
+    })
+}
+
+// summarize a per-estimator metric across replicas
+fn estimation_bands(
+    stats: &[Stats],
+    metric: fn(&Stats) -> &BTreeMap<String, Vec<f64>>,
+    percentiles: &[f64],
+) -> BTreeMap<String, ErrorBands> {
+    metric(&stats[0])
+        .keys()
+        .map(|name| {
+            let replicas: Vec<&Vec<f64>> = stats.iter().map(|s| &metric(s)[name]).collect();
+            (name.clone(), ErrorBands::new(&replicas, percentiles))
+        })
+        .collect()
+}
+
+fn run_simulation(config: &SimulationConfig, seed: u64) -> Result<Stats, String> {
+    let mut config = config.clone();
+    config.seed = Some(seed);
+
+    let error =
+        |e: Box<dyn std::error::Error>| format!("simulation with seed {} failed: {}", seed, e);
+    let mut simulation = Simulation::new(config).map_err(error)?;
+    simulation.run().map_err(error)?;
+    Ok(simulation.stats)
+}
+
+// run each (config, seed) pair to completion and return the stats in the same order
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub(crate) fn run_simulations(
+    jobs: &[(SimulationConfig, u64)],
+    parallel: bool,
+) -> Result<Vec<Stats>, String> {
+    // threads are not available in the browser
+    #[cfg(not(target_arch = "wasm32"))]
+    if parallel {
+        use rayon::prelude::*;
+        return jobs
+            .par_iter()
+            .map(|(config, seed)| run_simulation(config, *seed))
+            .collect();
+    }
+
+    jobs.iter()
+        .map(|(config, seed)| run_simulation(config, *seed))
+        .collect()
+}
+
+impl ErrorBands {
+    // summarize one metric epoch by epoch, where each replica contributes one value per epoch
+    pub fn new(replicas: &[&Vec<f64>], percentiles: &[f64]) -> Self {
+        let n_epochs = replicas.iter().map(|r| r.len()).min().unwrap_or(0);
+
+        let mut bands = ErrorBands {
+            mean: Vec::with_capacity(n_epochs),
+            standard_error: Vec::with_capacity(n_epochs),
+            median: Vec::with_capacity(n_epochs),
+            percentiles: percentiles
+                .iter()
+                .map(|&percentile| PercentileBand {
+                    percentile,
+                    values: Vec::with_capacity(n_epochs),
+                })
+                .collect(),
+        };
+
+        for epoch in 0..n_epochs {
+            let mut values: Vec<f64> = replicas.iter().map(|r| r[epoch]).collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = if values.len() > 1 {
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+            } else {
+                0.0
+            };
+
+            bands.mean.push(mean);
+            bands.standard_error.push((variance / n).sqrt());
+            bands.median.push(percentile(&values, 50.0));
+            for band in bands.percentiles.iter_mut() {
+                band.values.push(percentile(&values, band.percentile));
+            }
+        }
+
+        bands
+    }
+}
+
+// linearly interpolated percentile (0-100) of sorted values
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+
+    #[test]
+    fn error_bands_summarize_each_epoch() {
+        let (a, b, c) = (vec![1.0, 10.0, 5.0], vec![3.0, 20.0], vec![2.0, 30.0, 7.0]);
+        let bands = ErrorBands::new(&[&a, &b, &c], &[0.0, 25.0, 100.0]);
+        // replicas are cut to the shortest one
+        assert_eq!(bands.mean, vec![2.0, 20.0]);
+        assert_eq!(bands.median, vec![2.0, 20.0]);
+        // sample standard deviation over sqrt(n)
+        assert!((bands.standard_error[0] - (1.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert!((bands.standard_error[1] - (100.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        let values: Vec<&Vec<f64>> = bands.percentiles.iter().map(|band| &band.values).collect();
+        assert_eq!(
+            values,
+            [&vec![1.0, 10.0], &vec![1.5, 15.0], &vec![3.0, 30.0]]
+        );
+
+        let single = ErrorBands::new(&[&a], &[]);
+        assert_eq!(single.standard_error, vec![0.0; 3]);
+    }
+
+    #[test]
+    fn batch_replicas_use_consecutive_seeds() {
+        let mut config = example_config();
+        config.n_nodes = 10;
+        config.n_epochs = 3;
+        config.seed = Some(7);
+        let batch = run_batch(&config, 3, false, &DEFAULT_PERCENTILES).unwrap();
+        assert_eq!(batch.seeds, vec![7, 8, 9]);
+        assert_eq!(batch.assertion_rms_error.mean.len(), 3);
+        assert_eq!(batch.assertion_rms_error.percentiles.len(), 4);
+
+        // replicas are reproducible whether or not they run in parallel
+        let parallel = run_batch(&config, 3, true, &DEFAULT_PERCENTILES).unwrap();
+        assert_eq!(
+            serde_json::to_string(&batch).unwrap(),
+            serde_json::to_string(&parallel).unwrap()
+        );
+
+        assert!(run_batch(&config, 0, false, &DEFAULT_PERCENTILES).is_err());
+        assert!(run_batch(&config, 1, false, &[101.0]).is_err());
+    }
+}
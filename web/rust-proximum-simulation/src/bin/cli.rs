@@ -0,0 +1,200 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use rust_proximum_simulation::batch::{run_batch, DEFAULT_PERCENTILES};
+use rust_proximum_simulation::import::{parse_node_locations, parse_rtt_dataset};
+use rust_proximum_simulation::sweep::run_sweep;
+use rust_proximum_simulation::types::{Simulation, SimulationConfig, SweepSpec};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Run simulations natively (e.g. on servers or in scripts) instead of in the browser
+#[derive(Parser)]
+#[command(about = "Headless runner for the Proximum location oracle simulation")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run one simulation for `n_epochs` epochs and write the config, final nodes and stats as JSON
+    Run {
+        /// Simulation config (.toml or .json)
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Override the seed set in the config
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Node locations to simulate instead of sampling them (.csv, or GeoJSON FeatureCollection of points)
+        #[arg(long)]
+        nodes: Option<PathBuf>,
+        /// Measured round trip times between the imported nodes to replay instead of simulating them (.csv or .json)
+        #[arg(long, requires = "nodes")]
+        rtts: Option<PathBuf>,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run independent Monte Carlo replicas of one config and write per-epoch error statistics as JSON
+    Batch {
+        /// Simulation config (.toml or .json)
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Number of replicas
+        #[arg(short = 'n', long)]
+        replicas: usize,
+        /// Base seed (replica i uses seed + i); overrides the seed set in the config
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Node locations to simulate instead of sampling them (.csv, or GeoJSON FeatureCollection of points)
+        #[arg(long)]
+        nodes: Option<PathBuf>,
+        /// Measured round trip times between the imported nodes to replay instead of simulating them (.csv or .json)
+        #[arg(long, requires = "nodes")]
+        rtts: Option<PathBuf>,
+        /// Run replicas in parallel
+        #[arg(short, long)]
+        parallel: bool,
+        /// Percentile bands to report, in [0, 100]
+        #[arg(long, value_delimiter = ',', default_values_t = DEFAULT_PERCENTILES)]
+        percentiles: Vec<f64>,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Simulate every combination of swept config values and write a tidy table of error metrics
+    Sweep {
+        /// Base simulation config (.toml or .json)
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Sweep specification (.toml or .json) listing the swept fields and their values
+        #[arg(short, long)]
+        spec: PathBuf,
+        /// Base seed (replica i uses seed + i); overrides the seed set in the config
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Node locations to simulate instead of sampling them (.csv, or GeoJSON FeatureCollection of points)
+        #[arg(long)]
+        nodes: Option<PathBuf>,
+        /// Measured round trip times between the imported nodes to replay instead of simulating them (.csv or .json)
+        #[arg(long, requires = "nodes")]
+        rtts: Option<PathBuf>,
+        /// Run simulations in parallel
+        #[arg(short, long)]
+        parallel: bool,
+        /// Only report the final epoch of each simulation
+        #[arg(long)]
+        final_only: bool,
+        #[arg(short, long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TableFormat {
+    Csv,
+    Json,
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => Ok(toml::from_str(&contents)?),
+        _ => Ok(serde_json::from_str(&contents)?),
+    }
+}
+
+// Load a config, overriding its seed, node locations and RTT dataset if given
+fn load_config(
+    path: &Path,
+    seed: Option<u64>,
+    nodes: Option<&Path>,
+    rtts: Option<&Path>,
+) -> Result<SimulationConfig, Box<dyn Error>> {
+    let mut config: SimulationConfig = load(path)?;
+    if seed.is_some() {
+        config.seed = seed;
+    }
+    if let Some(nodes) = nodes {
+        config.node_locations = Some(parse_node_locations(&fs::read_to_string(nodes)?)?);
+    }
+    if let (Some(rtts), Some(locations)) = (rtts, &config.node_locations) {
+        config.rtt_dataset = Some(parse_rtt_dataset(&fs::read_to_string(rtts)?, locations)?);
+    }
+    Ok(config)
+}
+
+fn write_output(contents: &str, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(path) => fs::write(path, contents)?,
+        None => writeln!(io::stdout(), "{}", contents)?,
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    match Cli::parse().command {
+        Command::Run {
+            config,
+            seed,
+            nodes,
+            rtts,
+            output,
+        } => {
+            let config = load_config(&config, seed, nodes.as_deref(), rtts.as_deref())?;
+
+            let mut simulation = Simulation::new(config)?;
+            simulation.run()?;
+
+            write_output(
+                &serde_json::to_string_pretty(&simulation)?,
+                output.as_deref(),
+            )
+        }
+        Command::Batch {
+            config,
+            replicas,
+            seed,
+            nodes,
+            rtts,
+            parallel,
+            percentiles,
+            output,
+        } => {
+            let config = load_config(&config, seed, nodes.as_deref(), rtts.as_deref())?;
+
+            let result = run_batch(&config, replicas, parallel, &percentiles)?;
+
+            write_output(&serde_json::to_string_pretty(&result)?, output.as_deref())
+        }
+        Command::Sweep {
+            config,
+            spec,
+            seed,
+            nodes,
+            rtts,
+            parallel,
+            final_only,
+            format,
+            output,
+        } => {
+            let config = load_config(&config, seed, nodes.as_deref(), rtts.as_deref())?;
+            let spec: SweepSpec = load(&spec)?;
+
+            let table = run_sweep(&config, &spec, parallel, final_only)?;
+
+            let contents = match format {
+                TableFormat::Csv => table.to_csv(),
+                TableFormat::Json => serde_json::to_string_pretty(&table.to_records())?,
+            };
+            write_output(&contents, output.as_deref())
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::kalman::OS;
+use crate::physics::{generate_timed_measurements, Measurements};
+use crate::types::{Node, RttReplay, RttSample, SimulationConfig};
+use nalgebra::OVector;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// A node's measurements, with their one-way ping times if estimators get them (see `physics::TimedMeasurements`)
+pub type NodeMeasurements = (Measurements, Option<OVector<f64, OS>>);
+
+impl RttReplay {
+    // Index a dataset's samples by the nodes at both ends
+    pub fn new(dataset: &[RttSample], n_nodes: usize) -> Result<Self, Box<dyn Error>> {
+        let mut samples = vec![Vec::new(); n_nodes];
+        for sample in dataset {
+            if sample.source >= n_nodes || sample.target >= n_nodes {
+                return Err(format!(
+                    "RTT sample between nodes {} and {} but there are {} nodes",
+                    sample.source, sample.target, n_nodes
+                )
+                .into());
+            }
+            if sample.source == sample.target {
+                return Err(format!("RTT sample from node {} to itself", sample.source).into());
+            }
+            if !sample.rtt.is_finite() || sample.rtt < 0.0 {
+                return Err(format!(
+                    "RTT sample between nodes {} and {} has an invalid time {}",
+                    sample.source, sample.target, sample.rtt
+                )
+                .into());
+            }
+            samples[sample.source].push(*sample);
+            samples[sample.target].push(RttSample {
+                source: sample.target,
+                target: sample.source,
+                ..*sample
+            });
+        }
+        let n_epochs = dataset
+            .iter()
+            .filter_map(|sample| sample.epoch)
+            .max()
+            .map(|epoch| epoch + 1);
+        Ok(RttReplay { samples, n_epochs })
+    }
+
+    // A batch of measured round trip times from a node: `n_measurements` distinct peers with samples in this epoch
+    // (samples without an epoch are always available; epochs past the dataset's wrap around), each with one of its
+    // samples. Dishonest nodes replay their measurements too: delay attacks and collusion are not simulated.
+    pub fn measurements(
+        &self,
+        my_index: usize,
+        epoch: usize,
+        nodes: &[Node],
+        config: &SimulationConfig,
+        rng: &mut impl Rng,
+    ) -> Result<Measurements, Box<dyn Error>> {
+        let epoch = self.n_epochs.map(|n_epochs| epoch % n_epochs);
+        let mut peers: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        for sample in &self.samples[my_index] {
+            let node = &nodes[sample.target];
+            if (sample.epoch.is_none() || sample.epoch == epoch)
+                // stop measuring untrusted nodes (until their reputation recovers)
+                && (!config.reputation || node.trust >= config.reputation_threshold)
+            {
+                peers.entry(sample.target).or_default().push(sample.rtt);
+            }
+        }
+
+        if peers.len() < config.n_measurements {
+            return Err(format!(
+                "Not enough measured peers. Found {} but need {}",
+                peers.len(),
+                config.n_measurements
+            )
+            .into());
+        }
+
+        let peers: Vec<(usize, Vec<f64>)> = peers.into_iter().collect();
+        let selected: Vec<&(usize, Vec<f64>)> =
+            peers.choose_multiple(rng, config.n_measurements).collect();
+        let (their_indices, times): (Vec<usize>, Vec<f64>) = selected
+            .into_iter()
+            .map(|(i, rtts)| (*i, *rtts.choose(rng).expect("peers have samples")))
+            .unzip();
+        Ok((their_indices, OVector::<f64, OS>::from_vec(times)))
+    }
+}
+
+// A node's measurements for an epoch: replayed from the RTT dataset if there is one, simulated otherwise. Simulated
+// measurements come with their one-way ping times when `SimulationConfig::one_way_timestamps` is set; datasets only
+// have round trip times.
+pub fn node_measurements(
+    my_index: usize,
+    replay: Option<&RttReplay>,
+    epoch: usize,
+    nodes: &[Node],
+    config: &SimulationConfig,
+    rng: &mut impl Rng,
+) -> Result<NodeMeasurements, Box<dyn Error>> {
+    match replay {
+        Some(replay) => Ok((
+            replay.measurements(my_index, epoch, nodes, config, rng)?,
+            None,
+        )),
+        None => {
+            let (measurements, ping_times) = generate_timed_measurements(
+                nodes[my_index].true_position,
+                nodes[my_index].true_beta,
+                nodes[my_index].true_tau,
+                Some(my_index),
+                nodes,
+                config,
+                rng,
+            )?;
+            Ok((
+                measurements,
+                config.one_way_timestamps.then_some(ping_times),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(source: usize, target: usize, rtt: f64) -> RttSample {
+        RttSample {
+            source,
+            target,
+            rtt,
+            epoch: None,
+        }
+    }
+
+    #[test]
+    fn replay_indexes_samples_by_both_ends() {
+        let replay = RttReplay::new(&[sample(0, 1, 0.02), sample(2, 0, 0.05)], 3).unwrap();
+        assert_eq!(replay.samples[0].len(), 2);
+        assert_eq!(
+            (replay.samples[1][0].source, replay.samples[1][0].target),
+            (1, 0)
+        );
+        assert_eq!(
+            (replay.samples[0][1].source, replay.samples[0][1].target),
+            (0, 2)
+        );
+        assert_eq!(replay.n_epochs, None);
+    }
+
+    #[test]
+    fn replay_rejects_invalid_samples() {
+        assert!(RttReplay::new(&[sample(0, 3, 0.02)], 3).is_err());
+        assert!(RttReplay::new(&[sample(1, 1, 0.02)], 3).is_err());
+        assert!(RttReplay::new(&[sample(0, 1, -0.02)], 3).is_err());
+        assert!(RttReplay::new(&[sample(0, 1, f64::NAN)], 3).is_err());
+        assert!(RttReplay::new(&[sample(0, 1, f64::INFINITY)], 3).is_err());
+    }
+}
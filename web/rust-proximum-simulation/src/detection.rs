@@ -0,0 +1,267 @@
+use crate::estimator::PositionEstimator;
+use crate::geometry::ecef_to_enu_rotation;
+use crate::types::{Node, RocPoint, SimulationConfig, Stats};
+use log::trace;
+use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+use nav_types::WGS84;
+
+// ECEF covariance (m^2) of an honest node's assertion error: gaussian in the East-North plane
+pub fn assertion_covariance(node: &Node, config: &SimulationConfig) -> Matrix3<f64> {
+    let rotation = ecef_to_enu_rotation(WGS84::from(node.asserted_position));
+    let variance = config.asserted_position_variance;
+    rotation.transpose() * Matrix3::from_diagonal(&Vector3::new(variance, variance, 0.0)) * rotation
+}
+
+// ECEF covariance (m^2) between the error of a node's initial position and its assertion error: the assertion's own
+// when the estimators start from it, none when they start from an independent guess (see `InitStrategy`)
+pub fn initial_assertion_cross_covariance(node: &Node, config: &SimulationConfig) -> Matrix3<f64> {
+    if node.initial_position == node.asserted_position {
+        assertion_covariance(node, config)
+    } else {
+        Matrix3::zeros()
+    }
+}
+
+// Chi-square statistic of a node's assertion against an estimate: the squared Mahalanobis distance, in the
+// East-North plane, between the asserted position and the estimate. The difference's covariance is the assertion's
+// noise plus the estimate's covariance, less their correlation: estimators that start from the assertion (or weigh it
+// as a prior) carry its error into their estimate. For an honest node it follows a chi-square distribution with 2
+// degrees of freedom. None for estimators that don't track a covariance.
+pub fn assertion_statistic(
+    node: &Node,
+    estimator: &dyn PositionEstimator,
+    config: &SimulationConfig,
+) -> Option<f64> {
+    let ecef_covariance = estimator.covariance(node.id)?;
+    let estimate = &node.estimates[estimator.name()];
+    let rotation = ecef_to_enu_rotation(estimate.wgs84);
+
+    let delta = node.asserted_position - estimate.position;
+    let delta_en = Vector2::new(delta.east(), delta.north());
+
+    let mut difference_covariance = ecef_covariance;
+    if let Some(cross_covariance) = estimator.assertion_cross_covariance(node.id) {
+        difference_covariance -= cross_covariance + cross_covariance.transpose();
+    }
+    let enu_covariance = rotation * difference_covariance * rotation.transpose();
+    let covariance = Matrix2::identity() * config.asserted_position_variance
+        + enu_covariance.fixed_view::<2, 2>(0, 0);
+
+    Some(match covariance.try_inverse() {
+        Some(inverse) => (delta_en.transpose() * inverse * delta_en)[0],
+        None if delta_en.norm() > 0.0 => f64::INFINITY,
+        None => 0.0,
+    })
+}
+
+// Distance (m) from its assertion beyond which a node is considered elsewhere: `SimulationConfig::detection_distance`,
+// or by default 3 standard deviations of an honest assertion
+pub fn detection_distance(config: &SimulationConfig) -> f64 {
+    config
+        .detection_distance
+        .unwrap_or(3.0 * config.asserted_position_variance.sqrt())
+}
+
+// Threshold on the statistic for a false alarm rate: the chi-square (2 degrees of freedom) quantile, which has the
+// closed form -2 ln(α)
+pub fn chi_square_threshold(false_alarm_rate: f64) -> f64 {
+    -2.0 * false_alarm_rate.ln()
+}
+
+// ROC curve of scores (higher means more likely dishonest) against the true labels: one point per distinct score
+fn roc_curve(scores: &[(f64, bool)]) -> Vec<RocPoint> {
+    let n_positive = scores.iter().filter(|(_, dishonest)| *dishonest).count() as f64;
+    let n_negative = scores.len() as f64 - n_positive;
+
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut curve = Vec::new();
+    let (mut true_positives, mut false_positives) = (0.0, 0.0);
+    for (i, &(score, dishonest)) in sorted.iter().enumerate() {
+        if dishonest {
+            true_positives += 1.0;
+        } else {
+            false_positives += 1.0;
+        }
+        // nodes with equal scores are flagged together
+        if sorted.get(i + 1).is_none_or(|next| next.0 < score) {
+            curve.push(RocPoint {
+                threshold: score,
+                true_positive_rate: true_positives / n_positive,
+                false_positive_rate: false_positives / n_negative,
+            });
+        }
+    }
+    curve
+}
+
+// area under a ROC curve (trapezoidal, starting from the origin)
+fn roc_auc(curve: &[RocPoint]) -> f64 {
+    let mut area = 0.0;
+    let (mut previous_fpr, mut previous_tpr) = (0.0, 0.0);
+    for point in curve {
+        area += (point.false_positive_rate - previous_fpr)
+            * (point.true_positive_rate + previous_tpr)
+            / 2.0;
+        previous_fpr = point.false_positive_rate;
+        previous_tpr = point.true_positive_rate;
+    }
+    area
+}
+
+// Test every node's assertion against each estimator's estimate and record detection rates, the ROC curve and
+// detection latency. The false positive rate is also recorded when every node is honest, to check that the test is
+// calibrated (it should be close to `detection_false_alarm_rate`). Estimators without a covariance aren't tested. The
+// per-node filters ignore how their peers' errors correlate with their own, so on small networks where each node
+// measures most of its peers every epoch (the ekf especially) they grow overconfident and flag more honest nodes.
+pub fn log_detection(
+    stats: &mut Stats,
+    nodes: &[Node],
+    estimators: &[Box<dyn PositionEstimator>],
+    config: &SimulationConfig,
+) {
+    let n_dishonest = nodes.iter().filter(|node| !node.honest).count();
+    if n_dishonest == nodes.len() {
+        return;
+    }
+    let n_honest = nodes.len() - n_dishonest;
+    let threshold = chi_square_threshold(config.detection_false_alarm_rate);
+    // the assertion error stats are logged first, so this is the current epoch counting from 1
+    let epoch = stats.assertion_rms_error.len();
+
+    for estimator in estimators {
+        let name = estimator.name().to_string();
+        let Some(scores) = nodes
+            .iter()
+            .map(|node| {
+                assertion_statistic(node, estimator.as_ref(), config)
+                    .map(|score| (score, !node.honest))
+            })
+            .collect::<Option<Vec<(f64, bool)>>>()
+        else {
+            continue;
+        };
+
+        let first_detections = stats
+            .first_detection_epoch
+            .entry(name.clone())
+            .or_insert_with(|| vec![None; nodes.len()]);
+        let (mut true_positives, mut false_positives) = (0, 0);
+        for (node, &(score, dishonest)) in nodes.iter().zip(&scores) {
+            if score < threshold {
+                continue;
+            }
+            trace!("{} flags node {} (statistic {})", name, node.id, score);
+            if dishonest {
+                true_positives += 1;
+            } else {
+                false_positives += 1;
+            }
+            first_detections[node.id].get_or_insert(epoch);
+        }
+
+        stats
+            .detection_false_positive_rate
+            .entry(name.clone())
+            .or_default()
+            .push(false_positives as f64 / n_honest as f64);
+        if n_dishonest == 0 {
+            continue;
+        }
+
+        let latencies: Vec<f64> = nodes
+            .iter()
+            .filter(|node| !node.honest)
+            .filter_map(|node| first_detections[node.id].map(|epoch| epoch as f64))
+            .collect();
+        let latency =
+            (!latencies.is_empty()).then(|| latencies.iter().sum::<f64>() / latencies.len() as f64);
+
+        let roc = roc_curve(&scores);
+        stats
+            .detection_true_positive_rate
+            .entry(name.clone())
+            .or_default()
+            .push(true_positives as f64 / n_dishonest as f64);
+        stats
+            .detection_auc
+            .entry(name.clone())
+            .or_default()
+            .push(roc_auc(&roc));
+        stats.detection_roc.insert(name.clone(), roc);
+        stats.detection_latency.insert(name, latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::{EstimatorKind, Simulation};
+
+    #[test]
+    fn roc_auc_of_separated_and_reversed_scores() {
+        let separated = [(3.0, true), (2.0, true), (1.0, false), (0.5, false)];
+        assert_eq!(roc_auc(&roc_curve(&separated)), 1.0);
+        let reversed = separated.map(|(score, dishonest)| (score, !dishonest));
+        assert_eq!(roc_auc(&roc_curve(&reversed)), 0.0);
+    }
+
+    // On all-honest networks, each estimator that tests assertions flags `detection_false_alarm_rate` of them: the
+    // last epoch's decisions, pooled over seeds, fall within 3 binomial standard deviations of it. The UKF is only
+    // checked not to exceed it: its sigma points don't carry the correlation between its estimate and its peers',
+    // which leaves its assertion cross-covariance and hence its statistic on the low side, so it flags fewer.
+    #[test]
+    fn false_positive_rate_is_calibrated() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.n_measurements = 5;
+        config.n_epochs = 20;
+        config.asserted_position_variance = 1e10;
+        config.pf_n_particles = 200;
+        config.detection_false_alarm_rate = 0.05;
+        config.estimators = vec![
+            EstimatorKind::Ls,
+            EstimatorKind::Ekf,
+            EstimatorKind::Ukf,
+            EstimatorKind::Pf,
+            EstimatorKind::Joint,
+        ];
+        let names = ["ekf", "ukf", "pf", "joint"];
+        let n_seeds = 20;
+
+        let mut flagged = [0.0; 4];
+        for seed in 0..n_seeds {
+            config.seed = Some(seed);
+            let mut simulation = Simulation::new(config.clone()).unwrap();
+            simulation.run().unwrap();
+            let rates = &simulation.stats.detection_false_positive_rate;
+            assert!(!rates.contains_key("ls"));
+            for (count, name) in flagged.iter_mut().zip(names) {
+                *count += rates[name].last().unwrap() * config.n_nodes as f64;
+            }
+        }
+
+        let alpha = config.detection_false_alarm_rate;
+        let n_samples = (n_seeds as usize * config.n_nodes) as f64;
+        let band = 3.0 * (alpha * (1.0 - alpha) / n_samples).sqrt();
+        for (count, name) in flagged.iter().zip(names) {
+            let rate = count / n_samples;
+            assert!(
+                rate <= alpha + band,
+                "{} false positive rate {}",
+                name,
+                rate
+            );
+            if name != "ukf" {
+                assert!(
+                    rate >= alpha - band,
+                    "{} false positive rate {}",
+                    name,
+                    rate
+                );
+            }
+        }
+    }
+}
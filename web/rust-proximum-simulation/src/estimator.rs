@@ -0,0 +1,200 @@
+use crate::geometry::{ecef_to_enu_rotation, ecef_to_h3};
+use crate::joint::JointEstimator;
+use crate::kalman::{ExtendedKalmanFilter, OS};
+use crate::least_squares::LeastSquares;
+use crate::particle_filter::ParticleFilter;
+use crate::physics::Measurements;
+use crate::ransac::Ransac;
+use crate::types::{Estimate, EstimatorKind, MeasurementWeight, Node, SimulationConfig};
+use crate::ukf::UnscentedKalmanFilter;
+use h3o::Resolution;
+use log::trace;
+use nalgebra::{Const, Matrix3, OVector, Vector3};
+use nav_types::{ECEF, WGS84};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// A position estimation algorithm. Each estimator keeps its own state for every node in the network
+// and reports a common set of quantities so estimates and stats can be compared across algorithms.
+pub trait PositionEstimator {
+    // key under which this estimator's estimates and stats are reported
+    fn name(&self) -> &'static str;
+
+    // start estimating a new node from its initial position. Nodes are initialized in id order.
+    fn init(&mut self, node: &Node, config: &SimulationConfig);
+
+    // update one node's estimate from a batch of measurements to its peers
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>>;
+
+    // update from a batch with per-direction timestamps (see `SimulationConfig::one_way_timestamps`). The filters (ekf,
+    // ukf, pf) score each leg separately; the round trip solvers fall back to the round trip times.
+    fn update_timed(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        // one-way ping times (see `physics::TimedMeasurements`)
+        _ping_times: &OVector<f64, OS>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        self.update(index, measurements, nodes, config)
+    }
+
+    // called once every node has been updated in an epoch, for estimators that work on the whole network at once
+    fn end_epoch(
+        &mut self,
+        _nodes: &[Node],
+        _config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64>;
+
+    // move a node's position estimate (e.g. back inside the simulated region), for estimators that can
+    fn set_position(&mut self, _index: usize, _position: ECEF<f64>) {}
+
+    // ECEF position covariance (m^2), if the estimator tracks one
+    fn covariance(&self, _index: usize) -> Option<Matrix3<f64>> {
+        None
+    }
+
+    // ECEF covariance (m^2) between the position estimate's error and the node's assertion error, for estimators whose
+    // estimate draws on the assertion (see `detection`); None if they are independent
+    fn assertion_cross_covariance(&self, _index: usize) -> Option<Matrix3<f64>> {
+        None
+    }
+
+    // estimated message speed (fraction of c), if the estimator models it
+    fn beta(&self, _index: usize) -> Option<f64> {
+        None
+    }
+
+    // estimated latency (s), if the estimator models it
+    fn tau(&self, _index: usize) -> Option<f64> {
+        None
+    }
+
+    // effective sample size of a sample based estimator's belief
+    fn effective_sample_size(&self, _index: usize) -> Option<f64> {
+        None
+    }
+
+    // weights given to the peers' measurements in the node's last update, if the estimator reweights them
+    fn measurement_weights(&self, _index: usize) -> Option<Vec<MeasurementWeight>> {
+        None
+    }
+
+    // number of times the node rejected each peer's measurement, if the estimator rejects outliers
+    fn rejected_peers(&self, _index: usize) -> Option<BTreeMap<usize, usize>> {
+        None
+    }
+
+    // number of times the estimator has re-initialized a diverged node, if it can diverge
+    fn resets(&self) -> Option<usize> {
+        None
+    }
+
+    fn estimate(&self, index: usize) -> Result<Estimate, Box<dyn Error>> {
+        Estimate::new(
+            self.position(index),
+            self.covariance(index),
+            self.beta(index),
+            self.tau(index),
+            self.effective_sample_size(index),
+            self.measurement_weights(index),
+            self.rejected_peers(index),
+        )
+    }
+}
+
+impl EstimatorKind {
+    pub fn build(&self, config: &SimulationConfig) -> Box<dyn PositionEstimator> {
+        match self {
+            EstimatorKind::Ls => Box::new(LeastSquares::default()),
+            EstimatorKind::Ekf => Box::new(ExtendedKalmanFilter::new(config)),
+            EstimatorKind::Ukf => Box::new(UnscentedKalmanFilter::new(config)),
+            EstimatorKind::Pf => Box::new(ParticleFilter::new(config)),
+            EstimatorKind::Joint => Box::new(JointEstimator::new(config)),
+            EstimatorKind::Ransac => Box::new(Ransac::new(config)),
+        }
+    }
+}
+
+impl Estimate {
+    pub fn new(
+        position: ECEF<f64>,
+        covariance: Option<Matrix3<f64>>,
+        beta: Option<f64>,
+        tau: Option<f64>,
+        effective_sample_size: Option<f64>,
+        measurement_weights: Option<Vec<MeasurementWeight>>,
+        rejected_peers: Option<BTreeMap<usize, usize>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let wgs84 = WGS84::from(position);
+
+        let mut estimate = Estimate {
+            index: ecef_to_h3(position, Resolution::try_from(10).unwrap())?,
+            position,
+            wgs84,
+            beta,
+            tau,
+            en_variance_semimajor_axis: OVector::<f64, Const<2>>::zeros(),
+            en_variance_semimajor_axis_length: 0.0,
+            en_variance_semiminor_axis_length: 0.0,
+            effective_sample_size,
+            measurement_weights,
+            rejected_peers,
+        };
+
+        if let Some(ecef_covariance) = covariance {
+            estimate.set_en_variance_ellipse(ecef_covariance);
+        }
+
+        Ok(estimate)
+    }
+
+    // project the eigenvectors/values of the ECEF covariance onto EN coordinates to plot a confidence ellipse
+    fn set_en_variance_ellipse(&mut self, ecef_covariance: Matrix3<f64>) {
+        trace!("Covariance: {:#?}", ecef_covariance);
+        let eigendecomposition = ecef_covariance.symmetric_eigen();
+        let eigenvectors = eigendecomposition.eigenvectors;
+        let eigenvalues = eigendecomposition.eigenvalues;
+
+        // TODO: sort eigenvalues, fix issue returning zeros
+        // TODO: surely there is some way to do this transformation in nav_types?
+
+        let ecef_to_enu_matrix = ecef_to_enu_rotation(self.wgs84);
+
+        let enu_eigenvectors = ecef_to_enu_matrix * eigenvectors;
+
+        let en_semimajor_axis_projection =
+            Vector3::new(enu_eigenvectors[(0, 0)], enu_eigenvectors[(1, 0)], 0.0);
+
+        let en_semiminor_axis_projection =
+            Vector3::new(enu_eigenvectors[(0, 1)], enu_eigenvectors[(1, 1)], 0.0);
+
+        let en_semimajor_length_projection = (en_semimajor_axis_projection[0].powi(2)
+            + en_semimajor_axis_projection[1].powi(2))
+        .sqrt()
+            * eigenvalues[0].sqrt();
+
+        let en_semiminor_length_projection = (en_semiminor_axis_projection[0].powi(2)
+            + en_semiminor_axis_projection[1].powi(2))
+        .sqrt()
+            * eigenvalues[1].sqrt();
+
+        // Project the ENU eigenvectors onto the East-North plane
+        self.en_variance_semimajor_axis =
+            OVector::<f64, Const<2>>::new(enu_eigenvectors[(0, 0)], enu_eigenvectors[(1, 0)]);
+
+        self.en_variance_semimajor_axis_length = en_semimajor_length_projection;
+        self.en_variance_semiminor_axis_length = en_semiminor_length_projection;
+    }
+}
@@ -1,11 +1,16 @@
 use h3o::{CellIndex, LatLng, Resolution};
 use log::info;
 use rand::Rng;
+use std::error::Error;
 use std::f64::consts::PI;
+
+// mean earth radius (m) for spherical approximations
+const EARTH_RADIUS: f64 = 6_371_000.0;
 extern crate nav_types;
+use crate::types::{LieStrategy, Region, SybilAttack};
 use log::trace;
+use nalgebra::Matrix3;
 use nav_types::{ECEF, ENU, WGS84};
-use rand::thread_rng;
 use rand_distr::Distribution;
 use rand_distr::Normal;
 
@@ -20,19 +25,22 @@ pub fn h3_to_ecef(h3_index: CellIndex) -> ECEF<f64> {
     position
 }
 
-// Convert an ECEF position to an H3 index
-pub fn ecef_to_h3(position: ECEF<f64>, resolution: Resolution) -> CellIndex {
+// Convert an ECEF position to an H3 index. Fails for non-finite positions (e.g. from a diverged estimator).
+pub fn ecef_to_h3(
+    position: ECEF<f64>,
+    resolution: Resolution,
+) -> Result<CellIndex, Box<dyn Error>> {
+    if !(position.x().is_finite() && position.y().is_finite() && position.z().is_finite()) {
+        return Err(format!("invalid ECEF position: {:?}", position).into());
+    }
     let wgs84 = WGS84::from(position);
     trace!("ecef_position: {:#?}, wgs84 latlng: {:#?}", position, wgs84);
-    let lat_lng = LatLng::from_radians(wgs84.latitude_radians(), wgs84.longitude_radians())
-        .expect("invalid position");
-    let cell = lat_lng.to_cell(resolution);
-    cell
+    let lat_lng = LatLng::from_radians(wgs84.latitude_radians(), wgs84.longitude_radians())?;
+    Ok(lat_lng.to_cell(resolution))
 }
 
 // get a random H3 index drawing from a uniform distribution over the earth's surface
-pub fn uniform_h3_index(resolution: Resolution) -> CellIndex {
-    let mut rng = rand::thread_rng();
+pub fn uniform_h3_index(resolution: Resolution, rng: &mut impl Rng) -> CellIndex {
     let u: f64 = rng.gen_range(0.0..=1.0);
     let v: f64 = rng.gen_range(0.0..=1.0);
 
@@ -44,31 +52,188 @@ pub fn uniform_h3_index(resolution: Resolution) -> CellIndex {
 
     let lat_lng =
         LatLng::from_radians(lat, lng).expect("Failed to convert ECEF position to H3 index");
-    let cell = lat_lng.to_cell(resolution);
-    cell
+    lat_lng.to_cell(resolution)
 }
 
-pub fn normal_neighbor_index(mean: CellIndex, variance: f64, resolution: Resolution) -> CellIndex {
+pub fn normal_neighbor_index(
+    mean: CellIndex,
+    variance: f64,
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> CellIndex {
     let mean_lat_lng = LatLng::from(mean);
 
     let mean_ecef: ECEF<f64> =
         WGS84::from_radians_and_meters(mean_lat_lng.lat_radians(), mean_lat_lng.lng_radians(), 0.0)
             .into();
 
-    let diff_enu = en_gaussian_sample(ENU::new(0.0, 0.0, 0.0), variance.sqrt());
+    let diff_enu = en_gaussian_sample(ENU::new(0.0, 0.0, 0.0), variance.sqrt(), rng);
 
     let neighbor_ecef = mean_ecef + diff_enu;
 
-    ecef_to_h3(neighbor_ecef, resolution)
+    ecef_to_h3(neighbor_ecef, resolution).expect("invalid neighbor position")
+}
+
+// Rotation taking ECEF vectors to East-North-Up coordinates at a given location
+pub fn ecef_to_enu_rotation(location: WGS84<f64>) -> Matrix3<f64> {
+    let lat = location.latitude_radians();
+    let lon = location.longitude_radians();
+
+    let sin_lat = lat.sin();
+    let cos_lat = lat.cos();
+    let sin_lon = lon.sin();
+    let cos_lon = lon.cos();
+
+    Matrix3::new(
+        -sin_lon,
+        cos_lon,
+        0.0,
+        -sin_lat * cos_lon,
+        -sin_lat * sin_lon,
+        cos_lat,
+        cos_lat * cos_lon,
+        cos_lat * sin_lon,
+        sin_lat,
+    )
 }
 
 // draw a point from a 2D Gaussian distribution
-fn en_gaussian_sample(mean: ENU<f64>, sigma: f64) -> ENU<f64> {
-    let mut rng = thread_rng();
+pub fn en_gaussian_sample(mean: ENU<f64>, sigma: f64, rng: &mut impl Rng) -> ENU<f64> {
     let normal_dist = Normal::new(0.0, sigma).expect("could not create normal distribution");
 
-    let x = normal_dist.sample(&mut rng);
-    let y = normal_dist.sample(&mut rng);
+    let x = normal_dist.sample(rng);
+    let y = normal_dist.sample(rng);
 
     ENU::new(mean.east() + x, mean.north() + y, 0.0)
 }
+
+// Choose the asserted position of a dishonest node. Random lies stay inside the simulated region, if there is one.
+pub fn lie_index(
+    true_index: CellIndex,
+    strategy: LieStrategy,
+    region: Option<&Region>,
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> Result<CellIndex, Box<dyn Error>> {
+    match strategy {
+        LieStrategy::Random => match region {
+            Some(region) => region.sample(resolution, rng),
+            None => Ok(uniform_h3_index(resolution, rng)),
+        },
+        LieStrategy::TargetCity {
+            latitude,
+            longitude,
+        } => Ok(LatLng::new(latitude, longitude)?.to_cell(resolution)),
+        LieStrategy::Offset { distance, bearing } => {
+            Ok(destination(LatLng::from(true_index), distance, bearing)?.to_cell(resolution))
+        }
+    }
+}
+
+// Draw an asserted position for a Sybil node: uniform over the area of the attack's disk
+pub fn sybil_index(
+    sybil: &SybilAttack,
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> Result<CellIndex, Box<dyn Error>> {
+    let center = LatLng::new(sybil.latitude, sybil.longitude)?;
+    let distance = sybil.radius * rng.gen::<f64>().sqrt();
+    let bearing = rng.gen_range(0.0..360.0);
+    Ok(destination(center, distance, bearing)?.to_cell(resolution))
+}
+
+// Point reached by travelling `distance` (m) along the great circle with initial `bearing` (degrees) on a spherical
+// earth
+fn destination(start: LatLng, distance: f64, bearing: f64) -> Result<LatLng, Box<dyn Error>> {
+    let angular_distance = distance / EARTH_RADIUS;
+    let bearing = bearing.to_radians();
+    let (lat, lng) = (start.lat_radians(), start.lng_radians());
+
+    let destination_lat = (lat.sin() * angular_distance.cos()
+        + lat.cos() * angular_distance.sin() * bearing.cos())
+    .asin();
+    let destination_lng = lng
+        + (bearing.sin() * angular_distance.sin() * lat.cos())
+            .atan2(angular_distance.cos() - lat.sin() * destination_lat.sin());
+
+    // wrap the longitude to [-π, π]
+    let destination_lng = (destination_lng + 3.0 * PI).rem_euclid(2.0 * PI) - PI;
+    Ok(LatLng::from_radians(destination_lat, destination_lng)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn destination_travels_the_distance_along_the_bearing() {
+        let paris = LatLng::new(48.86, 2.35).unwrap();
+        for bearing in [0.0, 90.0, 200.0] {
+            let end = destination(paris, 500_000.0, bearing).unwrap();
+            assert!((paris.distance_m(end) - 500_000.0).abs() < 1.0);
+        }
+        let north = destination(paris, 100_000.0, 0.0).unwrap();
+        assert!((north.lng() - paris.lng()).abs() < 1e-9 && north.lat() > paris.lat());
+        // two degrees east across the antimeridian
+        let east = destination(LatLng::new(0.0, 179.0).unwrap(), 222_390.0, 90.0).unwrap();
+        assert!((east.lng() + 179.0).abs() < 1e-3, "{}", east.lng());
+    }
+
+    #[test]
+    fn lies_follow_the_strategy() {
+        let resolution = Resolution::Seven;
+        let true_index = LatLng::new(48.86, 2.35).unwrap().to_cell(resolution);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let target = LieStrategy::TargetCity {
+            latitude: 35.68,
+            longitude: 139.69,
+        };
+        assert_eq!(
+            lie_index(true_index, target, None, resolution, &mut rng).unwrap(),
+            LatLng::new(35.68, 139.69).unwrap().to_cell(resolution)
+        );
+
+        let offset = LieStrategy::Offset {
+            distance: 1e6,
+            bearing: 45.0,
+        };
+        let lie = lie_index(true_index, offset, None, resolution, &mut rng).unwrap();
+        // within a few cells (about 1.4 km across at resolution 7) of the offset
+        let distance = LatLng::from(true_index).distance_m(LatLng::from(lie));
+        assert!((distance - 1e6).abs() < 3e3, "{}", distance);
+
+        let lie = lie_index(true_index, LieStrategy::Random, None, resolution, &mut rng).unwrap();
+        assert_eq!(lie.resolution(), resolution);
+    }
+
+    #[test]
+    fn sybils_are_asserted_within_the_disk() {
+        let sybil = SybilAttack {
+            latitude: 48.86,
+            longitude: 2.35,
+            radius: 1e5,
+        };
+        let center = LatLng::new(sybil.latitude, sybil.longitude).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let distances: Vec<f64> = (0..200)
+            .map(|_| {
+                let index = sybil_index(&sybil, Resolution::Seven, &mut rng).unwrap();
+                center.distance_m(LatLng::from(index))
+            })
+            .collect();
+        // up to a cell's size beyond the radius, and spread over the disk's area rather than bunched at its center
+        assert!(distances.iter().all(|&distance| distance < 1.02e5));
+        let inner = distances
+            .iter()
+            .filter(|&&distance| distance < 0.5e5)
+            .count();
+        assert!(
+            (30..=70).contains(&inner),
+            "{} of 200 in the inner quarter",
+            inner
+        );
+    }
+}
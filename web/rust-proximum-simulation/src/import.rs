@@ -0,0 +1,447 @@
+use crate::types::{NodeLocation, RttSample};
+use log::{info, warn};
+use nav_types::{ECEF, WGS84};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+
+impl NodeLocation {
+    pub fn true_position(&self) -> ECEF<f64> {
+        WGS84::from_degrees_and_meters(self.latitude, self.longitude, self.altitude).into()
+    }
+
+    // None unless both asserted coordinates are given
+    pub fn asserted_position(&self) -> Option<ECEF<f64>> {
+        let latitude = self.asserted_latitude?;
+        let longitude = self.asserted_longitude?;
+        let altitude = self.asserted_altitude.unwrap_or_default();
+        Some(WGS84::from_degrees_and_meters(latitude, longitude, altitude).into())
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let valid = |latitude: f64, longitude: f64| {
+            (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+        };
+        if !valid(self.latitude, self.longitude) {
+            return Err(format!("invalid location ({}, {})", self.latitude, self.longitude).into());
+        }
+        match (self.asserted_latitude, self.asserted_longitude) {
+            (Some(latitude), Some(longitude)) if !valid(latitude, longitude) => {
+                Err(format!("invalid asserted location ({}, {})", latitude, longitude).into())
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                Err("asserted locations need both a latitude and a longitude".into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Parse node locations from the contents of a GeoJSON FeatureCollection of points (anything starting with '{') or a
+// CSV file
+pub fn parse_node_locations(contents: &str) -> Result<Vec<NodeLocation>, Box<dyn Error>> {
+    let locations = if contents.trim_start().starts_with('{') {
+        parse_geojson(contents)?
+    } else {
+        parse_csv(contents)?
+    };
+    if locations.is_empty() {
+        return Err("no node locations found".into());
+    }
+    for (i, location) in locations.iter().enumerate() {
+        location
+            .validate()
+            .map_err(|e| format!("node {}: {}", i, e))?;
+    }
+    Ok(locations)
+}
+
+// CSV with a header row naming the columns: `latitude` (or `lat`), `longitude` (or `lon`, `lng`) and optionally `id`,
+// `altitude` (or `alt`), `asserted_latitude`, `asserted_longitude`, `asserted_altitude`, `beta` and `tau`. Empty
+// values are missing; blank lines and lines starting with '#' are skipped. Quoted fields are not supported.
+fn parse_csv(contents: &str) -> Result<Vec<NodeLocation>, Box<dyn Error>> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let (_, header) = lines.next().ok_or("empty CSV")?;
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+
+    let latitude = column(&["latitude", "lat"]).ok_or("CSV has no latitude column")?;
+    let longitude = column(&["longitude", "lon", "lng"]).ok_or("CSV has no longitude column")?;
+    let id = column(&["id"]);
+    let altitude = column(&["altitude", "alt"]);
+    let asserted_latitude = column(&["asserted_latitude"]);
+    let asserted_longitude = column(&["asserted_longitude"]);
+    let asserted_altitude = column(&["asserted_altitude"]);
+    let beta = column(&["beta"]);
+    let tau = column(&["tau"]);
+
+    lines
+        .map(|(line_number, line)| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let optional = |index: Option<usize>| -> Result<Option<f64>, Box<dyn Error>> {
+                match index.and_then(|i| fields.get(i)) {
+                    None | Some(&"") => Ok(None),
+                    Some(field) => Ok(Some(field.parse().map_err(|e| {
+                        format!("line {}: invalid value {:?}: {}", line_number, field, e)
+                    })?)),
+                }
+            };
+            let required = |index: usize| -> Result<f64, Box<dyn Error>> {
+                optional(Some(index))?.ok_or_else(|| {
+                    format!("line {}: missing {}", line_number, columns[index]).into()
+                })
+            };
+
+            Ok(NodeLocation {
+                id: id
+                    .and_then(|i| fields.get(i))
+                    .filter(|field| !field.is_empty())
+                    .map(|field| field.to_string()),
+                latitude: required(latitude)?,
+                longitude: required(longitude)?,
+                altitude: optional(altitude)?.unwrap_or_default(),
+                asserted_latitude: optional(asserted_latitude)?,
+                asserted_longitude: optional(asserted_longitude)?,
+                asserted_altitude: optional(asserted_altitude)?,
+                beta: optional(beta)?,
+                tau: optional(tau)?,
+            })
+        })
+        .collect()
+}
+
+// GeoJSON FeatureCollection of Point features with [longitude, latitude(, altitude)] coordinates. The optional values
+// are read from the feature properties with the same names as the CSV columns; the id may also be the feature's id.
+fn parse_geojson(contents: &str) -> Result<Vec<NodeLocation>, Box<dyn Error>> {
+    let collection: Value = serde_json::from_str(contents)?;
+    if collection["type"] != "FeatureCollection" {
+        return Err("GeoJSON must be a FeatureCollection".into());
+    }
+    let features = collection["features"]
+        .as_array()
+        .ok_or("GeoJSON FeatureCollection has no features")?;
+
+    features
+        .iter()
+        .enumerate()
+        .map(|(i, feature)| {
+            let geometry = &feature["geometry"];
+            if geometry["type"] != "Point" {
+                return Err(format!("feature {} is not a Point", i).into());
+            }
+            let coordinates: Vec<f64> = geometry["coordinates"]
+                .as_array()
+                .map(|values| values.iter().filter_map(Value::as_f64).collect())
+                .unwrap_or_default();
+            if !(2..=3).contains(&coordinates.len()) {
+                return Err(format!("feature {} has invalid coordinates", i).into());
+            }
+
+            let property = |name: &str| feature["properties"][name].as_f64();
+            Ok(NodeLocation {
+                id: json_key(&feature["id"]).or_else(|| json_key(&feature["properties"]["id"])),
+                latitude: coordinates[1],
+                longitude: coordinates[0],
+                altitude: coordinates.get(2).copied().unwrap_or_default(),
+                asserted_latitude: property("asserted_latitude"),
+                asserted_longitude: property("asserted_longitude"),
+                asserted_altitude: property("asserted_altitude"),
+                beta: property("beta"),
+                tau: property("tau"),
+            })
+        })
+        .collect()
+}
+
+// a JSON string or number as a key
+fn json_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(key) => Some(key.clone()),
+        Value::Number(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+// Parse a dataset of measured round trip times between the imported nodes: a JSON array of objects (or an object
+// with an `rtts` array), or a CSV file with a header row. Each record names its two ends with `source` (or `src`,
+// `from`, `prb_id`) and `target` (or `dst`, `to`, `dst_name`, `dst_addr`), referring to node ids when the locations
+// have them and to node indices otherwise, and gives the round trip time as `rtt` (s) or `rtt_ms`, `min` or `avg`
+// (ms, as in RIPE Atlas ping results), with an optional `epoch`. Records of unknown nodes, of a node to itself or
+// without a positive time are skipped.
+pub fn parse_rtt_dataset(
+    contents: &str,
+    locations: &[NodeLocation],
+) -> Result<Vec<RttSample>, Box<dyn Error>> {
+    let records = match contents.trim_start().chars().next() {
+        Some('[') | Some('{') => json_records(contents)?,
+        _ => csv_records(contents)?,
+    };
+
+    // node references are ids if the locations have them
+    let ids: HashMap<&str, usize> = locations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, location)| Some((location.id.as_deref()?, i)))
+        .collect();
+    let node = |reference: &str| -> Option<usize> {
+        if ids.is_empty() {
+            reference.parse().ok().filter(|&i| i < locations.len())
+        } else {
+            ids.get(reference).copied()
+        }
+    };
+
+    let mut samples = Vec::new();
+    let mut n_skipped = 0;
+    for (i, record) in records.iter().enumerate() {
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| record.get(*name).filter(|value| !value.is_empty()))
+        };
+        let number = |names: &[&str]| -> Result<Option<f64>, Box<dyn Error>> {
+            field(names)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|e| format!("record {}: invalid value {:?}: {}", i + 1, value, e))
+                })
+                .transpose()
+                .map_err(Into::into)
+        };
+
+        let source = field(&["source", "src", "from", "prb_id"])
+            .ok_or_else(|| format!("record {}: missing source", i + 1))?;
+        let target = field(&["target", "dst", "to", "dst_name", "dst_addr"])
+            .ok_or_else(|| format!("record {}: missing target", i + 1))?;
+        let rtt = match number(&["rtt"])? {
+            Some(rtt) => Some(rtt),
+            None => number(&["rtt_ms", "min", "avg"])?.map(|rtt| rtt / 1000.0),
+        };
+        let epoch = number(&["epoch"])?.map(|epoch| epoch as usize);
+
+        match (node(source), node(target), rtt) {
+            (Some(source), Some(target), Some(rtt)) if source != target && rtt > 0.0 => samples
+                .push(RttSample {
+                    source,
+                    target,
+                    rtt,
+                    epoch,
+                }),
+            _ => n_skipped += 1,
+        }
+    }
+
+    if n_skipped > 0 {
+        warn!(
+            "skipped {} RTT records of unknown nodes, self-measurements or without a positive time",
+            n_skipped
+        );
+    }
+    if samples.is_empty() {
+        return Err("no RTT samples between the imported nodes found".into());
+    }
+    info!("imported {} RTT samples", samples.len());
+    Ok(samples)
+}
+
+// the fields of each JSON record, with strings and numbers as text
+fn json_records(contents: &str) -> Result<Vec<HashMap<String, String>>, Box<dyn Error>> {
+    let dataset: Value = serde_json::from_str(contents)?;
+    let records = match &dataset {
+        Value::Array(records) => records,
+        _ => dataset["rtts"]
+            .as_array()
+            .ok_or("RTT dataset must be an array or have an `rtts` array")?,
+    };
+    Ok(records
+        .iter()
+        .map(|record| {
+            record
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, value)| Some((name.to_lowercase(), json_key(value)?)))
+                .collect()
+        })
+        .collect())
+}
+
+// the fields of each CSV row by column name; blank lines and lines starting with '#' are skipped
+fn csv_records(contents: &str) -> Result<Vec<HashMap<String, String>>, Box<dyn Error>> {
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let header = lines.next().ok_or("empty CSV")?;
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    Ok(lines
+        .map(|line| {
+            columns
+                .iter()
+                .cloned()
+                .zip(line.split(',').map(|field| field.trim().to_string()))
+                .collect()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_accepts_column_aliases_and_optional_values() {
+        let contents = "\
+# exported nodes
+id, lat, lng, alt, asserted_latitude, asserted_longitude, beta, tau
+
+a, 48.86, 2.35, 35, 48.0, 2.0, 0.7,
+b, -33.87, 151.21, , , , , 0.001
+";
+        let locations = parse_node_locations(contents).unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].id.as_deref(), Some("a"));
+        assert_eq!(
+            (locations[0].latitude, locations[0].longitude),
+            (48.86, 2.35)
+        );
+        assert_eq!(locations[0].altitude, 35.0);
+        assert_eq!(locations[0].asserted_latitude, Some(48.0));
+        assert_eq!(locations[0].asserted_altitude, None);
+        assert_eq!((locations[0].beta, locations[0].tau), (Some(0.7), None));
+        assert_eq!(locations[1].altitude, 0.0);
+        assert!(locations[1].asserted_position().is_none());
+        assert_eq!((locations[1].beta, locations[1].tau), (None, Some(0.001)));
+    }
+
+    #[test]
+    fn csv_rejects_missing_and_invalid_values() {
+        assert!(parse_node_locations("").is_err());
+        assert!(parse_node_locations("latitude,longitude\n").is_err());
+        assert!(parse_node_locations("id,latitude\na,1.0\n").is_err());
+        assert!(parse_node_locations("latitude,longitude\n1.0,\n").is_err());
+        assert!(parse_node_locations("latitude,longitude\n1.0,east\n").is_err());
+        assert!(parse_node_locations("latitude,longitude\n91.0,0.0\n").is_err());
+        // an asserted location needs both coordinates
+        assert!(
+            parse_node_locations("latitude,longitude,asserted_latitude\n1.0,2.0,3.0\n").is_err()
+        );
+    }
+
+    #[test]
+    fn geojson_reads_points_and_properties() {
+        let contents = r#"{
+            "type": "FeatureCollection",
+            "features": [
+                {"type": "Feature", "id": 7, "geometry": {"type": "Point", "coordinates": [2.35, 48.86, 35.0]},
+                 "properties": {"asserted_latitude": 48.0, "asserted_longitude": 2.0, "tau": 0.001}},
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [151.21, -33.87]},
+                 "properties": {"id": "sydney"}}
+            ]
+        }"#;
+        let locations = parse_node_locations(contents).unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].id.as_deref(), Some("7"));
+        assert_eq!(
+            (locations[0].latitude, locations[0].longitude),
+            (48.86, 2.35)
+        );
+        assert_eq!(locations[0].altitude, 35.0);
+        assert!(locations[0].asserted_position().is_some());
+        assert_eq!((locations[0].beta, locations[0].tau), (None, Some(0.001)));
+        assert_eq!(locations[1].id.as_deref(), Some("sydney"));
+        assert_eq!(locations[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn geojson_rejects_other_geometries() {
+        let point = r#"{"type": "Feature", "geometry": {"type": "Point", "coordinates": [0, 0]}}"#;
+        assert!(parse_node_locations(point).is_err());
+        let line = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[0, 0], [1, 1]]}}]}"#;
+        assert!(parse_node_locations(line).is_err());
+        let short = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [0]}}]}"#;
+        assert!(parse_node_locations(short).is_err());
+        let empty = r#"{"type": "FeatureCollection", "features": []}"#;
+        assert!(parse_node_locations(empty).is_err());
+    }
+
+    fn locations(ids: &[&str]) -> Vec<NodeLocation> {
+        ids.iter()
+            .enumerate()
+            .map(|(i, id)| NodeLocation {
+                id: (!id.is_empty()).then(|| id.to_string()),
+                latitude: i as f64,
+                longitude: i as f64,
+                altitude: 0.0,
+                asserted_latitude: None,
+                asserted_longitude: None,
+                asserted_altitude: None,
+                beta: None,
+                tau: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rtt_csv_refers_to_node_ids() {
+        let contents = "\
+# RIPE Atlas export
+prb_id, dst_name, min, epoch
+a, b, 20.0, 3
+b, c, , 
+c, a, 0.0,
+a, a, 5.0,
+x, b, 5.0,
+c, b, 12.5,
+";
+        let samples = parse_rtt_dataset(contents, &locations(&["a", "b", "c"])).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!((samples[0].source, samples[0].target), (0, 1));
+        assert!((samples[0].rtt - 0.02).abs() < 1e-12);
+        assert_eq!(samples[0].epoch, Some(3));
+        assert_eq!((samples[1].source, samples[1].target), (2, 1));
+        assert_eq!(samples[1].epoch, None);
+    }
+
+    #[test]
+    fn rtt_json_refers_to_node_indices() {
+        let contents = r#"{"rtts": [
+            {"src": 0, "dst": 2, "rtt": 0.05},
+            {"source": "1", "target": "0", "rtt_ms": 30, "epoch": 1},
+            {"from": 3, "to": 0, "rtt": 0.01}
+        ]}"#;
+        let samples = parse_rtt_dataset(contents, &locations(&["", "", ""])).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(
+            (samples[0].source, samples[0].target, samples[0].rtt),
+            (0, 2, 0.05)
+        );
+        assert_eq!((samples[1].source, samples[1].target), (1, 0));
+        assert!((samples[1].rtt - 0.03).abs() < 1e-12);
+        assert_eq!(samples[1].epoch, Some(1));
+    }
+
+    #[test]
+    fn rtt_dataset_rejects_malformed_records() {
+        let nodes = locations(&["", ""]);
+        assert!(parse_rtt_dataset("source,target,rtt\n0,1,fast\n", &nodes).is_err());
+        assert!(parse_rtt_dataset("source,rtt\n0,0.1\n", &nodes).is_err());
+        assert!(parse_rtt_dataset(r#"{"samples": []}"#, &nodes).is_err());
+        // nothing between known nodes
+        assert!(parse_rtt_dataset("source,target,rtt\n0,5,0.1\n", &nodes).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use crate::estimator::PositionEstimator;
+use crate::physics::{Measurements, C};
+use crate::types::{Node, SimulationConfig};
+use log::{info, trace, warn};
+use nalgebra::{Cholesky, DMatrix, DVector, Matrix3, SMatrix, SVector, Vector3};
+use nav_types::{ECEF, WGS84};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+
+// unknowns per node: [x_ECEF; y_ECEF; z_ECEF; β_c; τ]
+const N_PARAMETERS: usize = 5;
+
+type Block = SMatrix<f64, N_PARAMETERS, N_PARAMETERS>;
+type NodeGradient = SVector<f64, N_PARAMETERS>;
+
+// weak priors that keep the normal equations well conditioned for nodes with few measurements
+const BETA_PRIOR_VARIANCE: f64 = 0.25;
+const TAU_PRIOR_VARIANCE: f64 = 0.01;
+// stop iterating once a step improves the cost by less than this fraction
+const COST_TOLERANCE: f64 = 1e-3;
+// refit at most this many times while the residuals show the observation variance was off by more than this factor
+const MAX_REFITS: usize = 3;
+const REFIT_VARIANCE_RATIO: f64 = 2.0;
+// keep the message speed physical while iterating
+const BETA_BOUNDS: (f64, f64) = (0.01, 1.0);
+// conjugate gradient solves stop once the residual norm drops below this fraction of the right-hand side's
+const SOLVE_TOLERANCE: f64 = 1e-10;
+// Up to this many nodes the covariance is read off a dense inverse of the normal equations (a (5 n)^2 matrix, 50 MB at
+// the limit), which is much faster than solving for each node's columns; larger networks solve per node to keep memory
+// proportional to the number of measured pairs.
+const MAX_DENSE_COVARIANCE_NODES: usize = 500;
+
+// one node's round trip time measurements to its peers
+struct Observation {
+    index: usize,
+    their_indices: Vec<usize>,
+    times: Vec<f64>,
+}
+
+// Accumulates the Gauss-Newton normal equations J^T J δ = -J^T r one residual at a time. Every residual only depends
+// on the parameters of one or two nodes, so J^T J is kept as one block per node and one per pair of nodes with
+// measurements between them: its size grows with the number of measured pairs, not with the square of the network's.
+struct NormalEquations {
+    diagonal: Vec<Block>,
+    // block (i, j) for i < j, block (j, i) being its transpose; ordered so that sums don't depend on hashing
+    off_diagonal: BTreeMap<(usize, usize), Block>,
+    jtr: DVector<f64>,
+    cost: f64,
+    // part of the cost from the time measurements, and their number
+    measurement_cost: f64,
+    n_measurements: usize,
+}
+
+impl NormalEquations {
+    fn new(n_nodes: usize) -> Self {
+        Self {
+            diagonal: vec![Block::zeros(); n_nodes],
+            off_diagonal: BTreeMap::new(),
+            jtr: DVector::zeros(n_nodes * N_PARAMETERS),
+            cost: 0.0,
+            measurement_cost: 0.0,
+            n_measurements: 0,
+        }
+    }
+
+    // add a whitened residual with its gradient with respect to the parameters of each node it depends on
+    fn add(&mut self, residual: f64, gradients: &[(usize, NodeGradient)]) {
+        self.cost += residual * residual;
+        for (k, &(i, g_i)) in gradients.iter().enumerate() {
+            let mut jtr = self.jtr.fixed_rows_mut::<N_PARAMETERS>(i * N_PARAMETERS);
+            jtr += g_i * residual;
+            self.diagonal[i] += g_i * g_i.transpose();
+            for &(j, g_j) in &gradients[k + 1..] {
+                if i == j {
+                    self.diagonal[i] += g_i * g_j.transpose() + g_j * g_i.transpose();
+                } else {
+                    let (first, second) = if i < j { (g_i, g_j) } else { (g_j, g_i) };
+                    *self
+                        .off_diagonal
+                        .entry((i.min(j), i.max(j)))
+                        .or_insert_with(Block::zeros) += first * second.transpose();
+                }
+            }
+        }
+    }
+
+    // diagonal block with Levenberg-Marquardt damping, relative to its diagonal so that meters, c and seconds are
+    // comparable
+    fn damped_diagonal(&self, index: usize, lambda: f64) -> Block {
+        let block = &self.diagonal[index];
+        block + Block::from_diagonal(&(block.diagonal() * lambda))
+    }
+
+    // (J^T J + λ diag(J^T J)) x
+    fn multiply(&self, x: &DVector<f64>, lambda: f64) -> DVector<f64> {
+        let node = |index: usize| x.fixed_rows::<N_PARAMETERS>(index * N_PARAMETERS);
+        let mut product = DVector::zeros(x.len());
+        for (index, block) in self.diagonal.iter().enumerate() {
+            let mut rows = product.fixed_rows_mut::<N_PARAMETERS>(index * N_PARAMETERS);
+            rows += block * node(index) + block.diagonal().component_mul(&node(index)) * lambda;
+        }
+        for (&(i, j), block) in &self.off_diagonal {
+            let mut rows = product.fixed_rows_mut::<N_PARAMETERS>(i * N_PARAMETERS);
+            rows += block * node(j);
+            let mut rows = product.fixed_rows_mut::<N_PARAMETERS>(j * N_PARAMETERS);
+            rows += block.tr_mul(&node(i));
+        }
+        product
+    }
+
+    // inverses of the damped diagonal blocks, which precondition the conjugate gradient solves
+    fn preconditioner(&self, lambda: f64) -> Result<Vec<Block>, Box<dyn Error>> {
+        (0..self.diagonal.len())
+            .map(|index| {
+                Cholesky::new(self.damped_diagonal(index, lambda))
+                    .map(|cholesky| cholesky.inverse())
+                    .ok_or_else(|| "joint normal equations are not positive definite".into())
+            })
+            .collect()
+    }
+
+    // J^T J as a dense matrix
+    fn dense(&self) -> DMatrix<f64> {
+        let mut jtj = DMatrix::zeros(self.jtr.len(), self.jtr.len());
+        for (index, block) in self.diagonal.iter().enumerate() {
+            let i = index * N_PARAMETERS;
+            jtj.fixed_view_mut::<N_PARAMETERS, N_PARAMETERS>(i, i)
+                .copy_from(block);
+        }
+        for (&(i, j), block) in &self.off_diagonal {
+            let (i, j) = (i * N_PARAMETERS, j * N_PARAMETERS);
+            jtj.fixed_view_mut::<N_PARAMETERS, N_PARAMETERS>(i, j)
+                .copy_from(block);
+            jtj.fixed_view_mut::<N_PARAMETERS, N_PARAMETERS>(j, i)
+                .copy_from(&block.transpose());
+        }
+        jtj
+    }
+
+    // ECEF position covariance (m^2) of every node: the position blocks of (J^T J)^-1, which approximates the joint
+    // covariance of all parameters at the solution
+    fn position_covariances(&self) -> Result<Vec<Matrix3<f64>>, Box<dyn Error>> {
+        let n_nodes = self.diagonal.len();
+        if n_nodes <= MAX_DENSE_COVARIANCE_NODES {
+            let covariance = Cholesky::new(self.dense())
+                .ok_or("joint normal equations are not positive definite")?
+                .inverse();
+            return Ok((0..n_nodes)
+                .map(|index| {
+                    covariance
+                        .fixed_view::<3, 3>(index * N_PARAMETERS, index * N_PARAMETERS)
+                        .into()
+                })
+                .collect());
+        }
+
+        let preconditioner = self.preconditioner(0.0)?;
+        (0..n_nodes)
+            .map(|index| {
+                let mut covariance = Matrix3::zeros();
+                for axis in 0..3 {
+                    let mut column = DVector::zeros(self.jtr.len());
+                    column[index * N_PARAMETERS + axis] = 1.0;
+                    let solution = self.solve(&column, 0.0, &preconditioner)?;
+                    covariance.set_column(axis, &solution.fixed_rows::<3>(index * N_PARAMETERS));
+                }
+                Ok((covariance + covariance.transpose()) / 2.0)
+            })
+            .collect()
+    }
+
+    // Solve (J^T J + λ diag(J^T J)) x = b with block-Jacobi preconditioned conjugate gradients: each iteration costs
+    // one pass over the blocks, and the system is positive definite thanks to the priors
+    fn solve(
+        &self,
+        b: &DVector<f64>,
+        lambda: f64,
+        preconditioner: &[Block],
+    ) -> Result<DVector<f64>, Box<dyn Error>> {
+        let precondition = |residual: &DVector<f64>| {
+            let mut preconditioned = DVector::zeros(residual.len());
+            for (index, inverse) in preconditioner.iter().enumerate() {
+                let mut rows = preconditioned.fixed_rows_mut::<N_PARAMETERS>(index * N_PARAMETERS);
+                rows += inverse * residual.fixed_rows::<N_PARAMETERS>(index * N_PARAMETERS);
+            }
+            preconditioned
+        };
+
+        let mut x = DVector::zeros(b.len());
+        let tolerance = SOLVE_TOLERANCE * b.norm();
+        let mut residual = b.clone();
+        let mut preconditioned = precondition(&residual);
+        let mut direction = preconditioned.clone();
+        let mut residual_dot = residual.dot(&preconditioned);
+        // exact arithmetic converges within one iteration per parameter; round-off may take a few more
+        for _ in 0..2 * b.len() {
+            if residual.norm() <= tolerance {
+                return Ok(x);
+            }
+            let product = self.multiply(&direction, lambda);
+            let curvature = direction.dot(&product);
+            if curvature <= 0.0 || !curvature.is_finite() {
+                return Err("joint normal equations are not positive definite".into());
+            }
+            let step = residual_dot / curvature;
+            x.axpy(step, &direction, 1.0);
+            residual.axpy(-step, &product, 1.0);
+            preconditioned = precondition(&residual);
+            let next_residual_dot = residual.dot(&preconditioned);
+            direction = &preconditioned + &direction * (next_residual_dot / residual_dot);
+            residual_dot = next_residual_dot;
+        }
+        Err("joint normal equations solve did not converge".into())
+    }
+}
+
+fn position_vector(position: ECEF<f64>) -> Vector3<f64> {
+    Vector3::new(position.x(), position.y(), position.z())
+}
+
+fn project_to_surface(position: &Vector3<f64>) -> Vector3<f64> {
+    let wgs84 = WGS84::from(ECEF::new(position[0], position[1], position[2]));
+    position_vector(
+        WGS84::from_radians_and_meters(wgs84.latitude_radians(), wgs84.longitude_radians(), 0.0)
+            .into(),
+    )
+}
+
+// Centralized estimator: jointly solves for every node's position, β and τ from all measurements collected across the
+// network over a sliding window of epochs, using Levenberg-Marquardt on the sparse nonlinear least-squares problem.
+// Unlike the per-node estimators it never treats a peer's estimate as ground truth, so it serves as the best achievable
+// baseline for them. The normal equations are kept block-sparse and each step is solved iteratively, so memory and the
+// cost of an iteration grow with the number of measured pairs in the window (see `MAX_DENSE_COVARIANCE_NODES` for the
+// covariance).
+pub struct JointEstimator {
+    // all parameters, N_PARAMETERS per node
+    parameters: DVector<f64>,
+    asserted_positions: Vec<Vector3<f64>>,
+    // measurements of the current epoch and of the epochs before it (oldest first)
+    epoch_observations: Vec<Observation>,
+    window: VecDeque<Vec<Observation>>,
+    // ECEF position covariance (m^2) of each node from the last solve
+    covariances: Vec<Matrix3<f64>>,
+    // every node's trust (see `reputation`) at the last solve, scaling the weight of the measurements to it
+    trust: Vec<f64>,
+    // round trip time observation variance (s^2): the configured one, or the mean squared residual of the last solve's
+    // measurements if they scatter more
+    observation_variance: f64,
+}
+
+impl JointEstimator {
+    pub fn new(config: &SimulationConfig) -> Self {
+        Self {
+            parameters: DVector::zeros(0),
+            asserted_positions: Vec::new(),
+            epoch_observations: Vec::new(),
+            window: VecDeque::new(),
+            covariances: Vec::new(),
+            trust: Vec::new(),
+            observation_variance: config.kf_model_tof_observation_variance,
+        }
+    }
+
+    fn node_position(parameters: &DVector<f64>, index: usize) -> Vector3<f64> {
+        parameters.fixed_rows::<3>(index * N_PARAMETERS).into()
+    }
+
+    // build the normal equations of all window measurements and priors at the given parameters
+    fn linearize(&self, parameters: &DVector<f64>, config: &SimulationConfig) -> NormalEquations {
+        let mut equations = NormalEquations::new(self.asserted_positions.len());
+        let tof_sigma = self.observation_variance.sqrt();
+
+        for observation in self.window.iter().flatten() {
+            let i = observation.index * N_PARAMETERS;
+            let my_position = Self::node_position(parameters, observation.index);
+            let (my_beta, my_tau) = (parameters[i + 3], parameters[i + 4]);
+
+            for (&their_index, &time) in observation.their_indices.iter().zip(&observation.times) {
+                let j = their_index * N_PARAMETERS;
+                let tof_sigma = tof_sigma / self.trust[their_index].sqrt();
+                let their_position = Self::node_position(parameters, their_index);
+                let (their_beta, their_tau) = (parameters[j + 3], parameters[j + 4]);
+
+                let distance = config
+                    .model_propagation
+                    .path_length(&my_position, &their_position)
+                    .max(1.0);
+                let inverse_speed = 1.0 / (C * my_beta) + 1.0 / (C * their_beta);
+
+                // ping with our parameters, pong with theirs (see `physics::simulate_ping_pong_legs`)
+                let predicted_time = distance * inverse_speed + my_tau + their_tau;
+                let residual = (predicted_time - time) / tof_sigma;
+
+                let my_gradient = config
+                    .model_propagation
+                    .path_gradient(&my_position, &their_position)
+                    * inverse_speed
+                    / tof_sigma;
+                let their_gradient = config
+                    .model_propagation
+                    .path_gradient(&their_position, &my_position)
+                    * inverse_speed
+                    / tof_sigma;
+                equations.add(
+                    residual,
+                    &[
+                        (
+                            observation.index,
+                            NodeGradient::new(
+                                my_gradient[0],
+                                my_gradient[1],
+                                my_gradient[2],
+                                -distance / (C * my_beta.powi(2)) / tof_sigma,
+                                1.0 / tof_sigma,
+                            ),
+                        ),
+                        (
+                            their_index,
+                            NodeGradient::new(
+                                their_gradient[0],
+                                their_gradient[1],
+                                their_gradient[2],
+                                -distance / (C * their_beta.powi(2)) / tof_sigma,
+                                1.0 / tof_sigma,
+                            ),
+                        ),
+                    ],
+                );
+                equations.n_measurements += 1;
+            }
+        }
+        equations.measurement_cost = equations.cost;
+
+        let asserted_sigma = config.asserted_position_variance.sqrt();
+        let beta_sigma = BETA_PRIOR_VARIANCE.sqrt();
+        let tau_sigma = TAU_PRIOR_VARIANCE.sqrt();
+
+        for (index, asserted_position) in self.asserted_positions.iter().enumerate() {
+            let i = index * N_PARAMETERS;
+            let position = Self::node_position(parameters, index);
+
+            // asserted position prior
+            for axis in 0..3 {
+                equations.add(
+                    (position[axis] - asserted_position[axis]) / asserted_sigma,
+                    &[(index, NodeGradient::ith(axis, 1.0 / asserted_sigma))],
+                );
+            }
+
+            equations.add(
+                (parameters[i + 3] - config.kf_model_beta) / beta_sigma,
+                &[(index, NodeGradient::ith(3, 1.0 / beta_sigma))],
+            );
+            equations.add(
+                (parameters[i + 4] - config.kf_model_tau) / tau_sigma,
+                &[(index, NodeGradient::ith(4, 1.0 / tau_sigma))],
+            );
+        }
+
+        equations
+    }
+
+    // Levenberg-Marquardt from the current parameters, returning the solution and its normal equations
+    fn fit(
+        &self,
+        config: &SimulationConfig,
+    ) -> Result<(DVector<f64>, NormalEquations), Box<dyn Error>> {
+        let mut parameters = self.parameters.clone();
+        let mut equations = self.linearize(&parameters, config);
+        // Levenberg-Marquardt damping (see `NormalEquations::damped_diagonal`)
+        let mut lambda = 1e-3;
+
+        for iteration in 0..config.joint_iterations {
+            let preconditioner = equations.preconditioner(lambda)?;
+            let step = equations.solve(&-&equations.jtr, lambda, &preconditioner)?;
+
+            // nodes are on the earth's surface, so project every step back onto it
+            let mut candidate = &parameters + &step;
+            for index in 0..self.asserted_positions.len() {
+                let i = index * N_PARAMETERS;
+                let position = project_to_surface(&Self::node_position(&candidate, index));
+                candidate.fixed_rows_mut::<3>(i).copy_from(&position);
+                candidate[i + 3] = candidate[i + 3].clamp(BETA_BOUNDS.0, BETA_BOUNDS.1);
+            }
+
+            let candidate_equations = self.linearize(&candidate, config);
+            trace!(
+                "joint iteration {}: cost {} -> {}, lambda {}",
+                iteration,
+                equations.cost,
+                candidate_equations.cost,
+                lambda
+            );
+
+            if candidate_equations.cost.is_finite() && candidate_equations.cost < equations.cost {
+                let max_position_step = (0..self.asserted_positions.len())
+                    .map(|index| Self::node_position(&step, index).norm())
+                    .fold(0.0, f64::max);
+                let cost_reduction = 1.0 - candidate_equations.cost / equations.cost;
+                parameters = candidate;
+                equations = candidate_equations;
+                lambda /= 10.0;
+                // converged: positions barely move or the fit stopped improving
+                if max_position_step < config.joint_tolerance || cost_reduction < COST_TOLERANCE {
+                    break;
+                }
+            } else {
+                lambda *= 10.0;
+            }
+        }
+
+        Ok((parameters, equations))
+    }
+
+    fn solve(&mut self, config: &SimulationConfig) -> Result<(), Box<dyn Error>> {
+        let (mut parameters, mut equations) = self.fit(config)?;
+
+        // Weigh the measurements by how well the model fits them, so that the estimate doesn't overfit and the
+        // covariance isn't overconfident when the times are noisier than configured: the whitened residuals' mean
+        // square scales the variance they were whitened with. Refit while that changes the weighting substantially.
+        for _ in 0..MAX_REFITS {
+            if equations.n_measurements == 0 {
+                break;
+            }
+            let residual_variance = self.observation_variance * equations.measurement_cost
+                / equations.n_measurements as f64;
+            let previous_variance = self.observation_variance;
+            self.observation_variance =
+                residual_variance.max(config.kf_model_tof_observation_variance);
+            if self.observation_variance < REFIT_VARIANCE_RATIO * previous_variance {
+                equations = self.linearize(&parameters, config);
+                break;
+            }
+            (parameters, equations) = self.fit(config)?;
+        }
+
+        self.covariances = equations.position_covariances()?;
+        self.parameters = parameters;
+
+        Ok(())
+    }
+}
+
+impl PositionEstimator for JointEstimator {
+    fn name(&self) -> &'static str {
+        "joint"
+    }
+
+    fn init(&mut self, node: &Node, config: &SimulationConfig) {
+        let initial_position = position_vector(node.initial_position);
+        let initial_parameters = [
+            initial_position[0],
+            initial_position[1],
+            initial_position[2],
+            config.kf_model_beta,
+            config.kf_model_tau,
+        ];
+
+        let n_parameters = self.parameters.len();
+        self.parameters = self
+            .parameters
+            .clone()
+            .resize_vertically(n_parameters + N_PARAMETERS, 0.0);
+        self.parameters
+            .rows_mut(n_parameters, N_PARAMETERS)
+            .copy_from_slice(&initial_parameters);
+
+        self.asserted_positions
+            .push(position_vector(node.asserted_position));
+        self.covariances
+            .push(Matrix3::identity() * config.asserted_position_variance);
+        self.trust.push(node.trust);
+    }
+
+    // measurements are only collected here; the network is solved at the end of the epoch
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        _nodes: &[Node],
+        _config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let (their_indices, times) = measurements;
+        self.epoch_observations.push(Observation {
+            index,
+            their_indices: their_indices.clone(),
+            times: times.iter().cloned().collect(),
+        });
+        Ok(())
+    }
+
+    fn end_epoch(
+        &mut self,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        self.trust = nodes.iter().map(|node| node.trust).collect();
+        self.window
+            .push_back(std::mem::take(&mut self.epoch_observations));
+        while self.window.len() > config.joint_window_epochs.max(1) {
+            self.window.pop_front();
+        }
+
+        info!(
+            "solving jointly for {} nodes with {} epochs of measurements",
+            self.asserted_positions.len(),
+            self.window.len()
+        );
+        // keep the previous solution if the window can't be solved
+        if let Err(e) = self.solve(config) {
+            warn!("joint solve failed: {}", e);
+        }
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        let position = Self::node_position(&self.parameters, index);
+        ECEF::new(position[0], position[1], position[2])
+    }
+
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        self.parameters
+            .fixed_rows_mut::<3>(index * N_PARAMETERS)
+            .copy_from(&position_vector(position));
+    }
+
+    fn covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.covariances[index])
+    }
+
+    // the estimate weighs the assertion as a prior, so it shares the assertion's error in proportion to its covariance
+    fn assertion_cross_covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.covariances[index])
+    }
+
+    fn beta(&self, index: usize) -> Option<f64> {
+        Some(self.parameters[index * N_PARAMETERS + 3])
+    }
+
+    fn tau(&self, index: usize) -> Option<f64> {
+        Some(self.parameters[index * N_PARAMETERS + 4])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn sparse_solve_matches_dense_factorization() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut gradient = || NodeGradient::from_fn(|_, _| rng.gen_range(-1.0..1.0));
+        let n_nodes = 6;
+        let mut equations = NormalEquations::new(n_nodes);
+        for i in 0..n_nodes {
+            for k in 0..N_PARAMETERS {
+                equations.add(0.1, &[(i, NodeGradient::ith(k, 1.0))]);
+            }
+        }
+        for (i, j) in [(0, 1), (1, 2), (4, 2), (3, 5), (0, 5), (1, 2)] {
+            equations.add(0.5, &[(i, gradient()), (j, gradient())]);
+        }
+
+        let dense = equations.dense();
+        let b = -equations.jtr.clone();
+        for lambda in [0.0, 0.5] {
+            let mut damped = dense.clone();
+            for k in 0..damped.nrows() {
+                damped[(k, k)] *= 1.0 + lambda;
+            }
+            let expected = Cholesky::new(damped).unwrap().solve(&b);
+            let preconditioner = equations.preconditioner(lambda).unwrap();
+            let solution = equations.solve(&b, lambda, &preconditioner).unwrap();
+            assert!((solution - &expected).norm() < 1e-8 * expected.norm());
+        }
+    }
+}
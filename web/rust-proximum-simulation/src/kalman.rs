@@ -1,43 +1,41 @@
+use std::error::Error;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 
-use adskalman::{KalmanFilterNoControl, ObservationModel, StateAndCovariance, TransitionModelLinearNoControl};
-use log::trace;
-use nalgebra::DimName;
+use adskalman::{StateAndCovariance, TransitionModelLinearNoControl};
+use log::{info, trace};
 use nalgebra::{
-    allocator::Allocator, Const, DefaultAllocator, DimMin, OMatrix, OVector, RealField,
+    allocator::Allocator, Cholesky, Const, DefaultAllocator, Dyn, Matrix3, OMatrix, OVector,
+    RealField, Vector3,
 };
 use nav_types::{ECEF, WGS84};
 
-use crate::physics::C;
-use crate::types::Node;
-use log::error;
+use crate::detection::initial_assertion_cross_covariance;
+use crate::estimator::PositionEstimator;
+use crate::physics::{Measurements, C};
+use crate::types::{Node, PropagationModel, SimulationConfig};
 
 // Dimensions: ECEF coordinates +  [x_ECEF; y_ECEF; z_ECEF; β_c; τ]
 // β_c: average message propagation speed from this node to other nodes
 // τ: average latency from this node to other nodes
 pub type SS = Const<5>;
 
-// Observation size is the number of distance measurements
-#[cfg(n_measurements = "10")]
-pub type OS = Const<10>;
-#[cfg(n_measurements = "10")]
-pub const N_MEASUREMENTS: usize = 10;
+// Observation size is the number of distance measurements (`SimulationConfig::n_measurements`), known only at runtime
+pub type OS = Dyn;
 
 pub const MINIMUM_DISTANCE: f64 = 1.0;
 
 // We use the same precision for all numbers
 type Precision = f64;
 
-pub fn normalize_state (state: &OVector<f64, SS>) -> OVector<f64, SS> {
-  OVector::<f64, SS>::new(
-    state[0] * 6_371_000.0,
-    state[1] * 6_371_000.0,
-    state[2] * 6_371_000.0,
-    // bound the real message speed to (0, 1)
-    state[3].tanh() / 2.0 + 0.5,
-    state[4] * 0.1,
-  )
-
+pub fn normalize_state(state: &OVector<f64, SS>) -> OVector<f64, SS> {
+    OVector::<f64, SS>::new(
+        state[0] * 6_371_000.0,
+        state[1] * 6_371_000.0,
+        state[2] * 6_371_000.0,
+        // bound the real message speed to (0, 1)
+        state[3].tanh() / 2.0 + 0.5,
+        state[4] * 0.1,
+    )
 }
 
 // all Kalman values are internally normalized by these factors to reduce numerical instability. Multiply the state by this factor to get meaningful values (meters, seconds).
@@ -117,6 +115,7 @@ where
     }
 }
 
+#[derive(Default)]
 pub struct NonlinearObservationModel {}
 
 impl NonlinearObservationModel {
@@ -128,65 +127,89 @@ impl NonlinearObservationModel {
     // Note: the linearization is obviously only useful for the specified node indices and positions!
     pub fn linearize_at(
         &self,
-        nodes: &Vec<Node>,
+        states: &[StateAndCovariance<f64, SS>],
         my_index: usize,
-        their_indices: &Vec<usize>,
-        _observation_noise_covariance: f64,
+        their_indices: &[usize],
+        observation_noise_covariance: f64,
+        // the peers' trust (see `reputation`) scales down the weight of their measurements
+        nodes: &[Node],
+        // path the messages are assumed to travel
+        propagation: PropagationModel,
     ) -> LinearizedObservationModel {
+        let n_measurements = their_indices.len();
+
         // Create a new matrix representing positions of the other nodes used in this observation.
-        // This is a fixed size matrix with the number of rows equal to the number of measurements
-        let mut their_normalized_states = OMatrix::<f64, SS, OS>::zeros();
+        // This matrix has one column per measurement
+        let mut their_normalized_states = OMatrix::<f64, SS, OS>::zeros(n_measurements);
 
-        for i in 0..OS::dim() {
+        for i in 0..n_measurements {
             // we do our distance calculations in real units, so normalize from internal to real units
-            let their_normalized_state = normalize_state(nodes[their_indices[i]]
-                .kf_state_and_covariance
-                .state());
+            let their_normalized_state = normalize_state(states[their_indices[i]].state());
             their_normalized_states
                 .view_mut((0, i), (5, 1))
                 .copy_from(&their_normalized_state);
         }
 
+        // the evaluation function owns its own copy of the peer states; the Jacobian below uses the original
+        let evaluation_states = their_normalized_states.clone();
         let evaluation_func = Box::new(move |state: &OVector<f64, SS>| {
-            let mut y = OVector::<f64, OS>::zeros();
+            let mut y = OVector::<f64, OS>::zeros(n_measurements);
 
             let normalized_state = normalize_state(state);
 
-            for i in 0..OS::dim() {
-                let their_normalized_state = their_normalized_states.column(i);
-                // calculate the distance between nodes (rows 0-2 are the X,Y,Z positions)
-                let distance =
-                    (their_normalized_state.rows(0, 3) - normalized_state.rows(0, 3)).norm();
+            for i in 0..n_measurements {
+                let their_normalized_state = evaluation_states.column(i);
+                // calculate the path length between nodes (rows 0-2 are the X,Y,Z positions)
+                let distance = propagation.path_length(
+                    &normalized_state.fixed_rows::<3>(0).into(),
+                    &their_normalized_state.fixed_rows::<3>(0).into(),
+                );
 
                 // calculate estimated time-of-flight between nodes: we assume a ping with our parameters and a pong with their parameters
-                y[i] = 
-                  // ping
-                  distance / (C * normalized_state[3]) + normalized_state[4]
-                  // pong
-                  + distance / (C * their_normalized_state[3]) + their_normalized_state[4];
+                let ping = distance / (C * normalized_state[3]) + normalized_state[4];
+                let pong = distance / (C * their_normalized_state[3]) + their_normalized_state[4];
+                y[i] = ping + pong;
 
                 trace!("our normalized state: {:#?}, their normalized state: {:#?}, predicted measurement: {}", normalized_state, their_normalized_state, y[i]);
             }
             y
         });
 
-        let normalized_state = normalize_state(nodes[my_index].kf_state_and_covariance.state());
-        let mut observation_matrix = OMatrix::<f64, OS, SS>::zeros();
+        let state = states[my_index].state();
+        let normalized_state = normalize_state(state);
+        let mut observation_matrix = OMatrix::<f64, OS, SS>::zeros(n_measurements);
+        let mut observation_noise_covariance =
+            OMatrix::<f64, OS, OS>::identity(n_measurements, n_measurements)
+                * observation_noise_covariance;
 
-        for i in 0..OS::dim() {
-            let delta = their_normalized_states.column(i).rows(0, 3) - normalized_state.rows(0, 3);
-            let distance = delta.norm();
+        for i in 0..n_measurements {
+            let their_state_and_covariance = &states[their_indices[i]];
+            let their_normalized_state = their_normalized_states.column(i);
+            let my_position: Vector3<f64> = normalized_state.fixed_rows::<3>(0).into();
+            let their_position: Vector3<f64> = their_normalized_state.fixed_rows::<3>(0).into();
+            let distance = propagation.path_length(&my_position, &their_position);
 
             // if the distance is below the minimum, we expect a measurement of ~zero
             if distance > MINIMUM_DISTANCE {
-                // Partial derivatives with respect to x, y, z
-                let jacobian_position = -delta.transpose() / distance;
+                // the Jacobian is taken with respect to the internal (normalized) state, so each partial derivative
+                // includes the derivative of `normalize_state`
+
+                // Partial derivatives with respect to x, y, z: both the ping and the pong cover the distance
+                let inverse_speed =
+                    1.0 / (C * normalized_state[3]) + 1.0 / (C * their_normalized_state[3]);
+                let jacobian_position = propagation
+                    .path_gradient(&my_position, &their_position)
+                    .transpose()
+                    * inverse_speed
+                    * STATE_FACTOR[0];
 
                 // Partial derivative with respect to β_c (average message speed fraction of c)
-                let jacobian_beta = distance / (C * normalized_state[3] * normalized_state[3]);
+                let jacobian_beta = -distance / (C * normalized_state[3].powi(2))
+                    * (1.0 - state[3].tanh().powi(2))
+                    / 2.0;
 
                 // Partial derivative with respect to τ (average latency)
-                let jacobian_tau = 1.0;
+                let jacobian_tau = STATE_FACTOR[4];
 
                 // Fill in the Jacobian matrix
                 observation_matrix
@@ -194,12 +217,30 @@ impl NonlinearObservationModel {
                     .copy_from(&jacobian_position);
                 observation_matrix[(i, 3)] = jacobian_beta;
                 observation_matrix[(i, 4)] = jacobian_tau;
+
+                // the peer's state is only an estimate: add its uncertainty, propagated through the same model,
+                // to the measurement noise so that errors in peer estimates don't pull this node off course
+                let mut their_jacobian = OMatrix::<f64, Const<1>, SS>::zeros();
+                their_jacobian.view_mut((0, 0), (1, 3)).copy_from(
+                    &(propagation
+                        .path_gradient(&their_position, &my_position)
+                        .transpose()
+                        * inverse_speed
+                        * STATE_FACTOR[0]),
+                );
+                their_jacobian[3] = -distance / (C * their_normalized_state[3].powi(2))
+                    * (1.0 - their_state_and_covariance.state()[3].tanh().powi(2))
+                    / 2.0;
+                their_jacobian[4] = STATE_FACTOR[4];
+                observation_noise_covariance[(i, i)] += (their_jacobian
+                    * their_state_and_covariance.covariance()
+                    * their_jacobian.transpose())[0];
             }
+
+            observation_noise_covariance[(i, i)] /= nodes[their_indices[i]].trust;
         }
 
         let observation_matrix_transpose = observation_matrix.transpose();
-        let observation_noise_covariance =
-            OMatrix::<f64, OS, OS>::identity() * 10.0; // TODO - better values observation_noise_covariance;
 
         trace!("ob ns cov: {:#?}", observation_noise_covariance);
 
@@ -210,6 +251,125 @@ impl NonlinearObservationModel {
             observation_noise_covariance,
         }
     }
+
+    // Like `linearize_at`, for one-way times: the first half of the observation holds the pings (our parameters) and
+    // the second half the pongs (their parameters). Each leg gets half the round trip observation variance, and the
+    // clock offset between the two ends adds to one leg what it takes from the other.
+    #[allow(clippy::too_many_arguments)]
+    pub fn linearize_one_way_at(
+        &self,
+        states: &[StateAndCovariance<f64, SS>],
+        my_index: usize,
+        their_indices: &[usize],
+        observation_noise_covariance: f64,
+        clock_offset_variance: f64,
+        nodes: &[Node],
+        propagation: PropagationModel,
+    ) -> LinearizedObservationModel {
+        let n_measurements = their_indices.len();
+        let their_normalized_states: Vec<OVector<f64, SS>> = their_indices
+            .iter()
+            .map(|&i| normalize_state(states[i].state()))
+            .collect();
+
+        let evaluation_states = their_normalized_states.clone();
+        let evaluation_func = Box::new(move |state: &OVector<f64, SS>| {
+            let normalized_state = normalize_state(state);
+            let mut y = OVector::<f64, OS>::zeros(2 * n_measurements);
+            for (i, their_normalized_state) in evaluation_states.iter().enumerate() {
+                let distance = propagation.path_length(
+                    &normalized_state.fixed_rows::<3>(0).into(),
+                    &their_normalized_state.fixed_rows::<3>(0).into(),
+                );
+                y[i] = distance / (C * normalized_state[3]) + normalized_state[4];
+                y[n_measurements + i] =
+                    distance / (C * their_normalized_state[3]) + their_normalized_state[4];
+            }
+            y
+        });
+
+        let state = states[my_index].state();
+        let normalized_state = normalize_state(state);
+        let my_position: Vector3<f64> = normalized_state.fixed_rows::<3>(0).into();
+        let mut observation_matrix = OMatrix::<f64, OS, SS>::zeros(2 * n_measurements);
+        let mut observation_noise_covariance =
+            OMatrix::<f64, OS, OS>::identity(2 * n_measurements, 2 * n_measurements)
+                * (observation_noise_covariance / 2.0);
+        // variance of the difference between two clocks' offsets
+        let offset_variance = 2.0 * clock_offset_variance;
+
+        for (i, their_normalized_state) in their_normalized_states.iter().enumerate() {
+            let (ping, pong) = (i, n_measurements + i);
+            let their_state_and_covariance = &states[their_indices[i]];
+            let their_position: Vector3<f64> = their_normalized_state.fixed_rows::<3>(0).into();
+            let distance = propagation.path_length(&my_position, &their_position);
+
+            if distance > MINIMUM_DISTANCE {
+                let my_inverse_speed = 1.0 / (C * normalized_state[3]);
+                let their_inverse_speed = 1.0 / (C * their_normalized_state[3]);
+                let my_gradient = propagation
+                    .path_gradient(&my_position, &their_position)
+                    .transpose()
+                    * STATE_FACTOR[0];
+                let their_gradient = propagation
+                    .path_gradient(&their_position, &my_position)
+                    .transpose()
+                    * STATE_FACTOR[0];
+
+                // the ping depends on our position, speed and latency; the pong only on our position
+                observation_matrix
+                    .view_mut((ping, 0), (1, 3))
+                    .copy_from(&(my_gradient * my_inverse_speed));
+                observation_matrix[(ping, 3)] = -distance / (C * normalized_state[3].powi(2))
+                    * (1.0 - state[3].tanh().powi(2))
+                    / 2.0;
+                observation_matrix[(ping, 4)] = STATE_FACTOR[4];
+                observation_matrix
+                    .view_mut((pong, 0), (1, 3))
+                    .copy_from(&(my_gradient * their_inverse_speed));
+
+                // the peer's uncertainty, propagated to both legs (see `linearize_at`)
+                let mut their_jacobian = OMatrix::<f64, Const<2>, SS>::zeros();
+                their_jacobian
+                    .view_mut((0, 0), (1, 3))
+                    .copy_from(&(their_gradient * my_inverse_speed));
+                their_jacobian
+                    .view_mut((1, 0), (1, 3))
+                    .copy_from(&(their_gradient * their_inverse_speed));
+                their_jacobian[(1, 3)] = -distance / (C * their_normalized_state[3].powi(2))
+                    * (1.0 - their_state_and_covariance.state()[3].tanh().powi(2))
+                    / 2.0;
+                their_jacobian[(1, 4)] = STATE_FACTOR[4];
+                let their_covariance = their_jacobian
+                    * their_state_and_covariance.covariance()
+                    * their_jacobian.transpose();
+                for (a, row) in [(0, ping), (1, pong)] {
+                    for (b, column) in [(0, ping), (1, pong)] {
+                        observation_noise_covariance[(row, column)] += their_covariance[(a, b)];
+                    }
+                }
+            }
+
+            observation_noise_covariance[(ping, ping)] += offset_variance;
+            observation_noise_covariance[(pong, pong)] += offset_variance;
+            observation_noise_covariance[(ping, pong)] -= offset_variance;
+            observation_noise_covariance[(pong, ping)] -= offset_variance;
+            for row in [ping, pong] {
+                for column in [ping, pong] {
+                    observation_noise_covariance[(row, column)] /= nodes[their_indices[i]].trust;
+                }
+            }
+        }
+
+        let observation_matrix_transpose = observation_matrix.transpose();
+
+        LinearizedObservationModel {
+            evaluation_func,
+            observation_matrix,
+            observation_matrix_transpose,
+            observation_noise_covariance,
+        }
+    }
 }
 
 type EvaluationFn = Box<dyn Fn(&OVector<Precision, SS>) -> OVector<Precision, OS>>;
@@ -228,91 +388,284 @@ where
     observation_noise_covariance: OMatrix<Precision, OS, OS>,
 }
 
-impl ObservationModel<Precision, SS, OS> for LinearizedObservationModel
-where
-    DefaultAllocator: Allocator<Precision, SS, SS>,
-    DefaultAllocator: Allocator<Precision, OS, SS>,
-    DefaultAllocator: Allocator<Precision, SS, OS>,
-    DefaultAllocator: Allocator<Precision, OS, OS>,
-    DefaultAllocator: Allocator<Precision, SS>,
-    DefaultAllocator: Allocator<Precision, OS>,
-    DefaultAllocator: Allocator<(usize, usize), OS>,
-    OS: DimMin<OS, Output = OS>,
-{
-    fn H(&self) -> &OMatrix<Precision, OS, SS> {
-        &self.observation_matrix
-    }
-    fn HT(&self) -> &OMatrix<Precision, SS, OS> {
-        &self.observation_matrix_transpose
+// adskalman only supports statically sized observations, so the update step for our runtime-sized observation is
+// implemented here (mirroring `adskalman::ObservationModel::update` with the Joseph form covariance update).
+impl LinearizedObservationModel {
+    pub fn predict_observation(&self, state: &OVector<Precision, SS>) -> OVector<Precision, OS> {
+        (*self.evaluation_func)(state)
     }
-    fn R(&self) -> &OMatrix<Precision, OS, OS> {
+
+    pub fn observation_noise_covariance(&self) -> &OMatrix<Precision, OS, OS> {
         &self.observation_noise_covariance
     }
-    fn predict_observation(&self, state: &OVector<Precision, SS>) -> OVector<Precision, OS> {
-        (*self.evaluation_func)(state)
+
+    pub fn update(
+        &self,
+        prior: &StateAndCovariance<Precision, SS>,
+        observation: &OVector<Precision, OS>,
+    ) -> Result<(StateAndCovariance<Precision, SS>, InnovationStatistics), Box<dyn Error>> {
+        let h = &self.observation_matrix;
+        let ht = &self.observation_matrix_transpose;
+        let r = &self.observation_noise_covariance;
+        let p = prior.covariance();
+
+        // innovation covariance
+        let s = h * p * ht + r;
+        let s_inv = Cholesky::new(s.clone())
+            .ok_or("innovation covariance is not positive definite")?
+            .inverse();
+
+        let k_gain = p * ht * &s_inv;
+
+        let predicted = self.predict_observation(prior.state());
+        let innovation = observation - predicted;
+        trace!("innovation: {:#?}", innovation);
+        let statistics = InnovationStatistics::new(&innovation, &s, &s_inv);
+        let state = prior.state() + &k_gain * innovation;
+
+        let one_minus_kh = OMatrix::<Precision, SS, SS>::identity() - &k_gain * h;
+        let one_minus_kh_t = one_minus_kh.transpose();
+        let covariance = one_minus_kh * p * one_minus_kh_t + &k_gain * r * k_gain.transpose();
+
+        Ok((StateAndCovariance::new(state, covariance), statistics))
     }
 }
 
-// Update the estimated position of a specific node based on new measurements using the Kalman filter
-pub fn kf_step(
-  index: usize,
-  measurements: &(Vec<usize>, OVector<f64, OS>),
-  nodes: &Vec<Node>,
-  observation_model_generator: &NonlinearObservationModel,
-  state_model: &StationaryStateModel<f64>,
-  kf_model_tof_observation_variance: f64,
-) -> StateAndCovariance<f64, SS>{
-  let node = &nodes[index];
-  let (their_indices, times) = measurements;
-  let observation_model = observation_model_generator.linearize_at(
-      &nodes,
-     node.id,
-      their_indices,
-      kf_model_tof_observation_variance,
-  );
-
-  trace!("built observation model");
+// How well an update's innovations match the covariance the filter predicted for them
+#[derive(Debug, Clone, Copy)]
+pub struct InnovationStatistics {
+    // mean over the measurements of the squared innovation minus its predicted variance (s^2): positive when the
+    // measurements are noisier than modeled
+    pub excess_variance: f64,
+    // normalized innovation squared per measurement: ~1 for a consistent filter
+    pub normalized_innovation: f64,
+}
 
-  let kf = KalmanFilterNoControl::new(state_model, &observation_model);
+impl InnovationStatistics {
+    pub fn new(
+        innovation: &OVector<Precision, OS>,
+        innovation_covariance: &OMatrix<Precision, OS, OS>,
+        innovation_covariance_inverse: &OMatrix<Precision, OS, OS>,
+    ) -> Self {
+        let n = innovation.len().max(1) as f64;
+        Self {
+            excess_variance: innovation
+                .iter()
+                .zip(innovation_covariance.diagonal().iter())
+                .map(|(innovation, variance)| innovation.powi(2) - variance)
+                .sum::<f64>()
+                / n,
+            normalized_innovation: (innovation.transpose()
+                * innovation_covariance_inverse
+                * innovation)[0]
+                / n,
+        }
+    }
 
-  trace!("built kalman filter");
+    // Statistics of one-way innovations in round trip units: each leg carries half a round trip's variance
+    pub fn one_way(self) -> Self {
+        Self {
+            excess_variance: 2.0 * self.excess_variance,
+            ..self
+        }
+    }
+}
 
-  trace!("state before: {:#?}", node.kf_state_and_covariance);
+// Check that a filter state is still usable: a diverged filter has non-finite values, a covariance that is no longer
+// positive semi-definite on its diagonal, or a position uncertainty beyond the configured limit
+pub fn check_divergence(
+    state_and_covariance: &StateAndCovariance<f64, SS>,
+    max_position_variance: f64,
+) -> Result<(), Box<dyn Error>> {
+    let state = state_and_covariance.state();
+    let covariance = state_and_covariance.covariance();
+
+    if state
+        .iter()
+        .chain(covariance.iter())
+        .any(|value| !value.is_finite())
+    {
+        return Err("non-finite state or covariance".into());
+    }
 
-  let mut kf_state_and_covariance = kf
-      .step(&node.kf_state_and_covariance, &times)
-      .expect("bad kalman filter step");
+    if covariance.diagonal().iter().any(|&variance| variance < 0.0) {
+        return Err("negative state variance".into());
+    }
 
-  trace!("state after: {:#?}", node.kf_state_and_covariance);
+    // position variance in m^2
+    let position_variance = covariance.fixed_view::<3, 3>(0, 0).trace() * STATE_FACTOR[0].powi(2);
+    if position_variance > max_position_variance {
+        return Err(format!(
+            "position variance {:e} m^2 exceeds the limit",
+            position_variance
+        )
+        .into());
+    }
 
-  trace!("finished Kalman filter step. Now clamping state to earth's surface.");
+    // the position is projected onto the earth's surface, which is undefined at the earth's center
+    let position = normalize_state(state).rows(0, 3).norm();
+    if position < MINIMUM_DISTANCE {
+        return Err("position at the earth's center".into());
+    }
 
-  let state = kf_state_and_covariance.state_mut();
-  let normalized_state = normalize_state(state);
+    Ok(())
+}
 
-  let position = ECEF::new(
-    normalized_state[0],
-    normalized_state[1],
-    normalized_state[2],
-);
+// Update the estimated position of a specific node based on new measurements using the Kalman filter
+pub fn kf_step(
+    index: usize,
+    measurements: &Measurements,
+    filters: &KalmanFilters,
+    nodes: &[Node],
+    observation_model_generator: &NonlinearObservationModel,
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let (their_indices, times) = measurements;
+    let observation_model = observation_model_generator.linearize_at(
+        &filters.states,
+        index,
+        their_indices,
+        filters.observation_variance(config),
+        nodes,
+        config.model_propagation,
+    );
+
+    trace!("built observation model");
+
+    kf_observe(
+        index,
+        &observation_model,
+        times,
+        &filters.states,
+        nodes,
+        state_model,
+        config,
+    )
+}
 
-  let wgs84_position: WGS84<f64> = position.into();
+// Update a node's filter from one-way times: the pings and the pongs (round trip minus ping times)
+#[allow(clippy::too_many_arguments)]
+pub fn kf_timed_step(
+    index: usize,
+    measurements: &Measurements,
+    ping_times: &OVector<f64, OS>,
+    filters: &KalmanFilters,
+    nodes: &[Node],
+    observation_model_generator: &NonlinearObservationModel,
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let their_indices = &measurements.0;
+    let observation_model = observation_model_generator.linearize_one_way_at(
+        &filters.states,
+        index,
+        their_indices,
+        filters.observation_variance(config),
+        config.kf_model_clock_offset_variance,
+        nodes,
+        config.model_propagation,
+    );
+    let observation = one_way_observation(measurements, ping_times);
+
+    kf_observe(
+        index,
+        &observation_model,
+        &observation,
+        &filters.states,
+        nodes,
+        state_model,
+        config,
+    )
+    .map(KalmanStep::one_way)
+}
 
-  let clamped_ecef_position = catch_unwind(AssertUnwindSafe(|| {
-    WGS84::from_radians_and_meters(
-        wgs84_position.latitude_radians(),
-        wgs84_position.longitude_radians(),
-        0.0,
+// The one-way observation of `measurements` and its ping times: the pings followed by the pongs
+pub fn one_way_observation(
+    measurements: &Measurements,
+    ping_times: &OVector<f64, OS>,
+) -> OVector<f64, OS> {
+    let (their_indices, times) = measurements;
+    let pong_times = times - ping_times;
+    OVector::<f64, OS>::from_iterator(
+        2 * their_indices.len(),
+        ping_times.iter().chain(pong_times.iter()).copied(),
     )
-    .into()
-  }));
+}
+
+// Predict and update a node's filter with a linearized observation, then check and clamp the result
+fn kf_observe(
+    index: usize,
+    observation_model: &LinearizedObservationModel,
+    observation: &OVector<f64, OS>,
+    states: &[StateAndCovariance<f64, SS>],
+    nodes: &[Node],
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let state_and_covariance = &states[index];
+    trace!("state before: {:#?}", state_and_covariance);
+
+    let prior = state_model.predict(state_and_covariance);
+    let (mut kf_state_and_covariance, statistics) =
+        observation_model.update(&prior, observation)?;
+
+    trace!("state after: {:#?}", kf_state_and_covariance);
+
+    finish_step(&mut kf_state_and_covariance, &nodes[index], config)?;
+
+    Ok(KalmanStep {
+        state_and_covariance: kf_state_and_covariance,
+        prior_covariance: *prior.covariance(),
+        statistics,
+    })
+}
 
-  match clamped_ecef_position {
-      Ok(position) => {
-          // Conversion succeeded, use the position
-          let clamped_ecef_position: ECEF<f64> = position;
-          // ... rest of your code using clamped_ecef_position
-          let spring_displacement = (node.asserted_position - clamped_ecef_position) / 500.0;
+// Check an updated filter state for divergence and clamp it to the earth's surface, as every Kalman filter step does
+pub fn finish_step(
+    state_and_covariance: &mut StateAndCovariance<f64, SS>,
+    node: &Node,
+    config: &SimulationConfig,
+) -> Result<(), Box<dyn Error>> {
+    check_divergence(state_and_covariance, config.kf_max_position_variance)?;
+
+    trace!("finished Kalman filter step. Now clamping state to earth's surface.");
+
+    clamp_to_surface(state_and_covariance, node.asserted_position)
+}
+
+// Project a filter's position onto the earth's surface and pull it slightly toward the node's asserted position
+pub fn clamp_to_surface(
+    state_and_covariance: &mut StateAndCovariance<f64, SS>,
+    asserted_position: ECEF<f64>,
+) -> Result<(), Box<dyn Error>> {
+    let state = state_and_covariance.state_mut();
+    let normalized_state = normalize_state(state);
+
+    let position = ECEF::new(
+        normalized_state[0],
+        normalized_state[1],
+        normalized_state[2],
+    );
+
+    // nav_types panics on positions it cannot convert, so treat that as a diverged filter too
+    let clamped_ecef_position: ECEF<f64> = catch_unwind(AssertUnwindSafe(|| {
+        let wgs84_position: WGS84<f64> = position.into();
+        WGS84::from_radians_and_meters(
+            wgs84_position.latitude_radians(),
+            wgs84_position.longitude_radians(),
+            0.0,
+        )
+        .into()
+    }))
+    .map_err(|_| {
+        format!(
+            "ECEF to WGS84 conversion failed. ECEF: ({}, {}, {})",
+            normalized_state[0], normalized_state[1], normalized_state[2]
+        )
+    })?;
+
+    let spring_displacement = (asserted_position - clamped_ecef_position) / 500.0;
 
     let adjusted_ecef_position = clamped_ecef_position + spring_displacement;
 
@@ -320,30 +673,332 @@ pub fn kf_step(
     state[1] = adjusted_ecef_position.y() / STATE_FACTOR[1];
     state[2] = adjusted_ecef_position.z() / STATE_FACTOR[2];
 
-    return kf_state_and_covariance;
-      },
-      Err(e) => {
-          // Conversion failed, log the error and the input values
-          error!("ECEF to WGS84 conversion failed. ECEF: ({}, {}, {})", 
-                normalized_state[0], normalized_state[1], normalized_state[2]);
-          
-          if let Some(error_message) = e.downcast_ref::<String>() {
-              error!("Error message: {}", error_message);
-          } else {
-              error!("Unknown panic occurred during conversion");
-          }
-
-          let clamped_ecef_position: ECEF<f64> = position;
-          // ... rest of your code using clamped_ecef_position
-          let spring_displacement = (node.asserted_position - clamped_ecef_position) / 500.0;
-
-          let adjusted_ecef_position = clamped_ecef_position + spring_displacement;
-
-          state[0] = adjusted_ecef_position.x() / STATE_FACTOR[0];
-          state[1] = adjusted_ecef_position.y() / STATE_FACTOR[1];
-          state[2] = adjusted_ecef_position.z() / STATE_FACTOR[2];
-
-          return kf_state_and_covariance;
-      }
-  }
+    Ok(())
+}
+
+// process noise is configured in real units (m^2, c^2, s^2): convert to internal units
+pub fn kf_state_model(config: &SimulationConfig) -> StationaryStateModel<f64> {
+    StationaryStateModel::new(
+        config.kf_model_position_variance / STATE_FACTOR[0].powi(2),
+        config.kf_model_beta_variance / STATE_FACTOR[3].powi(2),
+        config.kf_model_tau_variance / STATE_FACTOR[4].powi(2),
+        STATE_FACTOR,
+    )
+}
+
+// start with the node's initial position and generic channel speed & latency parameters as a reasonable guess
+pub fn kf_initial_state(node: &Node, config: &SimulationConfig) -> StateAndCovariance<f64, SS> {
+    let state = OVector::<f64, SS>::new(
+        node.initial_position.x(),
+        node.initial_position.y(),
+        node.initial_position.z(),
+        // the internal message speed is the inverse of `normalize_state`
+        (2.0 * config.kf_model_beta - 1.0).atanh(),
+        config.kf_model_tau,
+    )
+    // convert normalized real units into internal units
+    .component_div(&STATE_FACTOR);
+
+    // the initial position is taken to be as uncertain as the assertion process; speed & latency keep a unit internal
+    // variance
+    let mut covariance = OMatrix::<f64, SS, SS>::identity();
+    for i in 0..3 {
+        covariance[(i, i)] = config.asserted_position_variance / STATE_FACTOR[i].powi(2);
+    }
+
+    trace!(
+        "Initial state: {:#?}, initial state covariance: {:#?}",
+        state,
+        covariance
+    );
+
+    StateAndCovariance::new(state, covariance)
+}
+
+// covariance between the initial state's error (internal units) and the node's assertion error (m)
+fn kf_initial_assertion_cross_covariance(
+    node: &Node,
+    config: &SimulationConfig,
+) -> OMatrix<f64, SS, Const<3>> {
+    let mut cross_covariance = OMatrix::<f64, SS, Const<3>>::zeros();
+    cross_covariance
+        .fixed_rows_mut::<3>(0)
+        .copy_from(&(initial_assertion_cross_covariance(node, config) / STATE_FACTOR[0]));
+    cross_covariance
+}
+
+pub fn kf_position(state_and_covariance: &StateAndCovariance<f64, SS>) -> ECEF<f64> {
+    let state = normalize_state(state_and_covariance.state());
+    ECEF::new(state[0], state[1], state[2])
+}
+
+// move the filter's position, keeping its covariance
+pub fn kf_set_position(
+    state_and_covariance: &mut StateAndCovariance<f64, SS>,
+    position: ECEF<f64>,
+) {
+    let state = state_and_covariance.state_mut();
+    state[0] = position.x() / STATE_FACTOR[0];
+    state[1] = position.y() / STATE_FACTOR[1];
+    state[2] = position.z() / STATE_FACTOR[2];
+}
+
+// convert the internal position covariance to m^2
+pub fn kf_position_covariance(state_and_covariance: &StateAndCovariance<f64, SS>) -> Matrix3<f64> {
+    let covariance: Matrix3<f64> = state_and_covariance
+        .covariance()
+        .fixed_view::<3, 3>(0, 0)
+        .into();
+    covariance * STATE_FACTOR[0].powi(2)
+}
+
+// A filter step's new state and covariance for a node, the covariance of the predicted state it updated, and the
+// statistics of its innovations
+pub struct KalmanStep {
+    pub state_and_covariance: StateAndCovariance<f64, SS>,
+    pub prior_covariance: OMatrix<f64, SS, SS>,
+    pub statistics: InnovationStatistics,
+}
+
+impl KalmanStep {
+    // a step of one-way times (see `InnovationStatistics::one_way`)
+    pub fn one_way(self) -> Self {
+        Self {
+            statistics: self.statistics.one_way(),
+            ..self
+        }
+    }
+}
+
+// rate at which the estimate of the unmodeled observation noise follows each update's excess innovation variance
+const NOISE_ADAPTATION_RATE: f64 = 0.02;
+
+// Round trip time variance (s^2) the observation model doesn't capture, estimated from the excess variance of
+// successive updates' innovations
+pub struct NoiseEstimate {
+    pub excess: f64,
+    // number of updates `excess` was estimated from
+    updates: usize,
+}
+
+impl NoiseEstimate {
+    pub fn new() -> Self {
+        Self {
+            excess: 0.0,
+            updates: 0,
+        }
+    }
+
+    // A plain mean of the first updates, so the estimators aren't overconfident while the estimate settles. An update
+    // whose prediction was very uncertain (e.g. just after a reset) says little about the measurement noise, so a
+    // single update can lower the estimate by at most the adaptation rate.
+    pub fn update(&mut self, excess_variance: f64) {
+        self.updates += 1;
+        let rate = (1.0 / self.updates as f64).max(NOISE_ADAPTATION_RATE);
+        self.excess += rate * excess_variance.max(-self.excess);
+    }
+}
+
+impl Default for NoiseEstimate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// weight of the latest update in each node's moving average of its normalized innovation squared
+const CONSISTENCY_RATE: f64 = 0.2;
+
+// The per-node states of a Kalman filter estimator and what it learns across updates: the observation noise the model
+// doesn't capture (e.g. the spread of message speeds and latencies around their per-node means), estimated from the
+// innovations of all nodes, and how consistent each node's recent innovations were with their predicted covariance
+pub struct KalmanFilters {
+    pub states: Vec<StateAndCovariance<f64, SS>>,
+    // unmodeled round trip time variance, added to `kf_model_tof_observation_variance`
+    pub excess_noise: NoiseEstimate,
+    // moving average of each node's normalized innovation squared per measurement
+    pub consistency: Vec<f64>,
+    // number of times a diverged or inconsistent filter was re-initialized from the node's initial position
+    pub resets: usize,
+    // covariance between each node's state error (internal units) and its assertion error (m), to test the assertion
+    // against the estimate (see `detection`)
+    pub assertion_cross_covariances: Vec<OMatrix<f64, SS, Const<3>>>,
+}
+
+impl KalmanFilters {
+    pub fn new() -> Self {
+        Self {
+            states: Vec::new(),
+            excess_noise: NoiseEstimate::new(),
+            consistency: Vec::new(),
+            resets: 0,
+            assertion_cross_covariances: Vec::new(),
+        }
+    }
+
+    pub fn init(&mut self, node: &Node, config: &SimulationConfig) {
+        self.states.push(kf_initial_state(node, config));
+        self.consistency.push(1.0);
+        self.assertion_cross_covariances
+            .push(kf_initial_assertion_cross_covariance(node, config));
+    }
+
+    // ECEF covariance (m^2) between a node's position error and its assertion error
+    pub fn assertion_cross_covariance(&self, index: usize) -> Matrix3<f64> {
+        self.assertion_cross_covariances[index].fixed_rows::<3>(0) * STATE_FACTOR[0]
+    }
+
+    // round trip time observation variance (s^2): the configured one plus the estimated unmodeled noise
+    pub fn observation_variance(&self, config: &SimulationConfig) -> f64 {
+        config.kf_model_tof_observation_variance + self.excess_noise.excess
+    }
+
+    // Keep a filter step's result, or re-initialize the node's filter if the step diverged or its innovations have
+    // been persistently larger than their predicted covariance (`kf_max_normalized_innovation`)
+    pub fn apply_step(
+        &mut self,
+        index: usize,
+        step: Result<KalmanStep, Box<dyn Error>>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) {
+        let error = match step {
+            Ok(KalmanStep {
+                state_and_covariance,
+                prior_covariance,
+                statistics,
+            }) => {
+                self.excess_noise.update(statistics.excess_variance);
+                self.consistency[index] = (1.0 - CONSISTENCY_RATE) * self.consistency[index]
+                    + CONSISTENCY_RATE * statistics.normalized_innovation;
+                if self.consistency[index] <= config.kf_max_normalized_innovation {
+                    // the update scales the prior's errors by (I - KH) = P+ (P-)^-1, including their correlation
+                    // with the assertion error
+                    if let Some(prior_information) = prior_covariance.try_inverse() {
+                        self.assertion_cross_covariances[index] = state_and_covariance.covariance()
+                            * prior_information
+                            * self.assertion_cross_covariances[index];
+                    }
+                    self.states[index] = state_and_covariance;
+                    return;
+                }
+                format!(
+                    "normalized innovation squared {:.1} per measurement",
+                    self.consistency[index]
+                )
+            }
+            Err(e) => e.to_string(),
+        };
+        info!(
+            "Kalman filter for node {} diverged ({}), re-initializing",
+            index, error
+        );
+        self.states[index] = kf_initial_state(&nodes[index], config);
+        self.consistency[index] = 1.0;
+        self.assertion_cross_covariances[index] =
+            kf_initial_assertion_cross_covariance(&nodes[index], config);
+        self.resets += 1;
+    }
+}
+
+impl Default for KalmanFilters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Extended Kalman filter estimator: one normalized state and covariance per node
+pub struct ExtendedKalmanFilter {
+    state_model: StationaryStateModel<f64>,
+    observation_model_generator: NonlinearObservationModel,
+    filters: KalmanFilters,
+}
+
+impl ExtendedKalmanFilter {
+    pub fn new(config: &SimulationConfig) -> Self {
+        Self {
+            state_model: kf_state_model(config),
+            observation_model_generator: NonlinearObservationModel::new(),
+            filters: KalmanFilters::new(),
+        }
+    }
+}
+
+impl PositionEstimator for ExtendedKalmanFilter {
+    fn name(&self) -> &'static str {
+        "ekf"
+    }
+
+    fn init(&mut self, node: &Node, config: &SimulationConfig) {
+        self.filters.init(node, config);
+    }
+
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let step = kf_step(
+            index,
+            measurements,
+            &self.filters,
+            nodes,
+            &self.observation_model_generator,
+            &self.state_model,
+            config,
+        );
+        self.filters.apply_step(index, step, nodes, config);
+        Ok(())
+    }
+
+    fn update_timed(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        ping_times: &OVector<f64, OS>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let step = kf_timed_step(
+            index,
+            measurements,
+            ping_times,
+            &self.filters,
+            nodes,
+            &self.observation_model_generator,
+            &self.state_model,
+            config,
+        );
+        self.filters.apply_step(index, step, nodes, config);
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        kf_position(&self.filters.states[index])
+    }
+
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        kf_set_position(&mut self.filters.states[index], position);
+    }
+
+    fn covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(kf_position_covariance(&self.filters.states[index]))
+    }
+
+    fn assertion_cross_covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.filters.assertion_cross_covariance(index))
+    }
+
+    fn beta(&self, index: usize) -> Option<f64> {
+        Some(normalize_state(self.filters.states[index].state())[3])
+    }
+
+    fn tau(&self, index: usize) -> Option<f64> {
+        Some(normalize_state(self.filters.states[index].state())[4])
+    }
+
+    fn resets(&self) -> Option<usize> {
+        Some(self.filters.resets)
+    }
 }
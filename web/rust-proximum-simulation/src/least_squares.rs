@@ -109,22 +109,26 @@ use nav_types::ECEF;
 use std::error::Error;
 
 use crate::{
-    kalman::{N_MEASUREMENTS, OS},
-    physics::C,
-    types::{Node, SimulationConfig},
+    estimator::PositionEstimator,
+    kalman::OS,
+    physics::{Measurements, C},
+    types::{MeasurementWeight, Node, RobustLoss, SimulationConfig},
 };
 
 // WGS84 Earth radius in meters
 const EARTH_RADIUS: f64 = 6_371_000.0;
 
+// Returns the estimated position and the final weight (robust weight times the peer's trust) of each measurement
 pub fn ls_estimate_position_ecef(
     initial_estimate: ECEF<f64>,
     asserted_position: ECEF<f64>,
-    _true_position: ECEF<f64>,
-    measurements: &(Vec<usize>, OVector<f64, OS>),
-    nodes: &[Node],
+    measurements: &Measurements,
+    // current least-squares estimates of every node's position
+    positions: &[ECEF<f64>],
+    // trust in every node's measurements (see `reputation`), scaling their weights
+    trust: &[f64],
     config: &SimulationConfig,
-) -> Result<ECEF<f64>, Box<dyn Error>> {
+) -> Result<(ECEF<f64>, Vec<f64>), Box<dyn Error>> {
     let (node_indices, measured_times) = measurements;
     let n = node_indices.len();
 
@@ -139,14 +143,10 @@ pub fn ls_estimate_position_ecef(
         initial_estimate.z(),
     ) / EARTH_RADIUS;
 
-    let scaled_nodes: Vec<OVector<f64, Const<3>>> = nodes
+    let scaled_nodes: Vec<OVector<f64, Const<3>>> = positions
         .iter()
-        .map(|node| {
-            Vector3::<f64>::new(
-                node.ls_estimated_position.x(),
-                node.ls_estimated_position.y(),
-                node.ls_estimated_position.z(),
-            ) / EARTH_RADIUS
+        .map(|position| {
+            Vector3::<f64>::new(position.x(), position.y(), position.z()) / EARTH_RADIUS
         })
         .collect();
 
@@ -158,17 +158,28 @@ pub fn ls_estimate_position_ecef(
 
     trace!("Initial: |x| = {}", x.norm(),);
 
+    let peer_trust = OVector::<f64, OS>::from_iterator(n, node_indices.iter().map(|&i| trust[i]));
+
     for iteration in 0..max_iterations {
-        let mut h = OMatrix::<f64, Const<N_MEASUREMENTS>, Const<3>>::zeros();
-        let mut z = OVector::<f64, OS>::zeros();
+        let mut h = OMatrix::<f64, OS, Const<3>>::zeros(n);
+        let mut z = OVector::<f64, OS>::zeros(n);
 
         for i in 0..n {
             let node_pos = &scaled_nodes[node_indices[i]];
-            let dx = &x - node_pos;
-            let r = dx.norm() * EARTH_RADIUS; // Unscale for time calculation
-
-            // Compute the Jacobian (keep it scaled)
-            h.set_row(i, &(dx.transpose() / dx.norm()));
+            // path length and its gradient in real units, which is also the gradient in scaled units. A peer at the
+            // same position (e.g. colluding nodes asserting the same location) gives no direction, so its row is left
+            // at zero.
+            let (position, node_position) = (x * EARTH_RADIUS, node_pos * EARTH_RADIUS);
+            let r = config
+                .model_propagation
+                .path_length(&position, &node_position);
+            h.set_row(
+                i,
+                &config
+                    .model_propagation
+                    .path_gradient(&position, &node_position)
+                    .transpose(),
+            );
 
             // Compute the residual
             let predicted_time = 2.0 * (r / (config.ls_model_beta * C) + config.ls_model_tau);
@@ -178,18 +189,21 @@ pub fn ls_estimate_position_ecef(
         // Scale the Jacobian to match the time units
         let scaled_h = h * (EARTH_RADIUS / (config.ls_model_beta * C));
 
-        // Solve the normal equations with Levenberg-Marquardt damping
-        let h_t = scaled_h.transpose();
-        let delta_x = (h_t * &scaled_h + lambda * nalgebra::DMatrix::identity(3, 3))
+        // Iteratively reweighted least squares: down-weight the measurements with large residuals
+        let w = robust_weights(&z, config).component_mul(&peer_trust);
+
+        // Solve the weighted normal equations with Levenberg-Marquardt damping
+        let h_t = scaled_h.transpose() * OMatrix::<f64, OS, OS>::from_diagonal(&w);
+        let delta_x = (&h_t * &scaled_h + lambda * nalgebra::DMatrix::identity(3, 3))
             .try_inverse()
             .ok_or("Matrix inversion failed")?
-            * h_t
-            * z;
+            * &h_t
+            * &z;
 
         // Constrain the update to keep the object near the Earth's surface
         let new_x = x + delta_x;
         let new_x_norm = new_x.norm();
-        let scale_factor = if new_x_norm > 1.01 || new_x_norm < 0.99 {
+        let scale_factor = if !(0.99..=1.01).contains(&new_x_norm) {
             1.0 / new_x_norm
         } else {
             1.0
@@ -233,15 +247,157 @@ pub fn ls_estimate_position_ecef(
         }
     }
 
-    // spring constant back to asserted location.
     let estimate = ECEF::new(
         x[0] * EARTH_RADIUS,
         x[1] * EARTH_RADIUS,
         x[2] * EARTH_RADIUS,
     );
 
+    let weights = robust_weights(
+        &ls_time_residuals(estimate, measurements, positions, config),
+        config,
+    )
+    .component_mul(&peer_trust)
+    .iter()
+    .cloned()
+    .collect();
+
+    // spring constant back to asserted location.
     let spring_direction = asserted_position - estimate;
     // let force = spring_direction.norm();
 
-    return Ok(estimate + spring_direction / 500.0);
+    Ok((estimate + spring_direction / 500.0, weights))
+}
+
+// Measured minus predicted round trip time (s) of every measurement from `position`, under the least squares model
+pub fn ls_time_residuals(
+    position: ECEF<f64>,
+    measurements: &Measurements,
+    positions: &[ECEF<f64>],
+    config: &SimulationConfig,
+) -> OVector<f64, OS> {
+    let (node_indices, measured_times) = measurements;
+    OVector::<f64, OS>::from_iterator(
+        node_indices.len(),
+        node_indices
+            .iter()
+            .zip(measured_times.iter())
+            .map(|(&i, time)| {
+                let r = config
+                    .model_propagation
+                    .ecef_path_length(position, positions[i]);
+                time - 2.0 * (r / (config.ls_model_beta * C) + config.ls_model_tau)
+            }),
+    )
+}
+
+// IRLS weight ψ(u)/u of every time residual u, relative to the loss scale
+pub fn robust_weights(residuals: &OVector<f64, OS>, config: &SimulationConfig) -> OVector<f64, OS> {
+    residuals.map(|residual| config.ls_loss.weight(residual / config.ls_loss_scale))
+}
+
+impl RobustLoss {
+    // IRLS weight of a residual measured in units of the loss scale
+    pub fn weight(&self, scaled_residual: f64) -> f64 {
+        let u = scaled_residual.abs();
+        match self {
+            RobustLoss::Squared => 1.0,
+            RobustLoss::Huber => {
+                if u <= 1.0 {
+                    1.0
+                } else {
+                    1.0 / u
+                }
+            }
+            RobustLoss::Cauchy => 1.0 / (1.0 + u * u),
+            RobustLoss::Tukey => {
+                if u < 1.0 {
+                    (1.0 - u * u).powi(2)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// Least-squares estimator: one position per node
+#[derive(Default)]
+pub struct LeastSquares {
+    positions: Vec<ECEF<f64>>,
+    // robust weights of each node's last measurements
+    weights: Vec<Vec<MeasurementWeight>>,
+}
+
+impl PositionEstimator for LeastSquares {
+    fn name(&self) -> &'static str {
+        "ls"
+    }
+
+    fn init(&mut self, node: &Node, _config: &SimulationConfig) {
+        self.positions.push(node.initial_position);
+        self.weights.push(Vec::new());
+    }
+
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let (position, weights) = ls_estimate_position_ecef(
+            self.positions[index],
+            nodes[index].asserted_position,
+            measurements,
+            &self.positions,
+            &nodes.iter().map(|node| node.trust).collect::<Vec<_>>(),
+            config,
+        )?;
+        self.positions[index] = position;
+        self.weights[index] = measurements
+            .0
+            .iter()
+            .zip(weights)
+            .map(|(&peer, weight)| MeasurementWeight { peer, weight })
+            .collect();
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        self.positions[index]
+    }
+
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        self.positions[index] = position;
+    }
+
+    fn measurement_weights(&self, index: usize) -> Option<Vec<MeasurementWeight>> {
+        Some(self.weights[index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn robust_weights_down_weight_large_residuals() {
+        for u in [0.0, 0.5, 3.0] {
+            assert_eq!(RobustLoss::Squared.weight(u), 1.0);
+        }
+        assert_eq!(RobustLoss::Huber.weight(0.5), 1.0);
+        assert_eq!(RobustLoss::Huber.weight(-4.0), 0.25);
+        assert_eq!(RobustLoss::Cauchy.weight(0.0), 1.0);
+        assert_eq!(RobustLoss::Cauchy.weight(-2.0), 0.2);
+        assert_eq!(RobustLoss::Tukey.weight(0.5), 0.5625);
+        assert_eq!(RobustLoss::Tukey.weight(1.0), 0.0);
+        assert_eq!(RobustLoss::Tukey.weight(-3.0), 0.0);
+        // every loss weighs a residual no more than a smaller one
+        for loss in [RobustLoss::Huber, RobustLoss::Cauchy, RobustLoss::Tukey] {
+            assert!(
+                (0..50).all(|i| loss.weight(i as f64 * 0.1) >= loss.weight((i + 1) as f64 * 0.1))
+            );
+        }
+    }
 }
@@ -1,9 +1,27 @@
-mod geometry;
-mod kalman;
-mod least_squares;
-mod node;
-mod physics;
-mod simulation;
-mod simulation_manager;
-mod stats;
-mod types;
+pub mod batch;
+pub mod dataset;
+pub mod detection;
+pub mod estimator;
+pub mod geometry;
+pub mod import;
+pub mod joint;
+pub mod kalman;
+pub mod least_squares;
+pub mod mds;
+pub mod node;
+pub mod particle_filter;
+pub mod physics;
+pub mod placement;
+pub mod propagation;
+pub mod ransac;
+pub mod region;
+pub mod reputation;
+pub mod simulation;
+pub mod simulation_manager;
+pub mod sprt;
+pub mod stats;
+pub mod sweep;
+pub mod types;
+pub mod ukf;
+#[cfg(test)]
+mod test_util;
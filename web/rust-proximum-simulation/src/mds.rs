@@ -0,0 +1,220 @@
+use crate::physics::{Measurements, C};
+use crate::types::{Node, SimulationConfig};
+use log::{info, trace};
+use nalgebra::{DMatrix, Matrix3, Vector3};
+use nav_types::{ECEF, WGS84};
+use std::error::Error;
+
+// Classical multidimensional scaling: recover every node's position from time-of-flight measurements alone.
+//
+// 1. convert round trip times to distances with the least squares model's β, τ and path model, averaging repeated
+//    measurements
+// 2. fill in pairs that were never measured with shortest path distances through measured pairs
+// 3. embed the squared distance matrix in 3D (double centering + top 3 eigenvectors)
+// 4. align the embedding to the anchor positions with a similarity transform (rotation, scale, translation) and
+//    project it onto the earth's surface
+//
+// The anchors are the asserted positions of all nodes: a few bad assertions barely move the fitted transform, so each
+// node's result depends on its measurements rather than on its own assertion.
+pub fn mds_initial_positions(
+    nodes: &[Node],
+    measurements: &[(usize, Measurements)],
+    config: &SimulationConfig,
+) -> Result<Vec<ECEF<f64>>, Box<dyn Error>> {
+    let n = nodes.len();
+    if n < 4 {
+        return Err("At least 4 nodes are required for a 3D embedding".into());
+    }
+
+    let distances = complete_distances(measured_distances(n, measurements, config))?;
+    let embedding = classical_mds(&distances)?;
+
+    let anchors: Vec<Vector3<f64>> = nodes
+        .iter()
+        .map(|node| {
+            Vector3::new(
+                node.asserted_position.x(),
+                node.asserted_position.y(),
+                node.asserted_position.z(),
+            )
+        })
+        .collect();
+    let transform = SimilarityTransform::fit(&embedding, &anchors)?;
+    info!(
+        "MDS embedding aligned to anchors with scale {}",
+        transform.scale
+    );
+
+    Ok(embedding
+        .iter()
+        .map(|point| {
+            let aligned = transform.apply(point);
+            let wgs84 = WGS84::from(ECEF::new(aligned[0], aligned[1], aligned[2]));
+            WGS84::from_radians_and_meters(wgs84.latitude_radians(), wgs84.longitude_radians(), 0.0)
+                .into()
+        })
+        .collect())
+}
+
+// average distance (m) for every measured pair, None where no measurement was made
+fn measured_distances(
+    n: usize,
+    measurements: &[(usize, Measurements)],
+    config: &SimulationConfig,
+) -> Vec<Vec<Option<f64>>> {
+    let mut sums = vec![vec![0.0; n]; n];
+    let mut counts = vec![vec![0usize; n]; n];
+
+    for (my_index, (their_indices, times)) in measurements {
+        for (&their_index, &time) in their_indices.iter().zip(times.iter()) {
+            // the same model as the least squares estimator: both legs at the model speed plus two latencies, along the
+            // modeled path whose straight-line distance is embedded
+            let path_length = C * config.ls_model_beta * (time - 2.0 * config.ls_model_tau) / 2.0;
+            let distance = config.model_propagation.chord_distance(path_length);
+            for (i, j) in [(*my_index, their_index), (their_index, *my_index)] {
+                sums[i][j] += distance;
+                counts[i][j] += 1;
+            }
+        }
+    }
+
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| match (i == j, counts[i][j]) {
+                    (true, _) => Some(0.0),
+                    (false, 0) => None,
+                    (false, count) => Some(sums[i][j] / count as f64),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Floyd-Warshall shortest paths over the measured pairs, so unmeasured pairs get an (over)estimate of their distance
+fn complete_distances(distances: Vec<Vec<Option<f64>>>) -> Result<DMatrix<f64>, Box<dyn Error>> {
+    let n = distances.len();
+    let mut completed = DMatrix::from_fn(n, n, |i, j| distances[i][j].unwrap_or(f64::INFINITY));
+
+    for k in 0..n {
+        for i in 0..n {
+            for j in 0..n {
+                let through_k = completed[(i, k)] + completed[(k, j)];
+                if through_k < completed[(i, j)] {
+                    completed[(i, j)] = through_k;
+                }
+            }
+        }
+    }
+
+    if completed.iter().any(|distance| !distance.is_finite()) {
+        return Err("the measurement graph is disconnected".into());
+    }
+    Ok(completed)
+}
+
+// embed a distance matrix in 3D from the top 3 eigenpairs of the double centered squared distances
+fn classical_mds(distances: &DMatrix<f64>) -> Result<Vec<Vector3<f64>>, Box<dyn Error>> {
+    let n = distances.nrows();
+    let squared = distances.map(|distance| distance * distance);
+    let centering =
+        DMatrix::<f64>::identity(n, n) - DMatrix::<f64>::from_element(n, n, 1.0 / n as f64);
+    let gram = -0.5 * &centering * squared * &centering;
+
+    let eigen = gram.symmetric_eigen();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| eigen.eigenvalues[b].total_cmp(&eigen.eigenvalues[a]));
+    trace!(
+        "MDS eigenvalues: {:?}",
+        order
+            .iter()
+            .take(4)
+            .map(|&i| eigen.eigenvalues[i])
+            .collect::<Vec<_>>()
+    );
+
+    let mut embedding = vec![Vector3::zeros(); n];
+    for (axis, &k) in order.iter().take(3).enumerate() {
+        let eigenvalue = eigen.eigenvalues[k];
+        if eigenvalue <= 0.0 {
+            return Err("distance matrix has fewer than 3 positive dimensions".into());
+        }
+        for (i, point) in embedding.iter_mut().enumerate() {
+            point[axis] = eigen.eigenvectors[(i, k)] * eigenvalue.sqrt();
+        }
+    }
+
+    Ok(embedding)
+}
+
+// anchor ≈ scale * rotation * point + translation
+struct SimilarityTransform {
+    rotation: Matrix3<f64>,
+    scale: f64,
+    translation: Vector3<f64>,
+}
+
+impl SimilarityTransform {
+    // least-squares fit of `points` onto `anchors` (Umeyama, without the reflection correction)
+    fn fit(points: &[Vector3<f64>], anchors: &[Vector3<f64>]) -> Result<Self, Box<dyn Error>> {
+        let n = points.len() as f64;
+        let point_mean = points.iter().sum::<Vector3<f64>>() / n;
+        let anchor_mean = anchors.iter().sum::<Vector3<f64>>() / n;
+
+        let mut cross_covariance = Matrix3::zeros();
+        let mut point_variance = 0.0;
+        for (point, anchor) in points.iter().zip(anchors) {
+            let point_deviation = point - point_mean;
+            cross_covariance += (anchor - anchor_mean) * point_deviation.transpose();
+            point_variance += point_deviation.norm_squared();
+        }
+        cross_covariance /= n;
+        point_variance /= n;
+
+        let svd = cross_covariance.svd(true, true);
+        let u = svd.u.ok_or("SVD failed")?;
+        let v_t = svd.v_t.ok_or("SVD failed")?;
+
+        // the signs of the MDS axes are arbitrary, so the embedding may be mirrored: the orthogonal part is allowed
+        // to be a reflection
+        let rotation = u * v_t;
+        let scale = svd.singular_values.sum() / point_variance;
+        let translation = anchor_mean - rotation * point_mean * scale;
+
+        Ok(Self {
+            rotation,
+            scale,
+            translation,
+        })
+    }
+
+    fn apply(&self, point: &Vector3<f64>) -> Vector3<f64> {
+        self.rotation * point * self.scale + self.translation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classical_mds_recovers_pairwise_distances() {
+        let points = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1e6, 0.0, 0.0),
+            Vector3::new(0.0, 2e6, 0.0),
+            Vector3::new(0.0, 0.0, 3e6),
+            Vector3::new(1e6, 1e6, 1e6),
+        ];
+        let distances = DMatrix::from_fn(points.len(), points.len(), |i, j| {
+            (points[i] - points[j]).norm()
+        });
+        let embedding = classical_mds(&distances).unwrap();
+        for i in 0..points.len() {
+            for j in 0..points.len() {
+                let distance = (embedding[i] - embedding[j]).norm();
+                assert!((distance - distances[(i, j)]).abs() < 1e-3, "{} {}", i, j);
+            }
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use crate::detection::initial_assertion_cross_covariance;
+use crate::estimator::PositionEstimator;
+use crate::geometry::{ecef_to_enu_rotation, en_gaussian_sample};
+use crate::kalman::{NoiseEstimate, OS};
+use crate::physics::{Measurements, C};
+use crate::types::{Node, SimulationConfig, SimulationRng};
+use log::{info, trace};
+use nalgebra::{Matrix2, Matrix3, OVector, Vector2, Vector3};
+use nav_types::{ECEF, ENU, WGS84};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::error::Error;
+
+// resample once the effective sample size drops below this fraction of the particle count
+const RESAMPLE_THRESHOLD: f64 = 0.5;
+
+// random stream used by the particle filter, so that its draws don't change the simulation's measurement stream
+const PARTICLE_FILTER_STREAM: u64 = 1;
+
+// positions closer than this (m) to the earth's center have no meaningful surface point below them
+const MINIMUM_PROJECTION_RADIUS: f64 = 1e6;
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    // ECEF coordinates (m): kept as a plain vector since nav_types' ECEF differences are rotated into ENU
+    position: Vector3<f64>,
+    beta: f64,
+    tau: f64,
+}
+
+// weighted particle set for one node along with its summary statistics
+struct ParticleBelief {
+    particles: Vec<Particle>,
+    weights: Vec<f64>,
+    mean: Particle,
+    // ECEF position covariance (m^2)
+    covariance: Matrix3<f64>,
+    // ECEF covariance (m^2) between the mean position's error and the node's assertion error
+    assertion_cross_covariance: Matrix3<f64>,
+    effective_sample_size: f64,
+}
+
+// Move a position onto the WGS84 surface (altitude 0); None for non-finite positions and ones near the earth's center,
+// which nav_types cannot convert
+fn project_to_surface(position: &Vector3<f64>) -> Option<Vector3<f64>> {
+    if !position.iter().all(|x| x.is_finite()) || position.norm() < MINIMUM_PROJECTION_RADIUS {
+        return None;
+    }
+    let wgs84 = WGS84::from(ecef_position(position));
+    let surface = position_vector(
+        WGS84::from_radians_and_meters(wgs84.latitude_radians(), wgs84.longitude_radians(), 0.0)
+            .into(),
+    );
+    surface.iter().all(|x| x.is_finite()).then_some(surface)
+}
+
+fn position_vector(position: ECEF<f64>) -> Vector3<f64> {
+    Vector3::new(position.x(), position.y(), position.z())
+}
+
+fn ecef_position(position: &Vector3<f64>) -> ECEF<f64> {
+    ECEF::new(position[0], position[1], position[2])
+}
+
+// draw a position on the earth's surface from a gaussian in the East-North plane around `mean`, None if the draw
+// cannot be projected onto the surface
+fn sample_surface_position(
+    mean: &Vector3<f64>,
+    sigma: f64,
+    rng: &mut impl Rng,
+) -> Option<Vector3<f64>> {
+    let displacement = en_gaussian_sample(ENU::new(0.0, 0.0, 0.0), sigma, rng);
+    project_to_surface(&position_vector(ecef_position(mean) + displacement))
+}
+
+// weighted mean and covariance of particle positions
+fn position_moments(particles: &[Particle], weights: &[f64]) -> (Vector3<f64>, Matrix3<f64>) {
+    let mut mean_position = Vector3::zeros();
+    for (particle, weight) in particles.iter().zip(weights) {
+        mean_position += particle.position * *weight;
+    }
+
+    let mut covariance = Matrix3::zeros();
+    for (particle, weight) in particles.iter().zip(weights) {
+        let deviation = particle.position - mean_position;
+        covariance += deviation * deviation.transpose() * *weight;
+    }
+    (mean_position, covariance)
+}
+
+impl ParticleBelief {
+    fn new(particles: Vec<Particle>) -> Self {
+        let n_particles = particles.len();
+        let mut belief = ParticleBelief {
+            particles,
+            weights: vec![1.0 / n_particles as f64; n_particles],
+            mean: Particle {
+                position: Vector3::zeros(),
+                beta: 0.0,
+                tau: 0.0,
+            },
+            covariance: Matrix3::zeros(),
+            assertion_cross_covariance: Matrix3::zeros(),
+            effective_sample_size: n_particles as f64,
+        };
+        belief.summarize();
+        belief
+    }
+
+    // weighted mean (projected onto the surface) and covariance of the particle positions. Particles spread around the
+    // globe can average out near the earth's center; the heaviest particle then stands in for the mean position.
+    fn summarize(&mut self) {
+        let mut beta = 0.0;
+        let mut tau = 0.0;
+        for (particle, weight) in self.particles.iter().zip(&self.weights) {
+            beta += particle.beta * weight;
+            tau += particle.tau * weight;
+        }
+        let (mean_position, covariance) = position_moments(&self.particles, &self.weights);
+
+        let position = project_to_surface(&mean_position).unwrap_or_else(|| {
+            let heaviest = (0..self.particles.len())
+                .max_by(|&a, &b| self.weights[a].total_cmp(&self.weights[b]))
+                .unwrap_or_default();
+            self.particles[heaviest].position
+        });
+
+        self.mean = Particle {
+            position,
+            beta,
+            tau,
+        };
+        self.covariance = covariance;
+    }
+
+    // Scale the correlation of the mean position with the assertion by the update's shrinkage of the position
+    // covariance, P+ (P-)^-1 as in a Kalman filter update. The particles lie on the earth's surface, so this is done in
+    // the East-North plane at the mean position.
+    fn update_assertion_cross_covariance(&mut self, prior_covariance: &Matrix3<f64>) {
+        let rotation = ecef_to_enu_rotation(WGS84::from(ecef_position(&self.mean.position)));
+        let east_north = |covariance: &Matrix3<f64>| -> Matrix2<f64> {
+            (rotation * covariance * rotation.transpose())
+                .fixed_view::<2, 2>(0, 0)
+                .into()
+        };
+        let Some(prior_information) = east_north(prior_covariance).try_inverse() else {
+            return;
+        };
+        let cross_covariance = east_north(&self.covariance)
+            * prior_information
+            * east_north(&self.assertion_cross_covariance);
+
+        let mut enu_cross_covariance = Matrix3::zeros();
+        enu_cross_covariance
+            .fixed_view_mut::<2, 2>(0, 0)
+            .copy_from(&cross_covariance);
+        self.assertion_cross_covariance = rotation.transpose() * enu_cross_covariance * rotation;
+    }
+
+    // systematic resampling: keeps particles in proportion to their weights with a single random draw
+    fn resample(&mut self, rng: &mut impl Rng) {
+        let n_particles = self.particles.len();
+        let step = 1.0 / n_particles as f64;
+        let mut target = rng.gen_range(0.0..step);
+        let mut cumulative_weight = self.weights[0];
+        let mut source = 0;
+
+        let mut resampled = Vec::with_capacity(n_particles);
+        for _ in 0..n_particles {
+            while target > cumulative_weight && source < n_particles - 1 {
+                source += 1;
+                cumulative_weight += self.weights[source];
+            }
+            resampled.push(self.particles[source]);
+            target += step;
+        }
+
+        self.particles = resampled;
+        self.weights = vec![step; n_particles];
+    }
+}
+
+// Particle filter estimator: a weighted set of (position, β, τ) hypotheses per node, so multimodal position beliefs
+// (e.g. early in a run with few, distant peers) are not forced into a single Gaussian
+pub struct ParticleFilter {
+    beliefs: Vec<ParticleBelief>,
+    rng: SimulationRng,
+    // number of times a collapsed or diverged particle set was re-initialized from the node's initial position
+    resets: usize,
+    // round trip time variance the likelihood doesn't model, as for the Kalman filters
+    excess_noise: NoiseEstimate,
+}
+
+impl ParticleFilter {
+    pub fn new(config: &SimulationConfig) -> Self {
+        let mut rng = SimulationRng::seed_from_u64(config.seed.unwrap_or_default());
+        rng.set_stream(PARTICLE_FILTER_STREAM);
+        Self {
+            beliefs: Vec::new(),
+            rng,
+            resets: 0,
+            excess_noise: NoiseEstimate::new(),
+        }
+    }
+
+    // sample positions around the initial position with the assertion's uncertainty, and speed & latency
+    // uniformly over their physical ranges
+    fn initial_belief(&mut self, node: &Node, config: &SimulationConfig) -> ParticleBelief {
+        let sigma = config.asserted_position_variance.sqrt();
+        let initial_position = position_vector(node.initial_position);
+        let particles = (0..config.pf_n_particles.max(1))
+            .map(|_| Particle {
+                position: sample_surface_position(&initial_position, sigma, &mut self.rng)
+                    .unwrap_or(initial_position),
+                beta: self.rng.gen_range(config.beta_min..=config.beta_max),
+                tau: self.rng.gen_range(config.tau_min..=config.tau_max),
+            })
+            .collect();
+
+        let mut belief = ParticleBelief::new(particles);
+        belief.assertion_cross_covariance = initial_assertion_cross_covariance(node, config);
+        belief
+    }
+
+    // Predicted round trip time (s) of a measurement from a particle to a peer, and its variance (s^2) under the noise
+    // model of `physics::simulate_ping_pong_legs` (noisy message speeds and latencies on both legs) plus the
+    // uncertainty of the peer's own position estimate and the estimated unmodeled noise
+    fn round_trip_model(
+        &self,
+        particle: &Particle,
+        their_index: usize,
+        config: &SimulationConfig,
+    ) -> (f64, f64) {
+        let peer = &self.beliefs[their_index];
+        let distance = config
+            .model_propagation
+            .path_length(&particle.position, &peer.mean.position);
+
+        let ping_time = distance / (C * particle.beta) + particle.tau;
+        let pong_time = distance / (C * peer.mean.beta) + peer.mean.tau;
+
+        // propagate the speed and latency noise of both legs through the time-of-flight model
+        let speed_variance = (distance / (C * particle.beta.powi(2))).powi(2)
+            * config.beta_variance
+            + (distance / (C * peer.mean.beta.powi(2))).powi(2) * config.beta_variance;
+        let latency_variance = 2.0 * config.tau_variance;
+        // average positional variance of the peer along any direction, converted to s^2
+        let peer_variance = peer.covariance.trace() / 3.0
+            * (1.0 / (C * particle.beta) + 1.0 / (C * peer.mean.beta)).powi(2);
+
+        (
+            ping_time + pong_time,
+            speed_variance
+                + latency_variance
+                + peer_variance
+                + config.kf_model_tof_observation_variance
+                + self.excess_noise.excess,
+        )
+    }
+
+    // log-likelihood of a node's time-of-flight measurements for one particle (see `round_trip_model`), counting
+    // measurements to less trusted peers (see `reputation`) for less
+    fn log_likelihood(
+        &self,
+        particle: &Particle,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> f64 {
+        let (their_indices, times) = measurements;
+        let mut log_likelihood = 0.0;
+
+        for (&their_index, &time) in their_indices.iter().zip(times.iter()) {
+            let (predicted_time, variance) = self.round_trip_model(particle, their_index, config);
+            let variance = variance / nodes[their_index].trust;
+
+            log_likelihood -= (time - predicted_time).powi(2) / (2.0 * variance)
+                + 0.5 * (2.0 * std::f64::consts::PI * variance).ln();
+        }
+
+        log_likelihood
+    }
+
+    // Mean excess of a node's squared round trip innovations over their predicted variance, at its belief before the
+    // update: the predicted variance adds the spread of the node's own position to `round_trip_model`'s
+    fn excess_variance(
+        &self,
+        index: usize,
+        measurements: &Measurements,
+        config: &SimulationConfig,
+    ) -> f64 {
+        let (their_indices, times) = measurements;
+        let belief = &self.beliefs[index];
+        let mut excess_variance = 0.0;
+        for (&their_index, &time) in their_indices.iter().zip(times.iter()) {
+            let (predicted_time, variance) =
+                self.round_trip_model(&belief.mean, their_index, config);
+            let peer = &self.beliefs[their_index].mean;
+            let own_variance = belief.covariance.trace() / 3.0
+                * (1.0 / (C * belief.mean.beta) + 1.0 / (C * peer.beta)).powi(2);
+            excess_variance += (time - predicted_time).powi(2) - variance - own_variance;
+        }
+        excess_variance / their_indices.len().max(1) as f64
+    }
+
+    // Like `log_likelihood`, for one-way times: the ping and pong of each measurement are scored separately, each with
+    // its own leg's noise and half the round trip observation variance (configured and estimated). The peer's position
+    // error and the clock offset between the two ends correlate the legs.
+    fn one_way_log_likelihood(
+        &self,
+        particle: &Particle,
+        measurements: &Measurements,
+        ping_times: &OVector<f64, OS>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> f64 {
+        let (their_indices, times) = measurements;
+        let offset_variance = 2.0 * config.kf_model_clock_offset_variance;
+        let mut log_likelihood = 0.0;
+
+        for ((&their_index, &time), &ping_time) in their_indices
+            .iter()
+            .zip(times.iter())
+            .zip(ping_times.iter())
+        {
+            let peer = &self.beliefs[their_index];
+            let distance = config
+                .model_propagation
+                .path_length(&particle.position, &peer.mean.position);
+
+            let inverse_speeds =
+                Vector2::new(1.0 / (C * particle.beta), 1.0 / (C * peer.mean.beta));
+            let residual = Vector2::new(
+                ping_time - (distance * inverse_speeds[0] + particle.tau),
+                time - ping_time - (distance * inverse_speeds[1] + peer.mean.tau),
+            );
+
+            let leg_variance = |beta: f64| {
+                (distance / (C * beta.powi(2))).powi(2) * config.beta_variance
+                    + config.tau_variance
+                    + (config.kf_model_tof_observation_variance + self.excess_noise.excess) / 2.0
+                    + offset_variance
+            };
+            let peer_variance =
+                peer.covariance.trace() / 3.0 * inverse_speeds * inverse_speeds.transpose();
+            let covariance = (Matrix2::new(
+                leg_variance(particle.beta),
+                -offset_variance,
+                -offset_variance,
+                leg_variance(peer.mean.beta),
+            ) + peer_variance)
+                / nodes[their_index].trust;
+            let Some(inverse) = covariance.try_inverse() else {
+                return f64::NEG_INFINITY;
+            };
+
+            log_likelihood -= 0.5 * (residual.transpose() * inverse * residual)[0]
+                + 0.5 * ((2.0 * std::f64::consts::PI).powi(2) * covariance.determinant()).ln();
+        }
+
+        log_likelihood
+    }
+
+    // Move a node's particles with the process noise and reweight them by a measurement likelihood
+    fn update_with(
+        &mut self,
+        index: usize,
+        nodes: &[Node],
+        config: &SimulationConfig,
+        log_likelihood: impl Fn(&Self, &Particle) -> f64,
+    ) -> Result<(), Box<dyn Error>> {
+        // predict: random walk with the same process noise as the Kalman filters
+        let position_sigma = config.kf_model_position_variance.sqrt();
+        let beta_noise = Normal::new(0.0, config.kf_model_beta_variance.sqrt())?;
+        let tau_noise = Normal::new(0.0, config.kf_model_tau_variance.sqrt())?;
+        let mut particles = std::mem::take(&mut self.beliefs[index].particles);
+        for particle in particles.iter_mut() {
+            let Some(position) =
+                sample_surface_position(&particle.position, position_sigma, &mut self.rng)
+            else {
+                info!(
+                    "particle of node {} left the earth's surface, re-initializing",
+                    index
+                );
+                self.beliefs[index] = self.initial_belief(&nodes[index], config);
+                self.resets += 1;
+                return Ok(());
+            };
+            particle.position = position;
+            particle.beta = (particle.beta + beta_noise.sample(&mut self.rng))
+                .clamp(config.beta_min, config.beta_max);
+            particle.tau = (particle.tau + tau_noise.sample(&mut self.rng))
+                .clamp(config.tau_min, config.tau_max);
+        }
+
+        let (_, prior_covariance) = position_moments(&particles, &self.beliefs[index].weights);
+
+        // update: reweight by the measurement likelihood, normalizing in log space to avoid underflow
+        let log_weights: Vec<f64> = particles
+            .iter()
+            .zip(&self.beliefs[index].weights)
+            .map(|(particle, weight)| weight.ln() + log_likelihood(self, particle))
+            .collect();
+        let max_log_weight = log_weights
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if !max_log_weight.is_finite() {
+            info!(
+                "particle weights for node {} collapsed, re-initializing",
+                index
+            );
+            self.beliefs[index] = self.initial_belief(&nodes[index], config);
+            self.resets += 1;
+            return Ok(());
+        }
+        let mut weights: Vec<f64> = log_weights
+            .iter()
+            .map(|log_weight| (log_weight - max_log_weight).exp())
+            .collect();
+        let total_weight: f64 = weights.iter().sum();
+        weights
+            .iter_mut()
+            .for_each(|weight| *weight /= total_weight);
+
+        let belief = &mut self.beliefs[index];
+        belief.particles = particles;
+        belief.weights = weights;
+        belief.effective_sample_size = 1.0 / belief.weights.iter().map(|w| w * w).sum::<f64>();
+        belief.summarize();
+        belief.update_assertion_cross_covariance(&prior_covariance);
+
+        trace!(
+            "node {} particle filter effective sample size: {}",
+            index,
+            belief.effective_sample_size
+        );
+
+        if belief.effective_sample_size < RESAMPLE_THRESHOLD * belief.particles.len() as f64 {
+            belief.resample(&mut self.rng);
+        }
+
+        Ok(())
+    }
+}
+
+impl PositionEstimator for ParticleFilter {
+    fn name(&self) -> &'static str {
+        "pf"
+    }
+
+    fn init(&mut self, node: &Node, config: &SimulationConfig) {
+        let belief = self.initial_belief(node, config);
+        self.beliefs.push(belief);
+    }
+
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let excess_variance = self.excess_variance(index, measurements, config);
+        self.excess_noise.update(excess_variance);
+        self.update_with(index, nodes, config, |filter, particle| {
+            filter.log_likelihood(particle, measurements, nodes, config)
+        })
+    }
+
+    fn update_timed(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        ping_times: &OVector<f64, OS>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        // the noise is estimated from the round trip times, which the clock offsets cancel out of
+        let excess_variance = self.excess_variance(index, measurements, config);
+        self.excess_noise.update(excess_variance);
+        self.update_with(index, nodes, config, |filter, particle| {
+            filter.one_way_log_likelihood(particle, measurements, ping_times, nodes, config)
+        })
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        ecef_position(&self.beliefs[index].mean.position)
+    }
+
+    // shift the whole particle set so that its mean lands on the position
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        let belief = &mut self.beliefs[index];
+        let shift = position_vector(position) - belief.mean.position;
+        for particle in belief.particles.iter_mut() {
+            particle.position = project_to_surface(&(particle.position + shift))
+                .unwrap_or(position_vector(position));
+        }
+        belief.summarize();
+    }
+
+    fn covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.beliefs[index].covariance)
+    }
+
+    fn assertion_cross_covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.beliefs[index].assertion_cross_covariance)
+    }
+
+    fn beta(&self, index: usize) -> Option<f64> {
+        Some(self.beliefs[index].mean.beta)
+    }
+
+    fn tau(&self, index: usize) -> Option<f64> {
+        Some(self.beliefs[index].mean.tau)
+    }
+
+    fn effective_sample_size(&self, index: usize) -> Option<f64> {
+        Some(self.beliefs[index].effective_sample_size)
+    }
+
+    fn resets(&self) -> Option<usize> {
+        Some(self.resets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection_rejects_positions_without_a_surface_point() {
+        let paris = position_vector(WGS84::from_degrees_and_meters(48.86, 2.35, 1e4).into());
+        let surface = project_to_surface(&paris).unwrap();
+        let altitude = WGS84::from(ecef_position(&surface)).altitude();
+        assert!(altitude.abs() < 1e-3, "{}", altitude);
+        assert!(project_to_surface(&Vector3::zeros()).is_none());
+        assert!(project_to_surface(&Vector3::new(1e3, -2e3, 5e2)).is_none());
+        assert!(project_to_surface(&Vector3::new(f64::NAN, 0.0, 6.4e6)).is_none());
+        assert!(project_to_surface(&Vector3::new(f64::INFINITY, 0.0, 0.0)).is_none());
+    }
+}
@@ -1,17 +1,25 @@
-use crate::kalman::N_MEASUREMENTS;
-use crate::types::SimulationConfig;
+use crate::types::{AsymmetricPaths, DelayStrategy, PropagationModel, SimulationConfig};
 use crate::{kalman::OS, types::Node};
 use log::trace;
 use nalgebra::OVector;
 use nav_types::ECEF;
 use rand::prelude::*;
+use rand_distr::Exp;
 use rand_distr::LogNormal;
 use rand_distr::Normal;
 use std::error::Error;
 
 pub const C: f64 = 299_792_458.0; // speed of light in m/s
 
-pub fn simulate_ping_pong_tof(
+// A batch of time-of-flight measurements: the indices of the measured peers and the corresponding round trip times (s)
+pub type Measurements = (Vec<usize>, OVector<f64, OS>);
+
+// Measurements with per-direction timestamps: the round trip batch and each ping's one-way time (s) from our clock at
+// sending to the peer's clock at receiving. A pong's one-way time is its round trip time minus its ping's.
+pub type TimedMeasurements = (Measurements, OVector<f64, OS>);
+
+// One-way times (s) of a ping from the measuring node at `true_position` to `n2` and of the pong back, in true time
+pub fn simulate_ping_pong_legs(
     // n1: &Node,
     true_position: ECEF<f64>,
     true_beta: f64,
@@ -19,9 +27,65 @@ pub fn simulate_ping_pong_tof(
     n2: &Node,
     config: &SimulationConfig,
     rng: &mut impl Rng,
+) -> Result<(f64, f64), Box<dyn Error>> {
+    let ping_distance =
+        config
+            .propagation
+            .sample_ecef_path_length(true_position, n2.true_position, rng)?;
+    let pong_distance = match config.asymmetric_paths {
+        // the pong takes its own route
+        Some(_) => {
+            config
+                .propagation
+                .sample_ecef_path_length(n2.true_position, true_position, rng)?
+        }
+        None => ping_distance,
+    };
+    ping_pong_legs(
+        ping_distance,
+        pong_distance,
+        true_beta,
+        true_tau,
+        n2,
+        config,
+        rng,
+    )
+}
+
+// extra time (s) a leg of `distance` (m) takes on an asymmetric path: its own detour and queueing
+fn asymmetric_delay(
+    distance: f64,
+    beta: f64,
+    paths: &AsymmetricPaths,
+    rng: &mut impl Rng,
 ) -> Result<f64, Box<dyn Error>> {
-    let true_distance = (true_position - n2.true_position).norm();
+    let inflation = if paths.inflation_spread > 0.0 {
+        Normal::new(0.0, paths.inflation_spread)?
+            .sample(rng)
+            .exp()
+            .max(1.0)
+    } else {
+        1.0
+    };
+    let queueing = if paths.queueing_delay > 0.0 {
+        Exp::new(1.0 / paths.queueing_delay)?.sample(rng)
+    } else {
+        0.0
+    };
+    Ok(distance * (inflation - 1.0) / (C * beta) + queueing)
+}
 
+// One-way times (s) of a ping over `ping_distance` and a pong over `pong_distance` (m) with noisy message speeds and
+// latencies on both legs
+fn ping_pong_legs(
+    ping_distance: f64,
+    pong_distance: f64,
+    true_beta: f64,
+    true_tau: f64,
+    n2: &Node,
+    config: &SimulationConfig,
+    rng: &mut impl Rng,
+) -> Result<(f64, f64), Box<dyn Error>> {
     let beta_1 = Normal::new(true_beta, config.beta_variance.sqrt())?
         .sample(rng)
         .clamp(config.beta_min, config.beta_max);
@@ -38,9 +102,12 @@ pub fn simulate_ping_pong_tof(
         .sample(rng)
         .clamp(config.tau_min, config.tau_max);
 
-    let ping_time = true_distance / (C * beta_1) + tau_1;
-    let pong_time = true_distance / (C * beta_2) + tau_2;
-    let total_time = ping_time + pong_time;
+    let mut ping_time = ping_distance / (C * beta_1) + tau_1;
+    let mut pong_time = pong_distance / (C * beta_2) + tau_2;
+    if let Some(paths) = &config.asymmetric_paths {
+        ping_time += asymmetric_delay(ping_distance, beta_1, paths, rng)?;
+        pong_time += asymmetric_delay(pong_distance, beta_2, paths, rng)?;
+    }
 
     trace!(
       "beta_1: {:.6}, tau_1: {:.9}, ping_time: {:.9}, beta_2: {:.6}, tau_2: {:.9}, pong_time: {:.9}",
@@ -53,12 +120,84 @@ pub fn simulate_ping_pong_tof(
   );
 
     trace!(
-        "true distance: {:.3}, measured time: {:.9}",
-        true_distance,
-        total_time,
+        "ping distance: {:.3}, pong distance: {:.3}, measured time: {:.9}",
+        ping_distance,
+        pong_distance,
+        ping_time + pong_time,
+    );
+
+    Ok((ping_time, pong_time))
+}
+
+// One-way times (s) that two colluding nodes report: as if both were at their asserted positions, with realistic noise
+// so the fabrication doesn't stand out
+pub fn fabricated_legs(
+    me: &Node,
+    them: &Node,
+    config: &SimulationConfig,
+    rng: &mut impl Rng,
+) -> Result<(f64, f64), Box<dyn Error>> {
+    let asserted_distance = config
+        .propagation
+        .ecef_path_length(me.asserted_position, them.asserted_position);
+    trace!(
+        "nodes {} and {} fabricate a round trip over {:.3} m",
+        me.id,
+        them.id,
+        asserted_distance
     );
+    ping_pong_legs(
+        asserted_distance,
+        asserted_distance,
+        me.true_beta,
+        me.true_tau,
+        them,
+        config,
+        rng,
+    )
+}
+
+// Delay (s) that a responding node adds to its pong to the measuring node, 0 unless the responder is a delay attacker
+// targeting it
+pub fn injected_delay(
+    my_position: ECEF<f64>,
+    my_node_index: Option<usize>,
+    responder: &Node,
+    propagation: &PropagationModel,
+    rng: &mut impl Rng,
+) -> Result<f64, Box<dyn Error>> {
+    let Some(attack) = &responder.delay_attack else {
+        return Ok(0.0);
+    };
+    if !my_node_index.is_some_and(|index| attack.victims.contains(&index)) {
+        return Ok(0.0);
+    }
 
-    Ok(total_time)
+    let delay = match attack.strategy {
+        DelayStrategy::Constant { delay } => delay,
+        DelayStrategy::Targeted => {
+            // the attacker doesn't know the measuring node's message speed, so it assumes both legs travel at its own
+            let true_distance = propagation.ecef_path_length(my_position, responder.true_position);
+            let fake_distance =
+                propagation.ecef_path_length(my_position, responder.asserted_position);
+            2.0 * (fake_distance - true_distance) / (C * responder.true_beta)
+        }
+        DelayStrategy::Random {
+            delay_min,
+            delay_max,
+        } => {
+            if delay_min > delay_max {
+                return Err(
+                    format!("invalid random delay range [{}, {}]", delay_min, delay_max).into(),
+                );
+            }
+            rng.gen_range(delay_min..=delay_max)
+        }
+    };
+    trace!("node {} delays its response by {:.9}", responder.id, delay);
+
+    // a response can't arrive before it was sent
+    Ok(delay.max(0.0))
 }
 
 pub fn generate_measurements(
@@ -68,47 +207,232 @@ pub fn generate_measurements(
     my_node_index: Option<usize>,
     nodes: &[Node],
     config: &SimulationConfig,
-) -> Result<(Vec<usize>, OVector<f64, OS>), Box<dyn Error>> {
-    let mut rng = rand::thread_rng();
+    rng: &mut impl Rng,
+) -> Result<Measurements, Box<dyn Error>> {
+    let (measurements, _) = generate_timed_measurements(
+        true_position,
+        true_beta,
+        true_tau,
+        my_node_index,
+        nodes,
+        config,
+        rng,
+    )?;
+    Ok(measurements)
+}
+
+// Measurements along with the one-way ping times between the clocks of both ends (see `TimedMeasurements`)
+pub fn generate_timed_measurements(
+    true_position: ECEF<f64>,
+    true_beta: f64,
+    true_tau: f64,
+    my_node_index: Option<usize>,
+    nodes: &[Node],
+    config: &SimulationConfig,
+    rng: &mut impl Rng,
+) -> Result<TimedMeasurements, Box<dyn Error>> {
+    let n_measurements = config.n_measurements;
 
-    // Filter nodes within range and exclude the current node
+    // Filter nodes within range and exclude the current node and untrusted nodes
     let eligible_nodes: Vec<(usize, &Node)> = nodes
         .iter()
         .enumerate()
         .filter(|&(i, node)| {
             Some(i) != my_node_index
                 && true_position.distance(&node.true_position) <= config.message_distance_max
+                // stop measuring untrusted nodes (until their reputation recovers)
+                && (!config.reputation || node.trust >= config.reputation_threshold)
         })
         .collect();
 
-    if eligible_nodes.len() < N_MEASUREMENTS {
+    if eligible_nodes.len() < n_measurements {
         return Err(format!(
             "Not enough eligible nodes. Found {} but need {}",
             eligible_nodes.len(),
-            N_MEASUREMENTS
+            n_measurements
         )
         .into());
     }
 
-    // Randomly select N_MEASUREMENTS unique nodes
+    // Randomly select n_measurements unique nodes
     let selected_nodes = eligible_nodes
-        .choose_multiple(&mut rng, N_MEASUREMENTS)
+        .choose_multiple(rng, n_measurements)
         .collect::<Vec<_>>();
 
     let their_indices: Vec<usize> = selected_nodes.iter().map(|&(i, _)| *i).collect();
-    let mut times = Vec::with_capacity(N_MEASUREMENTS);
+    let mut times = Vec::with_capacity(n_measurements);
+    let mut ping_times = Vec::with_capacity(n_measurements);
+
+    // colluding dishonest nodes fabricate their mutual measurements
+    let colluder = my_node_index
+        .map(|i| &nodes[i])
+        .filter(|me| config.colluding && !me.honest);
+    let my_clock_offset = my_node_index.map_or(0.0, |i| nodes[i].clock_offset);
 
     for &(i, node) in &selected_nodes {
         assert!(my_node_index != Some(*i));
-        times.push(simulate_ping_pong_tof(
+        // a delaying responder holds back its pong
+        let (ping_time, pong_time, delay) = match colluder {
+            Some(me) if !node.honest => {
+                let (ping_time, pong_time) = fabricated_legs(me, node, config, rng)?;
+                (ping_time, pong_time, 0.0)
+            }
+            _ => {
+                let (ping_time, pong_time) =
+                    simulate_ping_pong_legs(true_position, true_beta, true_tau, node, config, rng)?;
+                let delay =
+                    injected_delay(true_position, my_node_index, node, &config.propagation, rng)?;
+                (ping_time, pong_time, delay)
+            }
+        };
+        times.push(ping_time + pong_time + delay);
+        // the ping is sent by our clock and received by theirs
+        ping_times.push(ping_time + node.clock_offset - my_clock_offset);
+    }
+
+    Ok((
+        (their_indices, OVector::<f64, OS>::from_vec(times)),
+        OVector::<f64, OS>::from_vec(ping_times),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::DelayAttack;
+    use h3o::{LatLng, Resolution};
+    use nav_types::WGS84;
+    use rand_chacha::ChaCha8Rng;
+
+    fn position(latitude: f64, longitude: f64) -> ECEF<f64> {
+        WGS84::from_degrees_and_meters(latitude, longitude, 0.0).into()
+    }
+
+    // a node at `true_position` asserting `asserted_position`, delaying its responses to node 0
+    fn attacker(
+        true_position: ECEF<f64>,
+        asserted_position: ECEF<f64>,
+        strategy: DelayStrategy,
+    ) -> Node {
+        let index = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Seven);
+        let mut node = Node::with_positions(
+            1,
+            index,
             true_position,
-            true_beta,
-            true_tau,
-            node,
-            config,
-            &mut rng,
-        )?);
+            index,
+            asserted_position,
+            0.7,
+            1e-4,
+            false,
+        );
+        node.delay_attack = Some(DelayAttack {
+            strategy,
+            victims: [0].into(),
+        });
+        node
+    }
+
+    #[test]
+    fn delays_only_reach_victims() {
+        let (me, them) = (position(0.0, 0.0), position(0.0, 10.0));
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let chord = PropagationModel::Chord;
+        let mut node = attacker(them, them, DelayStrategy::Constant { delay: 1e-3 });
+        let delay = |node: &Node, index, rng: &mut ChaCha8Rng| {
+            injected_delay(me, index, node, &chord, rng).unwrap()
+        };
+
+        assert_eq!(delay(&node, Some(0), &mut rng), 1e-3);
+        assert_eq!(delay(&node, Some(2), &mut rng), 0.0);
+        assert_eq!(delay(&node, None, &mut rng), 0.0);
+        // responses can't be sped up
+        node.delay_attack.as_mut().unwrap().strategy = DelayStrategy::Constant { delay: -1e-3 };
+        assert_eq!(delay(&node, Some(0), &mut rng), 0.0);
+        node.delay_attack = None;
+        assert_eq!(delay(&node, Some(0), &mut rng), 0.0);
+    }
+
+    #[test]
+    fn targeted_delays_fake_the_asserted_distance() {
+        let (me, near, far) = (position(0.0, 0.0), position(0.0, 10.0), position(0.0, 20.0));
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let chord = PropagationModel::Chord;
+
+        // the delayed round trip at the attacker's speed covers the asserted distance both ways
+        let node = attacker(near, far, DelayStrategy::Targeted);
+        let delay = injected_delay(me, Some(0), &node, &chord, &mut rng).unwrap();
+        let round_trip = 2.0 * me.distance(&near) / (C * 0.7) + delay;
+        assert!((round_trip - 2.0 * me.distance(&far) / (C * 0.7)).abs() < 1e-12);
+
+        // an attacker can't pretend to be closer
+        let node = attacker(far, near, DelayStrategy::Targeted);
+        assert_eq!(
+            injected_delay(me, Some(0), &node, &chord, &mut rng).unwrap(),
+            0.0
+        );
     }
 
-    Ok((their_indices, OVector::<f64, OS>::from_vec(times)))
+    #[test]
+    fn random_delays_stay_in_range() {
+        let me = position(0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let chord = PropagationModel::Chord;
+        let strategy = DelayStrategy::Random {
+            delay_min: 1e-4,
+            delay_max: 2e-4,
+        };
+        let node = attacker(position(0.0, 10.0), me, strategy);
+        for _ in 0..100 {
+            let delay = injected_delay(me, Some(0), &node, &chord, &mut rng).unwrap();
+            assert!((1e-4..=2e-4).contains(&delay));
+        }
+
+        let strategy = DelayStrategy::Random {
+            delay_min: 2e-4,
+            delay_max: 1e-4,
+        };
+        let node = attacker(position(0.0, 10.0), me, strategy);
+        assert!(injected_delay(me, Some(0), &node, &chord, &mut rng).is_err());
+    }
+
+    // colluders report the round trip between their assertions; others measure the true one
+    #[test]
+    fn colluders_fabricate_their_mutual_times() {
+        let mut config = example_config();
+        config.n_measurements = 1;
+        config.beta_variance = 0.0;
+        (config.tau_min, config.tau_max) = (5e-3, 5e-3);
+        let index = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Seven);
+        let node = |id, true_position, asserted_position| {
+            Node::with_positions(
+                id,
+                index,
+                true_position,
+                index,
+                asserted_position,
+                0.5,
+                5e-3,
+                false,
+            )
+        };
+        let (here, next_door, far) = (position(0.0, 0.0), position(0.0, 0.01), position(0.0, 20.0));
+        let nodes = [node(0, here, here), node(1, next_door, far)];
+        let round_trip = |distance: f64| 2.0 * distance / (C * 0.5) + 2.0 * 5e-3;
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        for (colluding, distance) in [
+            (true, here.distance(&far)),
+            (false, here.distance(&next_door)),
+        ] {
+            config.colluding = colluding;
+            let (_, times) =
+                generate_measurements(here, 0.5, 5e-3, Some(0), &nodes, &config, &mut rng).unwrap();
+            assert!(
+                (times[0] - round_trip(distance)).abs() < 1e-9,
+                "{}",
+                times[0]
+            );
+        }
+    }
 }
@@ -0,0 +1,241 @@
+use crate::geometry::{normal_neighbor_index, uniform_h3_index};
+use crate::types::{City, PlacementStrategy, Region};
+use h3o::{CellIndex, LatLng, Resolution};
+use log::trace;
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use rand_distr::Distribution;
+use std::error::Error;
+
+// Major metro areas: name, latitude and longitude (degrees) and approximate population (millions)
+pub const METROS: &[(&str, f64, f64, f64)] = &[
+    ("Tokyo", 35.6895, 139.6917, 37.4),
+    ("Delhi", 28.7041, 77.1025, 32.9),
+    ("Shanghai", 31.2304, 121.4737, 29.2),
+    ("Dhaka", 23.8103, 90.4125, 23.2),
+    ("São Paulo", -23.5505, -46.6333, 22.6),
+    ("Mexico City", 19.4326, -99.1332, 22.3),
+    ("Cairo", 30.0444, 31.2357, 22.2),
+    ("Beijing", 39.9042, 116.4074, 21.8),
+    ("Mumbai", 19.0760, 72.8777, 21.3),
+    ("Osaka", 34.6937, 135.5023, 19.0),
+    ("New York", 40.7128, -74.0060, 18.9),
+    ("Karachi", 24.8607, 67.0011, 17.2),
+    ("Buenos Aires", -34.6037, -58.3816, 15.5),
+    ("Istanbul", 41.0082, 28.9784, 15.8),
+    ("Kolkata", 22.5726, 88.3639, 15.3),
+    ("Lagos", 6.5244, 3.3792, 15.9),
+    ("Manila", 14.5995, 120.9842, 14.7),
+    ("Guangzhou", 23.1291, 113.2644, 14.3),
+    ("Rio de Janeiro", -22.9068, -43.1729, 13.7),
+    ("Los Angeles", 34.0522, -118.2437, 12.5),
+    ("Moscow", 55.7558, 37.6173, 12.6),
+    ("Kinshasa", -4.4419, 15.2663, 16.3),
+    ("Jakarta", -6.2088, 106.8456, 11.2),
+    ("Paris", 48.8566, 2.3522, 11.1),
+    ("Lima", -12.0464, -77.0428, 11.0),
+    ("Seoul", 37.5665, 126.9780, 10.0),
+    ("Bangkok", 13.7563, 100.5018, 10.9),
+    ("London", 51.5074, -0.1278, 9.6),
+    ("Chicago", 41.8781, -87.6298, 8.9),
+    ("Tehran", 35.6892, 51.3890, 9.4),
+    ("Singapore", 1.3521, 103.8198, 5.9),
+    ("Johannesburg", -26.2041, 28.0473, 6.2),
+    ("Sydney", -33.8688, 151.2093, 5.1),
+    ("Frankfurt", 50.1109, 8.6821, 2.3),
+];
+
+// Built-in metro areas as cities weighted by population, all with the same spread (m)
+pub fn metro_cities(spread: f64) -> Vec<City> {
+    METROS
+        .iter()
+        .map(|&(_, latitude, longitude, population)| City {
+            latitude,
+            longitude,
+            weight: population,
+            spread,
+        })
+        .collect()
+}
+
+// Draw a node's true location according to the placement strategy, inside the region if there is one: uniform
+// placement is uniform over the region, and the other strategies are redrawn until they fall inside it
+pub fn placement_index<R: Rng>(
+    strategy: &PlacementStrategy,
+    region: Option<&Region>,
+    resolution: Resolution,
+    rng: &mut R,
+) -> Result<CellIndex, Box<dyn Error>> {
+    match (strategy, region) {
+        (_, None) => strategy_index(strategy, resolution, rng),
+        (PlacementStrategy::Uniform, Some(region)) => region.sample(resolution, rng),
+        (_, Some(region)) => region.resample(rng, |rng| strategy_index(strategy, resolution, rng)),
+    }
+}
+
+fn strategy_index(
+    strategy: &PlacementStrategy,
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> Result<CellIndex, Box<dyn Error>> {
+    match strategy {
+        PlacementStrategy::Uniform => Ok(uniform_h3_index(resolution, rng)),
+        PlacementStrategy::Cities { cities } => city_index(cities, resolution, rng),
+        PlacementStrategy::Metros { spread } => city_index(&metro_cities(*spread), resolution, rng),
+        PlacementStrategy::Bounds {
+            min_latitude,
+            max_latitude,
+            min_longitude,
+            max_longitude,
+        } => bounds_index(
+            *min_latitude,
+            *max_latitude,
+            *min_longitude,
+            *max_longitude,
+            resolution,
+            rng,
+        ),
+    }
+}
+
+// pick a city in proportion to its weight, then a location gaussian around it
+fn city_index(
+    cities: &[City],
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> Result<CellIndex, Box<dyn Error>> {
+    let weights = WeightedIndex::new(cities.iter().map(|city| city.weight))
+        .map_err(|e| format!("invalid city weights: {}", e))?;
+    let city = &cities[weights.sample(rng)];
+    trace!("placing a node around {:?}", city);
+
+    let center = LatLng::new(city.latitude, city.longitude)?.to_cell(resolution);
+    Ok(normal_neighbor_index(
+        center,
+        city.spread.powi(2),
+        resolution,
+        rng,
+    ))
+}
+
+// uniform over the area of a latitude / longitude box: uniform in longitude and in the sine of the latitude
+fn bounds_index(
+    min_latitude: f64,
+    max_latitude: f64,
+    min_longitude: f64,
+    max_longitude: f64,
+    resolution: Resolution,
+    rng: &mut impl Rng,
+) -> Result<CellIndex, Box<dyn Error>> {
+    if min_latitude > max_latitude {
+        return Err(format!(
+            "invalid latitude range [{}, {}]",
+            min_latitude, max_latitude
+        )
+        .into());
+    }
+    let (min_sin, max_sin) = (
+        min_latitude.to_radians().sin(),
+        max_latitude.to_radians().sin(),
+    );
+    let latitude = rng.gen_range(min_sin..=max_sin).asin().to_degrees();
+
+    // a box crossing the antimeridian wraps around
+    let max_longitude = if min_longitude > max_longitude {
+        max_longitude + 360.0
+    } else {
+        max_longitude
+    };
+    let longitude = rng.gen_range(min_longitude..=max_longitude);
+    let longitude = if longitude > 180.0 {
+        longitude - 360.0
+    } else {
+        longitude
+    };
+
+    Ok(LatLng::new(latitude, longitude)?.to_cell(resolution))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn bounds(latitudes: (f64, f64), longitudes: (f64, f64)) -> PlacementStrategy {
+        PlacementStrategy::Bounds {
+            min_latitude: latitudes.0,
+            max_latitude: latitudes.1,
+            min_longitude: longitudes.0,
+            max_longitude: longitudes.1,
+        }
+    }
+
+    fn draw(strategy: &PlacementStrategy, n: usize) -> Vec<LatLng> {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        (0..n)
+            .map(|_| {
+                LatLng::from(placement_index(strategy, None, Resolution::Seven, &mut rng).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cities_are_picked_in_proportion_to_their_weights() {
+        let city = |latitude, longitude, weight| City {
+            latitude,
+            longitude,
+            weight,
+            spread: 10e3,
+        };
+        let (paris, sydney) = (city(48.86, 2.35, 3.0), city(-33.87, 151.21, 1.0));
+        let center = |city: &City| LatLng::new(city.latitude, city.longitude).unwrap();
+        let strategy = PlacementStrategy::Cities {
+            cities: vec![paris, sydney],
+        };
+
+        let locations = draw(&strategy, 400);
+        let near = |city: &City| {
+            locations
+                .iter()
+                .filter(|location| location.distance_m(center(city)) < 100e3)
+                .count()
+        };
+        // every node is around one of them, three times as often around the heavier one (within 3 sigma)
+        assert_eq!(near(&paris) + near(&sydney), 400);
+        assert!((274..=326).contains(&near(&paris)), "{}", near(&paris));
+
+        let no_weight = PlacementStrategy::Cities {
+            cities: vec![city(0.0, 0.0, 0.0)],
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        assert!(placement_index(&no_weight, None, Resolution::Seven, &mut rng).is_err());
+    }
+
+    #[test]
+    fn bounds_are_sampled_uniformly_over_their_area() {
+        // cells are within about a kilometer of the drawn location
+        let tolerance = 0.02;
+        let locations = draw(&bounds((10.0, 20.0), (170.0, -170.0)), 200);
+        for location in &locations {
+            assert!((10.0 - tolerance..=20.0 + tolerance).contains(&location.lat()));
+            assert!(
+                location.lng().abs() >= 170.0 - tolerance,
+                "{}",
+                location.lng()
+            );
+        }
+
+        // half of a hemisphere's area is below 30 degrees of latitude, a third of its latitudes are
+        let locations = draw(&bounds((0.0, 90.0), (-180.0, 180.0)), 400);
+        let low = locations
+            .iter()
+            .filter(|location| location.lat() < 30.0)
+            .count();
+        assert!((170..=230).contains(&low), "{}", low);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let inverted = bounds((20.0, 10.0), (0.0, 10.0));
+        assert!(placement_index(&inverted, None, Resolution::Seven, &mut rng).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::types::PropagationModel;
+use nalgebra::Vector3;
+use nav_types::ECEF;
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::error::Error;
+
+// WGS84 semi-major axis (m) and flattening
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+// step (m) of the central differences for the gradients of non-chord path lengths
+const GRADIENT_STEP: f64 = 10.0;
+
+// below this central angle (rad) the geodesic is the chord
+const MINIMUM_CENTRAL_ANGLE: f64 = 1e-9;
+
+// mean earth radius (m) of the spherical earth path lengths are inverted on
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// bisection steps when inverting the route inflation, halving half the earth's circumference to well below a meter
+const INVERSION_STEPS: usize = 50;
+
+pub fn position_vector(position: ECEF<f64>) -> Vector3<f64> {
+    Vector3::new(position.x(), position.y(), position.z())
+}
+
+// reduced latitude and longitude (rad) of the surface point below an ECEF position, projecting along the radius
+fn reduced_coordinates(position: &Vector3<f64>) -> (f64, f64) {
+    let horizontal = position.x.hypot(position.y);
+    (
+        (position.z / ((1.0 - WGS84_F) * horizontal)).atan(),
+        position.y.atan2(position.x),
+    )
+}
+
+// Geodesic distance (m) on the WGS84 ellipsoid between the surface points below two positions, with Lambert's formula
+// (accurate to ~10 m over thousands of km)
+pub fn geodesic_distance(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    let (a_latitude, a_longitude) = reduced_coordinates(a);
+    let (b_latitude, b_longitude) = reduced_coordinates(b);
+    let unit = |latitude: f64, longitude: f64| {
+        Vector3::new(
+            latitude.cos() * longitude.cos(),
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+        )
+    };
+    let (a_unit, b_unit) = (unit(a_latitude, a_longitude), unit(b_latitude, b_longitude));
+    let central_angle = a_unit.cross(&b_unit).norm().atan2(a_unit.dot(&b_unit));
+    if central_angle < MINIMUM_CENTRAL_ANGLE {
+        return (a - b).norm();
+    }
+
+    let p = (a_latitude + b_latitude) / 2.0;
+    let q = (b_latitude - a_latitude) / 2.0;
+    let x = (central_angle - central_angle.sin()) * p.sin().powi(2) * q.cos().powi(2)
+        / (central_angle / 2.0).cos().powi(2).max(f64::EPSILON);
+    let y = (central_angle + central_angle.sin()) * p.cos().powi(2) * q.sin().powi(2)
+        / (central_angle / 2.0).sin().powi(2);
+    WGS84_A * (central_angle - WGS84_F / 2.0 * (x + y))
+}
+
+impl PropagationModel {
+    fn route_inflation(&self, geodesic: f64) -> f64 {
+        match *self {
+            PropagationModel::RouteInflated {
+                inflation,
+                short_route_inflation,
+                short_route_distance,
+                ..
+            } => inflation + short_route_inflation * (-geodesic / short_route_distance).exp(),
+            _ => 1.0,
+        }
+    }
+
+    // Expected path length (m) of a message between two positions
+    pub fn path_length(&self, a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+        match self {
+            PropagationModel::Chord => (a - b).norm(),
+            PropagationModel::GreatCircle => geodesic_distance(a, b),
+            PropagationModel::RouteInflated { .. } => {
+                let geodesic = geodesic_distance(a, b);
+                geodesic * self.route_inflation(geodesic)
+            }
+        }
+    }
+
+    // Straight-line distance (m) between two surface points whose expected path length is `path_length` (m): the
+    // inverse of `path_length` on a spherical earth, for embedding measured path lengths in space
+    pub fn chord_distance(&self, path_length: f64) -> f64 {
+        let geodesic = match self {
+            PropagationModel::Chord => return path_length.max(0.0),
+            PropagationModel::GreatCircle => path_length,
+            PropagationModel::RouteInflated { .. } => {
+                let (mut low, mut high) = (0.0, std::f64::consts::PI * EARTH_RADIUS);
+                for _ in 0..INVERSION_STEPS {
+                    let middle = (low + high) / 2.0;
+                    if middle * self.route_inflation(middle) < path_length {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+                (low + high) / 2.0
+            }
+        };
+        let central_angle = (geodesic.max(0.0) / EARTH_RADIUS).min(std::f64::consts::PI);
+        2.0 * EARTH_RADIUS * (central_angle / 2.0).sin()
+    }
+
+    // Path length (m) of one simulated message, with the model's noise
+    pub fn sample_path_length(
+        &self,
+        a: &Vector3<f64>,
+        b: &Vector3<f64>,
+        rng: &mut impl Rng,
+    ) -> Result<f64, Box<dyn Error>> {
+        match *self {
+            PropagationModel::RouteInflated { noise, .. } if noise > 0.0 => {
+                let geodesic = geodesic_distance(a, b);
+                let inflation =
+                    self.route_inflation(geodesic) * Normal::new(0.0, noise)?.sample(rng).exp();
+                Ok(geodesic * inflation.max(1.0))
+            }
+            _ => Ok(self.path_length(a, b)),
+        }
+    }
+
+    // Gradient of the expected path length with respect to the first position; zero for coincident chord endpoints
+    pub fn path_gradient(&self, a: &Vector3<f64>, b: &Vector3<f64>) -> Vector3<f64> {
+        match self {
+            PropagationModel::Chord => (a - b) / (a - b).norm().max(f64::EPSILON),
+            _ => Vector3::from_fn(|i, _| {
+                let step = Vector3::ith(i, GRADIENT_STEP);
+                (self.path_length(&(a + step), b) - self.path_length(&(a - step), b))
+                    / (2.0 * GRADIENT_STEP)
+            }),
+        }
+    }
+
+    // Expected path length (m) between two ECEF positions
+    pub fn ecef_path_length(&self, a: ECEF<f64>, b: ECEF<f64>) -> f64 {
+        match self {
+            PropagationModel::Chord => a.distance(&b),
+            _ => self.path_length(&position_vector(a), &position_vector(b)),
+        }
+    }
+
+    // Path length (m) of one simulated message between two ECEF positions
+    pub fn sample_ecef_path_length(
+        &self,
+        a: ECEF<f64>,
+        b: ECEF<f64>,
+        rng: &mut impl Rng,
+    ) -> Result<f64, Box<dyn Error>> {
+        match self {
+            PropagationModel::Chord => Ok((a - b).norm()),
+            _ => self.sample_path_length(&position_vector(a), &position_vector(b), rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nav_types::WGS84;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    fn surface(latitude: f64, longitude: f64) -> Vector3<f64> {
+        position_vector(WGS84::from_degrees_and_meters(latitude, longitude, 0.0).into())
+    }
+
+    #[test]
+    fn geodesic_distance_matches_known_arcs() {
+        // a quarter of the equator and of a meridian (Vincenty: 10018754.17 m and 10001965.73 m)
+        let equator = geodesic_distance(&surface(0.0, 0.0), &surface(0.0, 90.0));
+        assert!((equator - 10_018_754.17).abs() < 10.0, "{}", equator);
+        let meridian = geodesic_distance(&surface(0.0, 0.0), &surface(90.0, 0.0));
+        assert!((meridian - 10_001_965.73).abs() < 10.0, "{}", meridian);
+    }
+
+    #[test]
+    fn geodesic_distance_is_symmetric_and_longer_than_the_chord() {
+        let (paris, sydney) = (surface(48.86, 2.35), surface(-33.87, 151.21));
+        let distance = geodesic_distance(&paris, &sydney);
+        assert!((distance - geodesic_distance(&sydney, &paris)).abs() < 1e-6);
+        assert!(distance > (paris - sydney).norm());
+        assert_eq!(geodesic_distance(&paris, &paris), 0.0);
+        // short distances fall back to the chord
+        let nearby = paris + Vector3::new(1e-3, 0.0, 0.0);
+        assert!((geodesic_distance(&paris, &nearby) - 1e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn route_inflation_lengthens_paths() {
+        let model = PropagationModel::RouteInflated {
+            inflation: 1.5,
+            short_route_inflation: 0.5,
+            short_route_distance: 1e6,
+            noise: 0.2,
+        };
+        let (paris, berlin) = (surface(48.86, 2.35), surface(52.52, 13.40));
+        let geodesic = geodesic_distance(&paris, &berlin);
+        let expected = geodesic * (1.5 + 0.5 * (-geodesic / 1e6).exp());
+        assert!((model.path_length(&paris, &berlin) - expected).abs() < 1e-6);
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert!(model.sample_path_length(&paris, &berlin, &mut rng).unwrap() >= geodesic);
+        }
+    }
+
+    #[test]
+    fn chord_distance_inverts_path_lengths() {
+        let models = [
+            PropagationModel::Chord,
+            PropagationModel::GreatCircle,
+            PropagationModel::RouteInflated {
+                inflation: 1.5,
+                short_route_inflation: 0.5,
+                short_route_distance: 1e6,
+                noise: 0.0,
+            },
+        ];
+        let paris = surface(48.86, 2.35);
+        for destination in [
+            surface(48.0, 2.0),
+            surface(52.52, 13.40),
+            surface(-33.87, 151.21),
+        ] {
+            let chord = (paris - destination).norm();
+            for model in models {
+                let distance = model.chord_distance(model.path_length(&paris, &destination));
+                // the spherical earth is within about 0.5% of the ellipsoid
+                assert!(
+                    (distance - chord).abs() < 5e-3 * chord,
+                    "{:?} {} {}",
+                    model,
+                    distance,
+                    chord
+                );
+            }
+        }
+        assert_eq!(PropagationModel::GreatCircle.chord_distance(-1.0), 0.0);
+    }
+
+    #[test]
+    fn path_gradient_points_away_from_the_other_end() {
+        let (paris, berlin) = (surface(48.86, 2.35), surface(52.52, 13.40));
+        let model = PropagationModel::GreatCircle;
+        let gradient = model.path_gradient(&paris, &berlin);
+        let direction = (paris - berlin).normalize();
+        // the geodesic leaves along the surface, close to the chord for nearby points
+        assert!(
+            gradient.norm() > 0.99 && gradient.norm() < 1.01,
+            "{}",
+            gradient.norm()
+        );
+        assert!(gradient.normalize().dot(&direction) > 0.99);
+    }
+}
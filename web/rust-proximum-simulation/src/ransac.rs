@@ -0,0 +1,250 @@
+use crate::estimator::PositionEstimator;
+use crate::kalman::OS;
+use crate::least_squares::{ls_estimate_position_ecef, ls_time_residuals};
+use crate::physics::Measurements;
+use crate::types::{MeasurementWeight, Node, SimulationConfig, SimulationRng};
+use log::trace;
+use nalgebra::OVector;
+use nav_types::ECEF;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// measurements in a minimal subset: the least squares solve needs at least 4
+const MINIMAL_SUBSET: usize = 4;
+
+// random stream used for subset sampling, so that it doesn't change the simulation's measurement stream
+const RANSAC_STREAM: u64 = 2;
+
+// A RANSAC fit of one node's measurements: the refit position and the peers whose measurements were rejected
+pub struct RansacFit {
+    pub position: ECEF<f64>,
+    pub outliers: Vec<usize>,
+}
+
+// Fit a node's position while rejecting outlying measurements (e.g. delayed or lying peers):
+//
+// 1. solve the least squares problem on random minimal subsets of 4 measurements
+// 2. score every subset solution by the number of measurements within `ransac_threshold` of its predictions
+// 3. refit on the largest consensus set (ties broken by the smaller squared residual sum)
+pub fn ransac_estimate_position_ecef(
+    initial_estimate: ECEF<f64>,
+    asserted_position: ECEF<f64>,
+    measurements: &Measurements,
+    positions: &[ECEF<f64>],
+    trust: &[f64],
+    config: &SimulationConfig,
+    rng: &mut SimulationRng,
+) -> Result<RansacFit, Box<dyn Error>> {
+    let (their_indices, times) = measurements;
+    let n = their_indices.len();
+    if n < MINIMAL_SUBSET {
+        return Err("At least 4 measurements are required for 3D position estimation".into());
+    }
+
+    let subset_measurements = |members: &[usize]| -> Measurements {
+        (
+            members.iter().map(|&i| their_indices[i]).collect(),
+            OVector::<f64, OS>::from_iterator(members.len(), members.iter().map(|&i| times[i])),
+        )
+    };
+
+    // consensus set of a candidate position and its squared residual sum; the current estimate is the first candidate
+    let consensus = |position: ECEF<f64>| -> (Vec<usize>, f64) {
+        let residuals = ls_time_residuals(position, measurements, positions, config);
+        let inliers: Vec<usize> = (0..n)
+            .filter(|&i| residuals[i].abs() <= config.ransac_threshold)
+            .collect();
+        let cost = inliers.iter().map(|&i| residuals[i].powi(2)).sum();
+        (inliers, cost)
+    };
+    let (mut best_inliers, mut best_cost) = consensus(initial_estimate);
+
+    for iteration in 0..config.ransac_iterations {
+        let members = sample(rng, n, MINIMAL_SUBSET).into_vec();
+        // a degenerate subset (e.g. peers at the same position) just doesn't produce a candidate
+        let Ok((position, _)) = ls_estimate_position_ecef(
+            initial_estimate,
+            asserted_position,
+            &subset_measurements(&members),
+            positions,
+            trust,
+            config,
+        ) else {
+            continue;
+        };
+
+        let (inliers, cost) = consensus(position);
+        trace!(
+            "RANSAC iteration {}: {} inliers, cost {}",
+            iteration,
+            inliers.len(),
+            cost
+        );
+        if inliers.len() > best_inliers.len()
+            || (inliers.len() == best_inliers.len() && cost < best_cost)
+        {
+            best_inliers = inliers;
+            best_cost = cost;
+        }
+    }
+
+    // without enough consensus for a solve, fall back to all measurements
+    if best_inliers.len() < MINIMAL_SUBSET {
+        best_inliers = (0..n).collect();
+    }
+
+    let (position, _) = ls_estimate_position_ecef(
+        initial_estimate,
+        asserted_position,
+        &subset_measurements(&best_inliers),
+        positions,
+        trust,
+        config,
+    )?;
+    let outliers = (0..n)
+        .filter(|i| !best_inliers.contains(i))
+        .map(|i| their_indices[i])
+        .collect();
+
+    Ok(RansacFit { position, outliers })
+}
+
+// Least squares with RANSAC outlier rejection: one position per node, plus how often each node rejected each peer
+pub struct Ransac {
+    positions: Vec<ECEF<f64>>,
+    // inlier (1) / outlier (0) weights of each node's last measurements
+    weights: Vec<Vec<MeasurementWeight>>,
+    // number of times each node rejected a peer's measurement, by peer id
+    rejections: Vec<BTreeMap<usize, usize>>,
+    rng: SimulationRng,
+}
+
+impl Ransac {
+    pub fn new(config: &SimulationConfig) -> Self {
+        let mut rng = SimulationRng::seed_from_u64(config.seed.unwrap_or_default());
+        rng.set_stream(RANSAC_STREAM);
+        Self {
+            positions: Vec::new(),
+            weights: Vec::new(),
+            rejections: Vec::new(),
+            rng,
+        }
+    }
+}
+
+impl PositionEstimator for Ransac {
+    fn name(&self) -> &'static str {
+        "ransac"
+    }
+
+    fn init(&mut self, node: &Node, _config: &SimulationConfig) {
+        self.positions.push(node.initial_position);
+        self.weights.push(Vec::new());
+        self.rejections.push(BTreeMap::new());
+    }
+
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let fit = ransac_estimate_position_ecef(
+            self.positions[index],
+            nodes[index].asserted_position,
+            measurements,
+            &self.positions,
+            &nodes.iter().map(|node| node.trust).collect::<Vec<_>>(),
+            config,
+            &mut self.rng,
+        )?;
+
+        self.positions[index] = fit.position;
+        self.weights[index] = measurements
+            .0
+            .iter()
+            .map(|&peer| MeasurementWeight {
+                peer,
+                weight: if fit.outliers.contains(&peer) {
+                    0.0
+                } else {
+                    1.0
+                },
+            })
+            .collect();
+        for &peer in &fit.outliers {
+            *self.rejections[index].entry(peer).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        self.positions[index]
+    }
+
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        self.positions[index] = position;
+    }
+
+    fn measurement_weights(&self, index: usize) -> Option<Vec<MeasurementWeight>> {
+        Some(self.weights[index].clone())
+    }
+
+    fn rejected_peers(&self, index: usize) -> Option<BTreeMap<usize, usize>> {
+        Some(self.rejections[index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::C;
+    use crate::test_util::example_config;
+    use nav_types::WGS84;
+
+    fn position(latitude: f64, longitude: f64) -> ECEF<f64> {
+        WGS84::from_degrees_and_meters(latitude, longitude, 0.0).into()
+    }
+
+    #[test]
+    fn delayed_measurements_are_rejected() {
+        let config = example_config();
+        let truth = position(45.0, 10.0);
+        let positions: Vec<ECEF<f64>> = (0..10)
+            .map(|i| position(30.0 + 4.0 * i as f64, -20.0 + 9.0 * (i % 5) as f64))
+            .collect();
+        let mut times = OVector::<f64, OS>::from_iterator(
+            positions.len(),
+            positions.iter().map(|&peer| {
+                let distance = config.model_propagation.ecef_path_length(truth, peer);
+                2.0 * (distance / (config.ls_model_beta * C) + config.ls_model_tau)
+            }),
+        );
+        let trust = vec![1.0; positions.len()];
+        let start = position(45.2, 10.2);
+        let mut rng = SimulationRng::seed_from_u64(0);
+        let mut outliers = |times: &OVector<f64, OS>| {
+            let measurements = ((0..positions.len()).collect(), times.clone());
+            ransac_estimate_position_ecef(
+                start,
+                start,
+                &measurements,
+                &positions,
+                &trust,
+                &config,
+                &mut rng,
+            )
+            .unwrap()
+            .outliers
+        };
+        assert!(outliers(&times).is_empty());
+
+        // two peers delay their responses well beyond the inlier threshold
+        times[2] += 5.0 * config.ransac_threshold;
+        times[7] += 5.0 * config.ransac_threshold;
+        assert_eq!(outliers(&times), vec![2, 7]);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::geometry::ecef_to_h3;
+use crate::types::Region;
+use h3o::{CellIndex, LatLng, Resolution};
+use log::trace;
+use nav_types::{ECEF, WGS84};
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use rand_distr::Distribution;
+use std::error::Error;
+
+// draws tried before giving up on finding a location inside a region
+pub const MAX_REGION_ATTEMPTS: usize = 10_000;
+
+// fraction of the way from a cell's boundary to its center at which `Region::nearest` places points
+const BOUNDARY_INSET: f64 = 1e-6;
+
+// [longitude, latitude] (degrees) vertices of a ring
+type Ring = [Vec<f64>];
+
+// fewest distinct vertices of a ring that encloses an area
+const MIN_RING_VERTICES: usize = 3;
+
+// Latitude / longitude bounding box (degrees)
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    min_latitude: f64,
+    max_latitude: f64,
+    min_longitude: f64,
+    max_longitude: f64,
+}
+
+impl Bounds {
+    fn of_points(points: impl Iterator<Item = (f64, f64)>) -> Self {
+        points.fold(
+            Bounds {
+                min_latitude: f64::INFINITY,
+                max_latitude: f64::NEG_INFINITY,
+                min_longitude: f64::INFINITY,
+                max_longitude: f64::NEG_INFINITY,
+            },
+            |bounds, (latitude, longitude)| Bounds {
+                min_latitude: bounds.min_latitude.min(latitude),
+                max_latitude: bounds.max_latitude.max(latitude),
+                min_longitude: bounds.min_longitude.min(longitude),
+                max_longitude: bounds.max_longitude.max(longitude),
+            },
+        )
+    }
+
+    // surface area, up to a constant factor
+    fn area(&self) -> f64 {
+        (self.max_latitude.to_radians().sin() - self.min_latitude.to_radians().sin())
+            * (self.max_longitude - self.min_longitude)
+    }
+
+    // uniform over the box's area, with longitudes wrapped to [-180, 180]
+    fn sample(&self, rng: &mut impl Rng) -> Result<LatLng, Box<dyn Error>> {
+        let latitude = rng
+            .gen_range(self.min_latitude.to_radians().sin()..=self.max_latitude.to_radians().sin())
+            .asin()
+            .to_degrees();
+        let longitude = rng.gen_range(self.min_longitude..=self.max_longitude);
+        Ok(LatLng::new(latitude, wrap_longitude(longitude))?)
+    }
+}
+
+fn wrap_longitude(longitude: f64) -> f64 {
+    (longitude + 540.0).rem_euclid(360.0) - 180.0
+}
+
+// ray casting point-in-ring test in the longitude / latitude plane
+fn ring_contains(ring: &Ring, latitude: f64, longitude: f64) -> bool {
+    let mut inside = false;
+    for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+        let (a_longitude, a_latitude) = (a[0], a[1]);
+        let (b_longitude, b_latitude) = (b[0], b[1]);
+        if (a_latitude > latitude) != (b_latitude > latitude)
+            && longitude
+                < a_longitude
+                    + (latitude - a_latitude) / (b_latitude - a_latitude)
+                        * (b_longitude - a_longitude)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn polygon_contains(rings: &[Vec<Vec<f64>>], latitude: f64, longitude: f64) -> bool {
+    let Some((exterior, holes)) = rings.split_first() else {
+        return false;
+    };
+    ring_contains(exterior, latitude, longitude)
+        && !holes
+            .iter()
+            .any(|hole| ring_contains(hole, latitude, longitude))
+}
+
+fn polygon_bounds(rings: &[Vec<Vec<f64>>]) -> Bounds {
+    Bounds::of_points(
+        rings
+            .first()
+            .into_iter()
+            .flatten()
+            .map(|vertex| (vertex[1], vertex[0])),
+    )
+}
+
+// Nearest point to `point` on a ring's edges, in a local plane where longitudes are scaled by the cosine of the point's
+// latitude. Returns the point and its squared distance in that plane.
+fn nearest_on_ring(ring: &Ring, latitude: f64, longitude: f64) -> Option<((f64, f64), f64)> {
+    let scale = latitude.to_radians().cos();
+    let project = |vertex: &Vec<f64>| {
+        // unwrap the vertex longitude to the point's side of the antimeridian
+        let longitude_offset = wrap_longitude(vertex[0] - longitude);
+        (longitude_offset * scale, vertex[1] - latitude)
+    };
+
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            let (ax, ay) = project(a);
+            let (bx, by) = project(b);
+            let (dx, dy) = (bx - ax, by - ay);
+            let length_squared = dx * dx + dy * dy;
+            let t = if length_squared > 0.0 {
+                (-(ax * dx + ay * dy) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (x, y) = (ax + t * dx, ay + t * dy);
+            ((x, y), x * x + y * y)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|((x, y), distance)| {
+            let longitude = if scale > 0.0 {
+                longitude + x / scale
+            } else {
+                longitude
+            };
+            ((latitude + y, wrap_longitude(longitude)), distance)
+        })
+}
+
+impl Region {
+    fn polygons(&self) -> Vec<&[Vec<Vec<f64>>]> {
+        match self {
+            Region::Cells { .. } => Vec::new(),
+            Region::Polygon { coordinates } => vec![coordinates.as_slice()],
+            Region::MultiPolygon { coordinates } => coordinates
+                .iter()
+                .map(|polygon| polygon.as_slice())
+                .collect(),
+        }
+    }
+
+    // Check that a polygon region's rings are well formed, since they come from user input: every vertex a finite
+    // [longitude, latitude] pair, every ring with at least MIN_RING_VERTICES distinct vertices
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Region::Cells { cells } = self {
+            if cells.is_empty() {
+                return Err("region has no cells".into());
+            }
+        }
+        let polygons = self.polygons();
+        if polygons.is_empty() && !matches!(self, Region::Cells { .. }) {
+            return Err("region has no polygons".into());
+        }
+        for (i, rings) in polygons.iter().enumerate() {
+            if rings.is_empty() {
+                return Err(format!("region polygon {} has no rings", i).into());
+            }
+            for (j, ring) in rings.iter().enumerate() {
+                if let Some(vertex) = ring.iter().find(|vertex| {
+                    vertex.len() < 2
+                        || !vertex.iter().all(|coordinate| coordinate.is_finite())
+                        || vertex[1].abs() > 90.0
+                }) {
+                    return Err(format!(
+                        "region polygon {} ring {} has an invalid [longitude, latitude] vertex {:?}",
+                        i, j, vertex
+                    )
+                    .into());
+                }
+                // a closed ring repeats its first vertex at the end
+                let n_vertices = if ring.len() > 1 && ring.first() == ring.last() {
+                    ring.len() - 1
+                } else {
+                    ring.len()
+                };
+                if n_vertices < MIN_RING_VERTICES {
+                    return Err(format!(
+                        "region polygon {} ring {} has {} vertices, at least {} are needed",
+                        i, j, n_vertices, MIN_RING_VERTICES
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, lat_lng: LatLng) -> bool {
+        match self {
+            Region::Cells { cells } => cells
+                .iter()
+                .any(|&cell| lat_lng.to_cell(cell.resolution()) == cell),
+            _ => self
+                .polygons()
+                .into_iter()
+                .any(|rings| polygon_contains(rings, lat_lng.lat(), lat_lng.lng())),
+        }
+    }
+
+    pub fn contains_cell(&self, cell: CellIndex) -> bool {
+        self.contains(LatLng::from(cell))
+    }
+
+    // Draw a location uniformly over the region's area
+    pub fn sample(
+        &self,
+        resolution: Resolution,
+        rng: &mut impl Rng,
+    ) -> Result<CellIndex, Box<dyn Error>> {
+        match self {
+            Region::Cells { cells } => {
+                // pick a cell in proportion to its area, then a location inside it
+                let areas = WeightedIndex::new(cells.iter().map(|cell| cell.area_km2()))
+                    .map_err(|e| format!("invalid region cells: {}", e))?;
+                let cell = cells[areas.sample(rng)];
+                let bounds = cell_bounds(cell);
+                for _ in 0..MAX_REGION_ATTEMPTS {
+                    let lat_lng = bounds.sample(rng)?;
+                    if lat_lng.to_cell(cell.resolution()) == cell {
+                        return Ok(lat_lng.to_cell(resolution));
+                    }
+                }
+                // a location the cell certainly contains
+                Ok(LatLng::from(cell).to_cell(resolution))
+            }
+            _ => {
+                let polygons = self.polygons();
+                let bounds: Vec<Bounds> =
+                    polygons.iter().map(|rings| polygon_bounds(rings)).collect();
+                let areas = WeightedIndex::new(bounds.iter().map(Bounds::area))
+                    .map_err(|e| format!("invalid region polygons: {}", e))?;
+                // rejection sampling over the polygons' bounding boxes, restarting from the choice of polygon so the
+                // draw is uniform over their union
+                for _ in 0..MAX_REGION_ATTEMPTS {
+                    let i = areas.sample(rng);
+                    let lat_lng = bounds[i].sample(rng)?;
+                    if polygon_contains(polygons[i], lat_lng.lat(), lat_lng.lng()) {
+                        return Ok(lat_lng.to_cell(resolution));
+                    }
+                }
+                Err("could not draw a location inside the region polygons".into())
+            }
+        }
+    }
+
+    // Redraw a location until it falls inside the region
+    pub fn resample<R: Rng>(
+        &self,
+        rng: &mut R,
+        mut draw: impl FnMut(&mut R) -> Result<CellIndex, Box<dyn Error>>,
+    ) -> Result<CellIndex, Box<dyn Error>> {
+        for attempt in 0..MAX_REGION_ATTEMPTS {
+            let cell = draw(rng)?;
+            if self.contains_cell(cell) {
+                trace!(
+                    "drew a location inside the region after {} attempts",
+                    attempt + 1
+                );
+                return Ok(cell);
+            }
+        }
+        Err(format!(
+            "no location inside the region after {} draws",
+            MAX_REGION_ATTEMPTS
+        )
+        .into())
+    }
+
+    // Nearest point of the region to a location outside it
+    pub fn nearest(&self, lat_lng: LatLng) -> Result<LatLng, Box<dyn Error>> {
+        match self {
+            Region::Cells { cells } => {
+                let (cell, (latitude, longitude)) = cells
+                    .iter()
+                    .filter_map(|&cell| {
+                        let boundary: Vec<Vec<f64>> = cell
+                            .boundary()
+                            .iter()
+                            .map(|vertex| vec![vertex.lng(), vertex.lat()])
+                            .collect();
+                        nearest_on_ring(&boundary, lat_lng.lat(), lat_lng.lng())
+                            .map(|(point, distance)| (cell, point, distance))
+                    })
+                    .min_by(|a, b| a.2.total_cmp(&b.2))
+                    .map(|(cell, point, _)| (cell, point))
+                    .ok_or("empty region")?;
+                // a point on the boundary may round into the neighboring cell, so step slightly towards the center
+                let center = LatLng::from(cell);
+                let longitude_to_center = wrap_longitude(center.lng() - longitude);
+                Ok(LatLng::new(
+                    latitude + BOUNDARY_INSET * (center.lat() - latitude),
+                    wrap_longitude(longitude + BOUNDARY_INSET * longitude_to_center),
+                )?)
+            }
+            _ => {
+                let (latitude, longitude) = self
+                    .polygons()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|ring| nearest_on_ring(ring, lat_lng.lat(), lat_lng.lng()))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .ok_or("empty region")?
+                    .0;
+                Ok(LatLng::new(latitude.clamp(-90.0, 90.0), longitude)?)
+            }
+        }
+    }
+
+    // Move a position estimate outside the region to the region's nearest point on the surface; None if it is inside
+    pub fn constrain(&self, position: ECEF<f64>) -> Result<Option<ECEF<f64>>, Box<dyn Error>> {
+        // fails on non-finite positions, which are left to the estimator's own divergence handling
+        if ecef_to_h3(position, Resolution::Zero).is_err() {
+            return Ok(None);
+        }
+        let wgs84 = WGS84::from(position);
+        let lat_lng = LatLng::from_radians(wgs84.latitude_radians(), wgs84.longitude_radians())?;
+        if self.contains(lat_lng) {
+            return Ok(None);
+        }
+
+        let nearest = self.nearest(lat_lng)?;
+        trace!(
+            "moving an estimate at {} into the region at {}",
+            lat_lng,
+            nearest
+        );
+        Ok(Some(
+            WGS84::from_radians_and_meters(nearest.lat_radians(), nearest.lng_radians(), 0.0)
+                .into(),
+        ))
+    }
+}
+
+// bounding box of a cell's boundary, with longitudes unwrapped around the cell's center
+fn cell_bounds(cell: CellIndex) -> Bounds {
+    let center = LatLng::from(cell);
+    Bounds::of_points(cell.boundary().iter().map(|vertex| {
+        (
+            vertex.lat(),
+            center.lng() + wrap_longitude(vertex.lng() - center.lng()),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // [longitude, latitude] square from (-10, 35) to (30, 60) with a hole from (0, 40) to (10, 50)
+    fn polygon_with_hole() -> Region {
+        let ring = |min: (f64, f64), max: (f64, f64)| {
+            vec![
+                vec![min.0, min.1],
+                vec![max.0, min.1],
+                vec![max.0, max.1],
+                vec![min.0, max.1],
+                vec![min.0, min.1],
+            ]
+        };
+        Region::Polygon {
+            coordinates: vec![
+                ring((-10.0, 35.0), (30.0, 60.0)),
+                ring((0.0, 40.0), (10.0, 50.0)),
+            ],
+        }
+    }
+
+    #[test]
+    fn polygon_contains_excludes_holes() {
+        let region = polygon_with_hole();
+        assert!(region.contains(LatLng::new(55.0, 20.0).unwrap()));
+        assert!(!region.contains(LatLng::new(45.0, 5.0).unwrap()));
+        assert!(!region.contains(LatLng::new(20.0, 5.0).unwrap()));
+    }
+
+    #[test]
+    fn cells_contain_their_descendants() {
+        let cell = LatLng::new(48.86, 2.35).unwrap().to_cell(Resolution::Three);
+        let region = Region::Cells { cells: vec![cell] };
+        assert!(region.contains(LatLng::from(cell)));
+        assert!(region.contains_cell(LatLng::from(cell).to_cell(Resolution::Nine)));
+        assert!(!region.contains(LatLng::new(-33.87, 151.21).unwrap()));
+    }
+
+    #[test]
+    fn validate_rejects_malformed_rings() {
+        assert!(polygon_with_hole().validate().is_ok());
+        let short_vertex = Region::Polygon {
+            coordinates: vec![vec![
+                vec![0.0, 0.0],
+                vec![1.0],
+                vec![1.0, 1.0],
+                vec![0.0, 0.0],
+            ]],
+        };
+        assert!(short_vertex.validate().is_err());
+        let two_vertices = Region::Polygon {
+            coordinates: vec![vec![vec![0.0, 0.0], vec![1.0, 1.0], vec![0.0, 0.0]]],
+        };
+        assert!(two_vertices.validate().is_err());
+        let no_rings = Region::MultiPolygon {
+            coordinates: vec![vec![]],
+        };
+        assert!(no_rings.validate().is_err());
+    }
+
+    #[test]
+    fn nearest_cell_point_is_on_its_boundary() {
+        let cell = LatLng::new(48.86, 2.35).unwrap().to_cell(Resolution::Three);
+        let region = Region::Cells { cells: vec![cell] };
+        let outside = LatLng::new(52.52, 13.40).unwrap();
+        let nearest = region.nearest(outside).unwrap();
+
+        assert!(region.contains(nearest));
+        // closer than the center, and no vertex of the cell is closer (up to the inset)
+        let distance = nearest.distance_rads(outside);
+        assert!(distance < LatLng::from(cell).distance_rads(outside));
+        assert!(cell
+            .boundary()
+            .iter()
+            .all(|vertex| vertex.distance_rads(outside) >= distance * (1.0 - BOUNDARY_INSET)));
+    }
+}
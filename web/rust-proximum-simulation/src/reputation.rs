@@ -0,0 +1,139 @@
+use crate::estimator::PositionEstimator;
+use crate::physics::{Measurements, C};
+use crate::types::{Node, Reputation, SimulationConfig};
+use log::trace;
+use nav_types::ECEF;
+
+// lowest trust a node can get, so that its measurements keep a finite weight
+const MIN_TRUST: f64 = 1e-3;
+
+// variance (s^2) of an honest node's time residuals
+pub fn residual_variance(config: &SimulationConfig) -> f64 {
+    config
+        .reputation_residual_variance
+        .unwrap_or(config.kf_model_tof_observation_variance)
+}
+
+// A node's message speed (fraction of c) and latency (s) under an estimator: its own estimates if it models them, the
+// least squares model's otherwise
+pub fn channel(
+    estimator: &dyn PositionEstimator,
+    index: usize,
+    config: &SimulationConfig,
+) -> (f64, f64) {
+    (
+        estimator.beta(index).unwrap_or(config.ls_model_beta),
+        estimator.tau(index).unwrap_or(config.ls_model_tau),
+    )
+}
+
+// Measured minus predicted round trip time (s) of a node's measurements, with a ping at its channel and a pong at each
+// peer's, all at the estimator's positions
+pub fn time_residuals(
+    index: usize,
+    measurements: &Measurements,
+    estimator: &dyn PositionEstimator,
+    config: &SimulationConfig,
+) -> Vec<f64> {
+    let (their_indices, times) = measurements;
+    let position: ECEF<f64> = estimator.position(index);
+    let (my_beta, my_tau) = channel(estimator, index, config);
+    their_indices
+        .iter()
+        .zip(times.iter())
+        .map(|(&their_index, time)| {
+            let (their_beta, their_tau) = channel(estimator, their_index, config);
+            let distance = config
+                .model_propagation
+                .ecef_path_length(position, estimator.position(their_index));
+            time - (distance / (C * my_beta) + my_tau + distance / (C * their_beta) + their_tau)
+        })
+        .collect()
+}
+
+// A node's trust compares the mean squared residual of the measurements to it with the residual variance of an honest
+// node: a node whose residuals are within it is fully trusted, and trust falls in proportion as its mean squared
+// residual grows beyond it. Untrusted nodes are no longer measured, so their score decays back toward the honest
+// variance every epoch until they are measured again: a node that keeps misbehaving is soon excluded again, one that
+// was unlucky recovers.
+impl Reputation {
+    pub fn new(n_nodes: usize) -> Self {
+        Reputation {
+            mean_squared_residual: vec![None; n_nodes],
+        }
+    }
+
+    // record the time residuals (s) of one node's measurements to its peers
+    pub fn observe(&mut self, their_indices: &[usize], residuals: &[f64], decay: f64) {
+        for (&their_index, &residual) in their_indices.iter().zip(residuals) {
+            let squared_residual = residual * residual;
+            let mean = &mut self.mean_squared_residual[their_index];
+            *mean = Some(match *mean {
+                Some(mean) => decay * mean + (1.0 - decay) * squared_residual,
+                None => squared_residual,
+            });
+        }
+    }
+
+    // update every node's trust from the residuals observed so far, first decaying the scores of the nodes that were
+    // too untrusted to measure
+    pub fn update_trust(&mut self, nodes: &mut [Node], config: &SimulationConfig) {
+        let variance = residual_variance(config);
+        for (node, mean) in nodes.iter_mut().zip(self.mean_squared_residual.iter_mut()) {
+            if let Some(mean) = mean {
+                if node.trust < config.reputation_threshold {
+                    *mean = config.reputation_decay * *mean
+                        + (1.0 - config.reputation_decay) * variance;
+                }
+                node.trust = (variance / *mean).clamp(MIN_TRUST, 1.0);
+                trace!("node {} trust: {}", node.id, node.trust);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use h3o::{LatLng, Resolution};
+
+    fn nodes(n: usize) -> Vec<Node> {
+        let index = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Seven);
+        let position = ECEF::new(6_378_137.0, 0.0, 0.0);
+        (0..n)
+            .map(|id| Node::with_positions(id, index, position, index, position, 0.5, 5e-3, true))
+            .collect()
+    }
+
+    #[test]
+    fn trust_follows_the_mean_squared_residual() {
+        let mut config = example_config();
+        config.reputation_residual_variance = Some(1e-4);
+        config.reputation_decay = 0.5;
+        config.reputation_threshold = 0.2;
+        let mut nodes = nodes(4);
+        let mut reputation = Reputation::new(nodes.len());
+
+        reputation.observe(&[0, 1, 2], &[0.005, 1.0, 0.04], config.reputation_decay);
+        reputation.observe(&[2], &[0.02], config.reputation_decay);
+        assert!((reputation.mean_squared_residual[2].unwrap() - 1e-3).abs() < 1e-15);
+        reputation.update_trust(&mut nodes, &config);
+        let trust: Vec<f64> = nodes.iter().map(|node| node.trust).collect();
+        // within the honest variance, far beyond it (down to the minimum), a tenth of it, and not measured yet
+        assert_eq!(trust[0], 1.0);
+        assert_eq!(trust[1], MIN_TRUST);
+        assert!((trust[2] - 0.1).abs() < 1e-12);
+        assert_eq!(trust[3], 1.0);
+
+        // untrusted nodes aren't measured, so their scores decay back toward the honest variance until they are
+        // trusted again
+        reputation.update_trust(&mut nodes, &config);
+        assert!((nodes[2].trust - 1e-4 / 5.5e-4).abs() < 1e-12);
+        reputation.update_trust(&mut nodes, &config);
+        assert!(nodes[2].trust >= config.reputation_threshold);
+        let recovered = nodes[2].trust;
+        reputation.update_trust(&mut nodes, &config);
+        assert_eq!(nodes[2].trust, recovered);
+    }
+}
@@ -1,60 +1,282 @@
-use crate::geometry::{normal_neighbor_index, uniform_h3_index};
+use crate::dataset::node_measurements;
+use crate::geometry::{ecef_to_h3, h3_to_ecef, lie_index, normal_neighbor_index, sybil_index};
 extern crate nav_types;
-use crate::kalman::{kf_step, NonlinearObservationModel, StationaryStateModel, STATE_FACTOR};
-use crate::least_squares::ls_estimate_position_ecef;
-use crate::physics::generate_measurements;
+use crate::estimator::PositionEstimator;
+use crate::mds::mds_initial_positions;
+use crate::physics::Measurements;
+use crate::placement::placement_index;
+use crate::reputation::time_residuals;
+use crate::sprt::{sprt_hypotheses, sprt_update};
 use crate::stats::log_stats;
-use crate::types::{Node, Simulation, SimulationConfig, Stats};
+use crate::types::{
+    DelayAttack, InitStrategy, Node, Reputation, RttReplay, Simulation, SimulationConfig,
+    SimulationRng, Stats,
+};
 use h3o::Resolution;
 use log::{info, trace, warn};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use rand::Rng;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::collections::BTreeSet;
 use std::error::Error;
 
+// largest seed a JavaScript number holds exactly, so that browser runs can be reproduced from their seed too
+pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+// a random seed for runs that don't set one
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen_range(0..=MAX_SEED)
+}
+
 impl Simulation {
-    pub fn new(config: SimulationConfig) -> Self {
+    pub fn new(mut config: SimulationConfig) -> Result<Self, Box<dyn Error>> {
         trace!("setting up simulation");
         let mut nodes: Vec<Node> = Vec::new();
-        let resolution = Resolution::try_from(config.h3_resolution).expect("invalid H3 resolution");
+        let resolution = Resolution::try_from(config.h3_resolution)
+            .map_err(|e| format!("invalid H3 resolution {}: {}", config.h3_resolution, e))?;
+        // the kalman filters' internal message speed is unbounded only strictly inside (0, 1)
+        if !(config.kf_model_beta > 0.0 && config.kf_model_beta < 1.0) {
+            return Err(format!(
+                "kf_model_beta must be strictly between 0 and 1, got {}",
+                config.kf_model_beta
+            )
+            .into());
+        }
+        // the sequential test divides by the residual variance
+        if let Some(sigma) = config
+            .sprt_residual_sigma
+            .filter(|sigma| !(*sigma > 0.0 && sigma.is_finite()))
+        {
+            return Err(format!("sprt_residual_sigma must be positive, got {}", sigma).into());
+        }
+
+        if let Some(region) = &config.region {
+            region.validate()?;
+        }
+
+        // record the seed actually used so any run can be reproduced from its config
+        let seed = *config.seed.get_or_insert_with(random_seed);
+        info!("simulation seed: {}", seed);
+        let mut rng = SimulationRng::seed_from_u64(seed);
+
+        let mut estimators: Vec<Box<dyn PositionEstimator>> = config
+            .estimators
+            .iter()
+            .map(|kind| kind.build(&config))
+            .collect();
+
+        // imported locations set the number of nodes
+        if let Some(locations) = &config.node_locations {
+            info!("simulating {} imported node locations", locations.len());
+            config.n_nodes = locations.len();
+        }
+
+        // pick which nodes lie about their position
+        let n_dishonest = (config.dishonest_fraction * config.n_nodes as f64).round() as usize;
+        let dishonest: BTreeSet<usize> = if n_dishonest > 0 {
+            (0..config.n_nodes)
+                .choose_multiple(&mut rng, n_dishonest.min(config.n_nodes))
+                .into_iter()
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+
+        // location of the Sybil operator: the true location of the first dishonest node
+        let mut sybil_operator_index = None;
 
         for i in 0..config.n_nodes {
             info!("creating node {}", i);
-            // randomly place a node somewhere on the earth's surface
-            let true_index = uniform_h3_index(resolution);
+            let location = config
+                .node_locations
+                .as_ref()
+                .map(|locations| &locations[i]);
+
+            // place a node at its imported location, or randomly according to the placement strategy
+            let mut true_index = match location {
+                Some(location) => ecef_to_h3(location.true_position(), resolution)?,
+                None => placement_index(
+                    &config.placement,
+                    config.region.as_ref(),
+                    resolution,
+                    &mut rng,
+                )?,
+            };
+
+            // use the imported asserted position, or generate a random one drawn from a gaussian distribution around
+            // the real position
+            let imported_asserted_position =
+                location.and_then(|location| location.asserted_position());
+            // the real position, inside the region if there is one
+            let mut asserted_index = match (imported_asserted_position, &config.region) {
+                (Some(position), _) => ecef_to_h3(position, resolution)?,
+                (None, None) => normal_neighbor_index(
+                    true_index,
+                    config.asserted_position_variance,
+                    resolution,
+                    &mut rng,
+                ),
+                (None, Some(region)) => region.resample(&mut rng, |rng| {
+                    Ok(normal_neighbor_index(
+                        true_index,
+                        config.asserted_position_variance,
+                        resolution,
+                        rng,
+                    ))
+                })?,
+            };
+
+            // dishonest nodes assert a position unrelated to their true position
+            let honest = !dishonest.contains(&i);
+            if !honest {
+                asserted_index = match &config.sybil {
+                    Some(sybil) => {
+                        true_index = *sybil_operator_index.get_or_insert(true_index);
+                        sybil_index(sybil, resolution, &mut rng)?
+                    }
+                    None => lie_index(
+                        true_index,
+                        config.lie_strategy,
+                        config.region.as_ref(),
+                        resolution,
+                        &mut rng,
+                    )?,
+                };
+            }
 
-            // generate a random asserted position drawn from a gaussian distribution around the real position
-            let asserted_index =
-                normal_neighbor_index(true_index, config.asserted_position_variance, resolution);
+            let true_beta = rng.gen_range(config.beta_min..=config.beta_max);
+            let true_tau = rng.gen_range(config.tau_min..=config.tau_max);
 
-            let mut node = Node::new(
+            // imported nodes keep their exact locations, unless their true or asserted cell was replaced above
+            let true_position = match location {
+                Some(location) if honest || config.sybil.is_none() => location.true_position(),
+                _ => h3_to_ecef(true_index),
+            };
+            let asserted_position = match imported_asserted_position {
+                Some(position) if honest => position,
+                _ => h3_to_ecef(asserted_index),
+            };
+
+            let node = Node::with_positions(
                 nodes.len(),
                 true_index,
+                true_position,
                 asserted_index,
-                rand::thread_rng().gen_range(config.beta_min..=config.beta_max),
-                rand::thread_rng().gen_range(config.tau_min..=config.tau_max),
-                config.kf_model_position_variance,
-                config.kf_model_beta,
-                config.kf_model_beta_variance,
-                config.kf_model_tau,
-                config.kf_model_tau_variance,
+                asserted_position,
+                location
+                    .and_then(|location| location.beta)
+                    .unwrap_or(true_beta),
+                location
+                    .and_then(|location| location.tau)
+                    .unwrap_or(true_tau),
+                honest,
             );
 
-            node.log_kf_estimated_positions();
-            node.log_ls_estimated_positions();
-
             // info!("{:#?}", node);
             nodes.push(node);
         }
 
-        Simulation {
+        // dishonest nodes also delay their responses to some of their peers
+        if let Some(strategy) = config.delay_strategy {
+            // every other node can be a victim (none in an empty network)
+            let n_peers = config.n_nodes.saturating_sub(1);
+            let n_victims = (config.delay_victim_fraction * n_peers as f64).round() as usize;
+            for node in nodes.iter_mut().filter(|node| !node.honest) {
+                let victims = (0..config.n_nodes)
+                    .filter(|&i| i != node.id)
+                    .choose_multiple(&mut rng, n_victims.min(n_peers))
+                    .into_iter()
+                    .collect();
+                node.delay_attack = Some(DelayAttack { strategy, victims });
+            }
+        }
+
+        // clocks are loosely synchronized
+        if config.clock_offset_variance > 0.0 {
+            let offset = Normal::new(0.0, config.clock_offset_variance.sqrt())?;
+            for node in nodes.iter_mut() {
+                node.clock_offset = offset.sample(&mut rng);
+            }
+        }
+
+        // measured round trip times between the imported nodes replace simulated measurements
+        let rtt_replay = match &config.rtt_dataset {
+            Some(_) if config.node_locations.is_none() => {
+                return Err("an RTT dataset needs imported node locations".into())
+            }
+            Some(dataset) => {
+                if config.one_way_timestamps {
+                    warn!("RTT datasets have no one-way times: estimators use round trip times");
+                }
+                Some(RttReplay::new(dataset, nodes.len())?)
+            }
+            None => None,
+        };
+
+        if config.init_strategy == InitStrategy::Mds {
+            // collect a few epochs of measurements to place every node before the estimators start
+            let mut measurements = Vec::new();
+            for epoch in 0..config.mds_bootstrap_epochs {
+                for node in &nodes {
+                    match node_measurements(
+                        node.id,
+                        rtt_replay.as_ref(),
+                        epoch,
+                        &nodes,
+                        &config,
+                        &mut rng,
+                    ) {
+                        Ok((node_measurements, _)) => {
+                            measurements.push((node.id, node_measurements))
+                        }
+                        Err(e) => warn!(
+                            "Skipping bootstrap measurements for node {}: {}",
+                            node.id, e
+                        ),
+                    }
+                }
+            }
+
+            match mds_initial_positions(&nodes, &measurements, &config) {
+                Ok(positions) => {
+                    for (node, position) in nodes.iter_mut().zip(positions) {
+                        node.initial_position = position;
+                    }
+                }
+                Err(e) => warn!(
+                    "MDS initialization failed, starting from asserted positions: {}",
+                    e
+                ),
+            }
+        }
+
+        for node in nodes.iter_mut() {
+            for estimator in estimators.iter_mut() {
+                estimator.init(node, &config);
+                node.estimates
+                    .insert(estimator.name().to_string(), estimator.estimate(node.id)?);
+            }
+        }
+
+        let reputation = Reputation::new(nodes.len());
+        let sprt_hypotheses = if config.sprt {
+            nodes
+                .iter()
+                .map(|node| sprt_hypotheses(node, &config))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Ok(Simulation {
             config,
             nodes,
             stats: Stats::new(),
-            kf_state_model: StationaryStateModel::new(1.0, 10.0, 10.0, STATE_FACTOR),
-            kf_observation_model_generator: NonlinearObservationModel::new(),
-            rng: thread_rng(),
-        }
+            rng,
+            estimators,
+            reputation,
+            sprt_hypotheses,
+            rtt_replay,
+        })
     }
 
     pub fn run_epoch(&mut self) -> Result<bool, Box<dyn Error>> {
@@ -62,38 +284,42 @@ impl Simulation {
         let mut indices: Vec<usize> = (0..self.config.n_nodes).collect();
         indices.shuffle(&mut self.rng);
 
+        // this epoch's measurements, kept for the sequential tests
+        let mut epoch_measurements = Vec::new();
+
+        let epoch = self.stats.assertion_rms_error.len();
         for &i in &indices {
-            match generate_measurements(
-                self.nodes[i].true_position,
-                self.nodes[i].true_beta,
-                self.nodes[i].true_tau,
-                Some(i),
+            match node_measurements(
+                i,
+                self.rtt_replay.as_ref(),
+                epoch,
                 &self.nodes,
                 &self.config,
+                &mut self.rng,
             ) {
-                Ok(measurements) => {
-                    if false {
-                        self.nodes[i].kf_state_and_covariance = kf_step(
-                            i,
-                            &measurements,
-                            &mut self.nodes,
-                            &self.kf_observation_model_generator,
-                            &self.kf_state_model,
-                            self.config.kf_model_tof_observation_variance,
-                        );
+                Ok((measurements, ping_times)) => {
+                    // every estimator sees the same measurements, so their errors are directly comparable
+                    for estimator in self.estimators.iter_mut() {
+                        match &ping_times {
+                            Some(ping_times) => estimator.update_timed(
+                                i,
+                                &measurements,
+                                ping_times,
+                                &self.nodes,
+                                &self.config,
+                            )?,
+                            None => {
+                                estimator.update(i, &measurements, &self.nodes, &self.config)?
+                            }
+                        }
+                    }
+                    self.constrain_estimates(i)?;
+                    if self.config.reputation {
+                        self.observe_residuals(i, &measurements);
+                    }
+                    if self.config.sprt {
+                        epoch_measurements.push((i, measurements));
                     }
-                    self.nodes[i].log_kf_estimated_positions();
-
-                    self.nodes[i].ls_estimated_position = ls_estimate_position_ecef(
-                        self.nodes[i].ls_estimated_position,
-                        self.nodes[i].asserted_position,
-                        self.nodes[i].true_position,
-                        &measurements,
-                        &self.nodes,
-                        &self.config,
-                    )?;
-
-                    self.nodes[i].log_ls_estimated_positions();
                 }
                 Err(e) => {
                     warn!("Skipping update for node {}: {}", i, e);
@@ -102,9 +328,202 @@ impl Simulation {
             }
         }
 
-        log_stats(&mut self.stats, &self.nodes);
+        if self.config.reputation {
+            self.reputation.update_trust(&mut self.nodes, &self.config);
+        }
+
+        for estimator in self.estimators.iter_mut() {
+            estimator.end_epoch(&self.nodes, &self.config)?;
+        }
+        // estimators solving for the whole network move estimates at the end of the epoch
+        for i in 0..self.nodes.len() {
+            self.constrain_estimates(i)?;
+        }
+
+        for estimator in self.estimators.iter_mut() {
+            for node in self.nodes.iter_mut() {
+                node.estimates
+                    .insert(estimator.name().to_string(), estimator.estimate(node.id)?);
+            }
+        }
+
+        if self.config.sprt {
+            if let Some(estimator) = self.estimators.first() {
+                let epoch = self.stats.assertion_rms_error.len() + 1;
+                sprt_update(
+                    &mut self.nodes,
+                    &mut self.sprt_hypotheses,
+                    &epoch_measurements,
+                    estimator.as_ref(),
+                    epoch,
+                    &self.config,
+                );
+            }
+        }
+
+        log_stats(&mut self.stats, &self.nodes, &self.estimators, &self.config);
 
         // info!("Finished epoch");
         Ok(true)
     }
+
+    // move a node's estimates that left the region back to its nearest point, if configured
+    fn constrain_estimates(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        let Some(region) = self
+            .config
+            .region
+            .as_ref()
+            .filter(|_| self.config.region_constrain_estimates)
+        else {
+            return Ok(());
+        };
+        for estimator in self.estimators.iter_mut() {
+            if let Some(position) = region.constrain(estimator.position(index))? {
+                estimator.set_position(index, position);
+            }
+        }
+        Ok(())
+    }
+
+    // attribute the residuals of a node's measurements, at the first estimator's updated positions, to its peers
+    fn observe_residuals(&mut self, index: usize, measurements: &Measurements) {
+        let Some(estimator) = self.estimators.first() else {
+            return;
+        };
+        let residuals = time_residuals(index, measurements, estimator.as_ref(), &self.config);
+        self.reputation
+            .observe(&measurements.0, &residuals, self.config.reputation_decay);
+    }
+
+    // run the simulation for the configured number of epochs
+    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        for epoch in 0..self.config.n_epochs {
+            trace!("running epoch {}", epoch);
+            self.run_epoch()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::{DelayStrategy, EstimatorKind, LieStrategy, SybilAttack};
+    use nav_types::{ECEF, WGS84};
+
+    // nodes and stats of a short run with every estimator, as JSON
+    fn run(seed: Option<u64>) -> (u64, String) {
+        let mut config = example_config();
+        config.n_nodes = 15;
+        config.n_epochs = 5;
+        config.seed = seed;
+        config.sprt = true;
+        config.estimators = vec![
+            EstimatorKind::Ls,
+            EstimatorKind::Ekf,
+            EstimatorKind::Ukf,
+            EstimatorKind::Pf,
+            EstimatorKind::Joint,
+            EstimatorKind::Ransac,
+        ];
+        let mut simulation = Simulation::new(config).unwrap();
+        simulation.run().unwrap();
+        let output = serde_json::to_string(&(&simulation.nodes, &simulation.stats)).unwrap();
+        (simulation.config.seed.unwrap(), output)
+    }
+
+    #[test]
+    fn runs_are_reproducible_from_their_seed() {
+        assert_eq!(run(Some(7)), run(Some(7)));
+        assert_ne!(run(Some(7)).1, run(Some(8)).1);
+        // an unseeded run records the seed it drew, which reproduces it
+        let (seed, output) = run(None);
+        assert_eq!(run(Some(seed)).1, output);
+    }
+
+    #[test]
+    fn dishonest_nodes_assert_their_lie() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.n_epochs = 2;
+        config.asserted_position_variance = 1e8;
+        config.dishonest_fraction = 0.2;
+        config.lie_strategy = LieStrategy::Offset {
+            distance: 1e6,
+            bearing: 90.0,
+        };
+        config.estimators = vec![EstimatorKind::Ekf];
+        let mut simulation = Simulation::new(config).unwrap();
+        simulation.run().unwrap();
+
+        let (honest, dishonest): (Vec<&Node>, Vec<&Node>) =
+            simulation.nodes.iter().partition(|node| node.honest);
+        assert_eq!(dishonest.len(), 6);
+        for node in dishonest {
+            let lie = node.true_position.distance(&node.asserted_position);
+            assert!(
+                (lie - 1e6).abs() < 1e4,
+                "node {} lies by {} m",
+                node.id,
+                lie
+            );
+        }
+        for node in honest {
+            assert!(node.true_position.distance(&node.asserted_position) < 1e5);
+        }
+
+        // errors are broken down by honesty
+        let stats = &simulation.stats;
+        assert_eq!(stats.dishonest_assertion_rms_error.len(), 2);
+        assert!(stats.dishonest_assertion_rms_error[0] > stats.honest_assertion_rms_error[0]);
+        assert!(stats.dishonest_estimation_rms_error.contains_key("ekf"));
+    }
+
+    #[test]
+    fn delay_attackers_pick_their_victims() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.n_epochs = 1;
+        config.dishonest_fraction = 0.2;
+        config.delay_strategy = Some(DelayStrategy::Targeted);
+        config.delay_victim_fraction = 0.5;
+        let simulation = Simulation::new(config).unwrap();
+
+        for node in &simulation.nodes {
+            match &node.delay_attack {
+                Some(attack) => {
+                    assert!(!node.honest);
+                    assert_eq!(attack.victims.len(), 15);
+                    assert!(!attack.victims.contains(&node.id));
+                }
+                None => assert!(node.honest),
+            }
+        }
+    }
+
+    #[test]
+    fn sybil_nodes_share_the_operator_location() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.dishonest_fraction = 0.2;
+        config.sybil = Some(SybilAttack {
+            latitude: 48.86,
+            longitude: 2.35,
+            radius: 1e5,
+        });
+        let simulation = Simulation::new(config).unwrap();
+
+        let sybils: Vec<&Node> = simulation
+            .nodes
+            .iter()
+            .filter(|node| !node.honest)
+            .collect();
+        assert_eq!(sybils.len(), 6);
+        let paris: ECEF<f64> = WGS84::from_degrees_and_meters(48.86, 2.35, 0.0).into();
+        for node in &sybils {
+            assert_eq!(node.true_index, sybils[0].true_index);
+            assert!(node.asserted_position.distance(&paris) < 1.02e5);
+        }
+    }
 }
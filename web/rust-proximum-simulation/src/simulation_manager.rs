@@ -1,8 +1,6 @@
 // #![allow(non_snake_case)]
-use crate::{
-    kalman::N_MEASUREMENTS,
-    types::{ChunkResult, CompilerParams, Simulation, SimulationConfig},
-};
+use crate::import::{parse_node_locations, parse_rtt_dataset};
+use crate::types::{ChunkResult, Simulation, SimulationConfig};
 use console_log::init_with_level;
 use log::{info, LevelFilter};
 // use serde_json;
@@ -27,15 +25,6 @@ use log::{info, LevelFilter};
 use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-pub fn get_compile_parameters() -> String {
-    let parameters = CompilerParams {
-        n_measurements: N_MEASUREMENTS,
-    };
-    let json = serde_json::to_string(&parameters).unwrap();
-    json
-}
-
 pub fn init_logger() {
     // ;
     if log::max_level() == LevelFilter::Off {
@@ -44,14 +33,14 @@ pub fn init_logger() {
 }
 
 thread_local! {
-    static SIMULATION: RefCell<Option<Simulation>> = RefCell::new(None);
+    static SIMULATION: RefCell<Option<Simulation>> = const { RefCell::new(None) };
 }
 
 #[wasm_bindgen]
 pub fn initialize_simulation(config: JsValue) -> Result<(), JsValue> {
     init_logger();
     let config: SimulationConfig = serde_wasm_bindgen::from_value(config)?;
-    let simulation = Simulation::new(config);
+    let simulation = Simulation::new(config).map_err(|e| e.to_string())?;
     SIMULATION.with(|sim| {
         *sim.borrow_mut() = Some(simulation);
     });
@@ -60,6 +49,43 @@ pub fn initialize_simulation(config: JsValue) -> Result<(), JsValue> {
     Ok(())
 }
 
+// Initialize a simulation of imported nodes: `nodes` is the contents of a CSV or GeoJSON file of node locations
+#[wasm_bindgen]
+pub fn initialize_simulation_with_nodes(config: JsValue, nodes: &str) -> Result<(), JsValue> {
+    init_logger();
+    let mut config: SimulationConfig = serde_wasm_bindgen::from_value(config)?;
+    config.node_locations = Some(parse_node_locations(nodes).map_err(|e| e.to_string())?);
+    let simulation = Simulation::new(config).map_err(|e| e.to_string())?;
+    SIMULATION.with(|sim| {
+        *sim.borrow_mut() = Some(simulation);
+    });
+
+    info!("initialized simulation with imported nodes");
+    Ok(())
+}
+
+// Initialize a simulation of imported nodes replaying measured round trip times: `rtts` is the contents of a CSV or
+// JSON dataset of RTTs between them
+#[wasm_bindgen]
+pub fn initialize_simulation_with_rtts(
+    config: JsValue,
+    nodes: &str,
+    rtts: &str,
+) -> Result<(), JsValue> {
+    init_logger();
+    let mut config: SimulationConfig = serde_wasm_bindgen::from_value(config)?;
+    let locations = parse_node_locations(nodes).map_err(|e| e.to_string())?;
+    config.rtt_dataset = Some(parse_rtt_dataset(rtts, &locations).map_err(|e| e.to_string())?);
+    config.node_locations = Some(locations);
+    let simulation = Simulation::new(config).map_err(|e| e.to_string())?;
+    SIMULATION.with(|sim| {
+        *sim.borrow_mut() = Some(simulation);
+    });
+
+    info!("initialized simulation replaying measured RTTs");
+    Ok(())
+}
+
 #[wasm_bindgen]
 pub fn run_simulation_chunk(epochs: u32) -> Result<JsValue, JsValue> {
     SIMULATION.with(|sim| {
@@ -82,6 +108,7 @@ pub fn run_simulation_chunk(epochs: u32) -> Result<JsValue, JsValue> {
         let chunk_result = ChunkResult {
             nodes: simulation.nodes.clone(),
             stats: simulation.stats.clone(),
+            seed: simulation.config.seed.unwrap_or_default(),
         };
 
         // info!("chunk result: {:#?}", chunk_result);
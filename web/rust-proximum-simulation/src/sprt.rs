@@ -0,0 +1,306 @@
+use crate::detection::{assertion_covariance, detection_distance};
+use crate::estimator::PositionEstimator;
+use crate::geometry::ecef_to_enu_rotation;
+use crate::physics::{Measurements, C};
+use crate::reputation::{channel, time_residuals};
+use crate::types::{Node, SimulationConfig, SprtDecision, SprtHypothesis, SprtOutcome, Stats};
+use log::debug;
+use nalgebra::{Matrix2x3, Matrix3, Matrix4, Vector3, Vector4};
+use nav_types::{ECEF, WGS84};
+use std::f64::consts::PI;
+
+// number of alternative positions around each assertion, at evenly spaced bearings
+const N_ALTERNATIVES: usize = 8;
+
+// median of a chi-square variable with one degree of freedom, to estimate a variance from squared residuals robustly
+const CHI_SQUARE_1_MEDIAN: f64 = 0.4549;
+
+fn position_vector(position: ECEF<f64>) -> Vector3<f64> {
+    Vector3::new(position.x(), position.y(), position.z())
+}
+
+// Wald's thresholds on the log-likelihood ratio for the configured error rates: (accept below, reject above)
+pub fn sprt_thresholds(config: &SimulationConfig) -> (f64, f64) {
+    let alpha = config.sprt_false_accusation_rate;
+    let beta = config.sprt_missed_detection_rate;
+    ((beta / (1.0 - alpha)).ln(), ((1.0 - beta) / alpha).ln())
+}
+
+// The hypotheses of a node's test, fixed before any measurement: its assertion, and N_ALTERNATIVES points
+// `detection_distance` away from it (kept at the assertion's distance from the earth's center). Under each, the node
+// is off the center by the assertion's spread, and its message speed and latency are anywhere in their ranges.
+pub fn sprt_hypotheses(node: &Node, config: &SimulationConfig) -> Vec<SprtHypothesis> {
+    let asserted = position_vector(node.asserted_position);
+    let distance = detection_distance(config);
+    let to_ecef = ecef_to_enu_rotation(node.asserted_wgs84).transpose();
+
+    // gaussian with the mean and variance of a uniform distribution over [min, max]
+    let uniform = |min: f64, max: f64| ((min + max) / 2.0, (max - min).powi(2) / 12.0);
+    let (inverse_speed, inverse_speed_variance) =
+        uniform(1.0 / (C * config.beta_max), 1.0 / (C * config.beta_min));
+    let (tau, tau_variance) = uniform(config.tau_min, config.tau_max);
+    let hypothesis = |center: Vector3<f64>| SprtHypothesis {
+        center,
+        state: Vector4::new(0.0, 0.0, inverse_speed, tau),
+        covariance: Matrix4::from_diagonal(&Vector4::new(
+            config.asserted_position_variance,
+            config.asserted_position_variance,
+            inverse_speed_variance,
+            tau_variance,
+        )),
+        log_likelihood: 0.0,
+    };
+
+    let mut hypotheses = vec![hypothesis(asserted)];
+    for k in 0..N_ALTERNATIVES {
+        let bearing = 2.0 * PI * k as f64 / N_ALTERNATIVES as f64;
+        let displacement = to_ecef * Vector3::new(bearing.sin(), bearing.cos(), 0.0) * distance;
+        hypotheses.push(hypothesis(
+            (asserted + displacement).normalize() * asserted.norm(),
+        ));
+    }
+    hypotheses
+}
+
+impl SprtHypothesis {
+    // rows of the ECEF to East-North rotation at the center
+    fn east_north(&self) -> Matrix2x3<f64> {
+        let center = ECEF::new(self.center[0], self.center[1], self.center[2]);
+        ecef_to_enu_rotation(WGS84::from(center))
+            .fixed_rows::<2>(0)
+            .into()
+    }
+
+    // Add one round trip time between the node and a peer, whose position estimate has an ECEF covariance (m^2) and
+    // whose message speed and latency are the estimator's: the node's state is updated by a Kalman filter step
+    // linearized at its current estimate, and the measurement's predictive log-likelihood is accumulated
+    fn observe(
+        &mut self,
+        time: f64,
+        other: &Vector3<f64>,
+        other_covariance: &Matrix3<f64>,
+        (their_beta, their_tau): (f64, f64),
+        residual_variance: f64,
+        config: &SimulationConfig,
+    ) {
+        let east_north = self.east_north();
+        let offset = self.state.fixed_rows::<2>(0);
+        let position =
+            (self.center + east_north.transpose() * offset).normalize() * self.center.norm();
+
+        let distance = config.model_propagation.path_length(&position, other);
+        let inverse_speed = self.state[2] + 1.0 / (C * their_beta);
+        let residual = time - (distance * inverse_speed + self.state[3] + their_tau);
+
+        // sensitivity of the time to the node's state and to the peer's position
+        let position_gradient =
+            east_north * config.model_propagation.path_gradient(&position, other) * inverse_speed;
+        let h = Vector4::new(position_gradient[0], position_gradient[1], distance, 1.0);
+        let g = config.model_propagation.path_gradient(other, &position) * inverse_speed;
+        let variance = residual_variance
+            + (g.transpose() * other_covariance * g)[0]
+            + (h.transpose() * self.covariance * h)[0];
+
+        let gain = self.covariance * h / variance;
+        self.state += gain * residual;
+        self.covariance -= gain * h.transpose() * self.covariance;
+        self.covariance = (self.covariance + self.covariance.transpose()) / 2.0;
+        self.log_likelihood -= 0.5 * (residual.powi(2) / variance + (2.0 * PI * variance).ln());
+    }
+}
+
+// Log-likelihood ratio of a node's measurements between the alternatives (an equally weighted mixture) and its
+// assertion
+fn log_likelihood_ratio(hypotheses: &[SprtHypothesis]) -> f64 {
+    let (asserted, alternatives) = hypotheses.split_first().unwrap();
+    let max = alternatives
+        .iter()
+        .map(|hypothesis| hypothesis.log_likelihood)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let mean = alternatives
+        .iter()
+        .map(|hypothesis| (hypothesis.log_likelihood - max).exp())
+        .sum::<f64>()
+        / alternatives.len() as f64;
+    max + mean.ln() - asserted.log_likelihood
+}
+
+// Variance (s^2) of a round trip time around the model: configured, or estimated from the median squared residual of
+// the epoch's measurements at the estimator's positions, which a minority of dishonest nodes barely moves
+fn residual_variance(
+    epoch_measurements: &[(usize, Measurements)],
+    estimator: &dyn PositionEstimator,
+    config: &SimulationConfig,
+) -> Option<f64> {
+    if let Some(sigma) = config.sprt_residual_sigma {
+        return Some(sigma.powi(2));
+    }
+    let mut squared_residuals: Vec<f64> = epoch_measurements
+        .iter()
+        .flat_map(|(index, measurements)| time_residuals(*index, measurements, estimator, config))
+        .map(|residual| residual.powi(2))
+        .collect();
+    if squared_residuals.is_empty() {
+        return None;
+    }
+    let middle = squared_residuals.len() / 2;
+    let (_, median, _) = squared_residuals.select_nth_unstable_by(middle, f64::total_cmp);
+    Some(*median / CHI_SQUARE_1_MEDIAN)
+}
+
+// Add one epoch of evidence to every undecided node's sequential test: all measurements it made or that were made to
+// it, with the other ends at the estimator's positions and covariances (an assertion's, for estimators without one).
+// Decides the nodes whose ratio crosses a threshold.
+pub fn sprt_update(
+    nodes: &mut [Node],
+    hypotheses: &mut [Vec<SprtHypothesis>],
+    epoch_measurements: &[(usize, Measurements)],
+    estimator: &dyn PositionEstimator,
+    epoch: usize,
+    config: &SimulationConfig,
+) {
+    let Some(residual_variance) = residual_variance(epoch_measurements, estimator, config) else {
+        return;
+    };
+    debug!(
+        "sequential test residual sigma: {} s",
+        residual_variance.sqrt()
+    );
+    let positions: Vec<Vector3<f64>> = (0..nodes.len())
+        .map(|index| position_vector(estimator.position(index)))
+        .collect();
+    let covariances: Vec<Matrix3<f64>> = nodes
+        .iter()
+        .map(|node| {
+            estimator
+                .covariance(node.id)
+                .unwrap_or_else(|| assertion_covariance(node, config))
+        })
+        .collect();
+    // a filter's message speed and latency can stray outside their physical ranges while it settles
+    let channels: Vec<(f64, f64)> = (0..nodes.len())
+        .map(|index| {
+            let (beta, tau) = channel(estimator, index, config);
+            (
+                beta.clamp(config.beta_min, config.beta_max),
+                tau.clamp(config.tau_min, config.tau_max),
+            )
+        })
+        .collect();
+
+    for (my_index, (their_indices, times)) in epoch_measurements {
+        for (&their_index, &time) in their_indices.iter().zip(times.iter()) {
+            // a round trip tests both of its ends
+            for (tested, other) in [(*my_index, their_index), (their_index, *my_index)] {
+                if nodes[tested].sprt_decision.is_some() {
+                    continue;
+                }
+                for hypothesis in hypotheses[tested].iter_mut() {
+                    hypothesis.observe(
+                        time,
+                        &positions[other],
+                        &covariances[other],
+                        channels[other],
+                        residual_variance,
+                        config,
+                    );
+                }
+            }
+        }
+    }
+
+    let (accept_below, reject_above) = sprt_thresholds(config);
+    for (node, hypotheses) in nodes.iter_mut().zip(hypotheses.iter()) {
+        if node.sprt_decision.is_some() {
+            continue;
+        }
+        node.sprt_log_likelihood_ratio = log_likelihood_ratio(hypotheses);
+        let outcome = if node.sprt_log_likelihood_ratio >= reject_above {
+            SprtOutcome::Rejected
+        } else if node.sprt_log_likelihood_ratio <= accept_below {
+            SprtOutcome::Accepted
+        } else {
+            continue;
+        };
+        debug!(
+            "node {} assertion {:?} at epoch {}",
+            node.id, outcome, epoch
+        );
+        node.sprt_decision = Some(SprtDecision { epoch, outcome });
+    }
+}
+
+// Record the sequential test outcomes so far. The false positive rate is also recorded when every node is honest, to
+// check it against `sprt_false_accusation_rate`.
+pub fn log_sprt(stats: &mut Stats, nodes: &[Node]) {
+    let n_dishonest = nodes.iter().filter(|node| !node.honest).count();
+    if n_dishonest == nodes.len() {
+        return;
+    }
+    let n_honest = nodes.len() - n_dishonest;
+
+    let rejected = |honest: bool| {
+        nodes
+            .iter()
+            .filter(|node| {
+                node.honest == honest
+                    && node
+                        .sprt_decision
+                        .is_some_and(|decision| decision.outcome == SprtOutcome::Rejected)
+            })
+            .count() as f64
+    };
+    let n_decided = nodes
+        .iter()
+        .filter(|node| node.sprt_decision.is_some())
+        .count();
+
+    if n_dishonest > 0 {
+        stats
+            .sprt_true_positive_rate
+            .push(rejected(false) / n_dishonest as f64);
+    }
+    stats
+        .sprt_false_positive_rate
+        .push(rejected(true) / n_honest as f64);
+    stats
+        .sprt_decided_fraction
+        .push(n_decided as f64 / nodes.len() as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::{EstimatorKind, Simulation};
+
+    #[test]
+    fn thresholds_follow_the_error_rates() {
+        let mut config = example_config();
+        config.sprt_false_accusation_rate = 0.01;
+        config.sprt_missed_detection_rate = 0.01;
+        let (accept_below, reject_above) = sprt_thresholds(&config);
+        assert!((reject_above - 99f64.ln()).abs() < 1e-12);
+        assert!((accept_below + 99f64.ln()).abs() < 1e-12);
+    }
+
+    // on an all-honest network, about `sprt_false_accusation_rate` of the nodes are rejected
+    #[test]
+    fn false_accusation_rate_is_calibrated() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.n_epochs = 20;
+        config.sprt = true;
+        config.estimators = vec![EstimatorKind::Ekf];
+        let mut simulation = Simulation::new(config.clone()).unwrap();
+        simulation.run().unwrap();
+
+        let rate = *simulation.stats.sprt_false_positive_rate.last().unwrap();
+        assert!(
+            rate <= 5.0 * config.sprt_false_accusation_rate,
+            "false accusation rate {}",
+            rate
+        );
+        assert!(simulation.stats.sprt_true_positive_rate.is_empty());
+    }
+}
@@ -1,62 +1,213 @@
-use crate::types::{Node, PositionType, Stats};
+use crate::detection::{detection_distance, log_detection};
+use crate::estimator::PositionEstimator;
+use crate::sprt::log_sprt;
+use crate::types::{Node, PositionType, SimulationConfig, Stats};
 use log::trace;
+use std::collections::BTreeMap;
 
 impl Stats {
     pub fn new() -> Self {
         Stats {
-            kf_estimation_rms_error: Vec::new(),
-            ls_estimation_rms_error: Vec::new(),
+            estimation_rms_error: BTreeMap::new(),
             assertion_rms_error: Vec::new(),
+            estimator_resets: BTreeMap::new(),
+            honest_estimation_rms_error: BTreeMap::new(),
+            dishonest_estimation_rms_error: BTreeMap::new(),
+            honest_assertion_rms_error: Vec::new(),
+            dishonest_assertion_rms_error: Vec::new(),
+            dishonest_undetected_fraction: BTreeMap::new(),
+            detection_true_positive_rate: BTreeMap::new(),
+            detection_false_positive_rate: BTreeMap::new(),
+            detection_auc: BTreeMap::new(),
+            detection_roc: BTreeMap::new(),
+            first_detection_epoch: BTreeMap::new(),
+            detection_latency: BTreeMap::new(),
+            sprt_true_positive_rate: Vec::new(),
+            sprt_false_positive_rate: Vec::new(),
+            sprt_decided_fraction: Vec::new(),
         }
     }
+
+    // every per-epoch metric with its column name: estimation errors (in estimator name order), assertion error, resets,
+    // then the honest / dishonest breakdown and detection rates when the simulation has dishonest nodes
+    pub fn metrics(&self) -> Vec<(String, &Vec<f64>)> {
+        let mut metrics: Vec<(String, &Vec<f64>)> = self
+            .estimation_rms_error
+            .iter()
+            .map(|(name, errors)| (format!("{}_estimation_rms_error", name), errors))
+            .collect();
+        metrics.push(("assertion_rms_error".to_string(), &self.assertion_rms_error));
+        metrics.extend(
+            self.estimator_resets
+                .iter()
+                .map(|(name, resets)| (format!("{}_resets", name), resets)),
+        );
+        for (group, estimation, assertion) in [
+            (
+                "honest",
+                &self.honest_estimation_rms_error,
+                &self.honest_assertion_rms_error,
+            ),
+            (
+                "dishonest",
+                &self.dishonest_estimation_rms_error,
+                &self.dishonest_assertion_rms_error,
+            ),
+        ] {
+            metrics.extend(estimation.iter().map(|(name, errors)| {
+                (format!("{}_{}_estimation_rms_error", group, name), errors)
+            }));
+            if !assertion.is_empty() {
+                metrics.push((format!("{}_assertion_rms_error", group), assertion));
+            }
+        }
+        metrics.extend(
+            self.dishonest_undetected_fraction
+                .iter()
+                .map(|(name, fraction)| {
+                    (format!("dishonest_{}_undetected_fraction", name), fraction)
+                }),
+        );
+        for (metric, values) in [
+            (
+                "detection_true_positive_rate",
+                &self.detection_true_positive_rate,
+            ),
+            (
+                "detection_false_positive_rate",
+                &self.detection_false_positive_rate,
+            ),
+            ("detection_auc", &self.detection_auc),
+        ] {
+            metrics.extend(
+                values
+                    .iter()
+                    .map(|(name, rates)| (format!("{}_{}", name, metric), rates)),
+            );
+        }
+        for (metric, values) in [
+            ("sprt_true_positive_rate", &self.sprt_true_positive_rate),
+            ("sprt_false_positive_rate", &self.sprt_false_positive_rate),
+            ("sprt_decided_fraction", &self.sprt_decided_fraction),
+        ] {
+            if !values.is_empty() {
+                metrics.push((metric.to_string(), values));
+            }
+        }
+        metrics
+    }
 }
 
-fn calculate_rms_error(nodes: &[Node], position_type: PositionType) -> f64 {
+fn calculate_rms_error<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    position_type: PositionType,
+) -> f64 {
     let mut squared_diff_sum = 0.0;
+    let mut n_nodes = 0;
 
     for node in nodes {
-        let diff = match position_type {
-            PositionType::KfEstimated => node.true_position - node.kf_estimated_position,
-            PositionType::LsEstimated => node.true_position - node.ls_estimated_position,
-            PositionType::Asserted => node.true_position - node.asserted_position,
+        n_nodes += 1;
+        let position = match position_type {
+            PositionType::Estimated(name) => node.estimates[name].position,
+            PositionType::Asserted => node.asserted_position,
         };
+        let diff = node.true_position - position;
         // let squared_diff = diff.norm().powi(2);
         // Only consider the E, N coordinates.
         let squared_diff = diff.east().powi(2) + diff.north().powi(2);
         squared_diff_sum += squared_diff;
 
-        if position_type == PositionType::KfEstimated {
-            trace!(
-              "node true position: {:#?}, asserted pos: {:#?}, est position: {:#?}, squared diff: {}",
-              node.true_position,node.asserted_position, node.kf_estimated_position, squared_diff
-          );
-        }
-
-        if position_type == PositionType::LsEstimated {
+        if let PositionType::Estimated(name) = position_type {
             trace!(
-            "node true position: {:#?}, asserted pos: {:#?}, est position: {:#?}, squared diff: {}",
-            node.true_position,node.asserted_position, node.ls_estimated_position, squared_diff
-        );
+                "{} node true position: {:#?}, asserted pos: {:#?}, est position: {:#?}, squared diff: {}",
+                name, node.true_position, node.asserted_position, position, squared_diff
+            );
         }
     }
 
-    let rms_error = squared_diff_sum / nodes.len() as f64;
-    let rms_error = rms_error.sqrt();
-
-    rms_error
+    let rms_error = squared_diff_sum / n_nodes as f64;
+    rms_error.sqrt()
 }
 
-pub fn log_stats(stats: &mut Stats, nodes: &Vec<Node>) {
-    stats
-        .kf_estimation_rms_error
-        .push(calculate_rms_error(&nodes, PositionType::KfEstimated));
+pub fn log_stats(
+    stats: &mut Stats,
+    nodes: &[Node],
+    estimators: &[Box<dyn PositionEstimator>],
+    config: &SimulationConfig,
+) {
+    for estimator in estimators {
+        let name = estimator.name();
+        stats
+            .estimation_rms_error
+            .entry(name.to_string())
+            .or_default()
+            .push(calculate_rms_error(nodes, PositionType::Estimated(name)));
 
-    // Push the initial stats
-    stats
-        .ls_estimation_rms_error
-        .push(calculate_rms_error(&nodes, PositionType::LsEstimated));
+        if let Some(resets) = estimator.resets() {
+            stats
+                .estimator_resets
+                .entry(name.to_string())
+                .or_default()
+                .push(resets as f64);
+        }
+    }
 
     stats
         .assertion_rms_error
-        .push(calculate_rms_error(&nodes, PositionType::Asserted));
+        .push(calculate_rms_error(nodes, PositionType::Asserted));
+
+    log_detection(stats, nodes, estimators, config);
+    if config.sprt {
+        log_sprt(stats, nodes);
+    }
+
+    // break the errors down by honesty, only when both groups exist
+    let n_honest = nodes.iter().filter(|node| node.honest).count();
+    if n_honest == 0 || n_honest == nodes.len() {
+        return;
+    }
+    for (honest, estimation, assertion) in [
+        (
+            true,
+            &mut stats.honest_estimation_rms_error,
+            &mut stats.honest_assertion_rms_error,
+        ),
+        (
+            false,
+            &mut stats.dishonest_estimation_rms_error,
+            &mut stats.dishonest_assertion_rms_error,
+        ),
+    ] {
+        let group = || nodes.iter().filter(move |node| node.honest == honest);
+        for estimator in estimators {
+            let name = estimator.name();
+            estimation
+                .entry(name.to_string())
+                .or_default()
+                .push(calculate_rms_error(group(), PositionType::Estimated(name)));
+        }
+        assertion.push(calculate_rms_error(group(), PositionType::Asserted));
+    }
+
+    // a dishonest node goes unnoticed while its estimate agrees with its assertion
+    let detection_distance = detection_distance(config);
+    let n_dishonest = nodes.len() - n_honest;
+    for estimator in estimators {
+        let name = estimator.name();
+        let n_undetected = nodes
+            .iter()
+            .filter(|node| {
+                !node.honest
+                    && node.estimates[name]
+                        .position
+                        .distance(&node.asserted_position)
+                        <= detection_distance
+            })
+            .count();
+        stats
+            .dishonest_undetected_fraction
+            .entry(name.to_string())
+            .or_default()
+            .push(n_undetected as f64 / n_dishonest as f64);
+    }
 }
@@ -0,0 +1,247 @@
+use crate::batch::run_simulations;
+use crate::simulation::random_seed;
+use crate::types::{SimulationConfig, SweepAxis, SweepSpec, SweepTable};
+use log::info;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+// Simulate every combination of the swept values starting from the base config.
+// Each variant is an ordinary SimulationConfig, so results match a single run of that config.
+pub fn run_sweep(
+    base: &SimulationConfig,
+    spec: &SweepSpec,
+    parallel: bool,
+    final_only: bool,
+) -> Result<SweepTable, Box<dyn Error>> {
+    if spec.replicas == 0 {
+        return Err("a sweep needs at least one replica".into());
+    }
+
+    let axis_values = spec
+        .axes
+        .iter()
+        .map(SweepAxis::values)
+        .collect::<Result<Vec<_>, _>>()?;
+    let points = cartesian_product(&axis_values);
+
+    let base_seed = base.seed.unwrap_or_else(random_seed);
+    info!(
+        "sweeping {} combinations x {} replicas from seed {}",
+        points.len(),
+        spec.replicas,
+        base_seed
+    );
+
+    let mut jobs = Vec::with_capacity(points.len() * spec.replicas);
+    let mut applied_points = Vec::with_capacity(points.len());
+    for point in &points {
+        let (config, applied_point) = apply_point(base, &spec.axes, point)?;
+        applied_points.push(applied_point);
+        for replica in 0..spec.replicas as u64 {
+            jobs.push((config.clone(), base_seed.wrapping_add(replica)));
+        }
+    }
+
+    let stats = run_simulations(&jobs, parallel)?;
+
+    let mut table = SweepTable {
+        columns: spec.axes.iter().map(|axis| axis.field.clone()).collect(),
+        rows: Vec::new(),
+    };
+    table
+        .columns
+        .extend(["replica", "seed", "epoch"].map(String::from));
+
+    // the estimators (and so the metrics) may themselves be swept, so report the union of all metrics
+    let metric_names: BTreeSet<String> = stats
+        .iter()
+        .flat_map(|stats| stats.metrics().into_iter().map(|(name, _)| name))
+        .collect();
+    table.columns.extend(metric_names.iter().cloned());
+
+    for (job, ((_, seed), stats)) in jobs.iter().zip(&stats).enumerate() {
+        let point = &applied_points[job / spec.replicas];
+        let metrics: BTreeMap<String, &Vec<f64>> = stats.metrics().into_iter().collect();
+        let n_epochs = metrics.values().map(|m| m.len()).max().unwrap_or(0);
+        let first_epoch = if final_only {
+            n_epochs.saturating_sub(1)
+        } else {
+            0
+        };
+
+        for epoch in first_epoch..n_epochs {
+            let mut row = point.clone();
+            row.push(Value::from(job % spec.replicas));
+            row.push(Value::from(*seed));
+            row.push(Value::from(epoch));
+            row.extend(metric_names.iter().map(|name| {
+                metrics
+                    .get(name)
+                    .and_then(|metric| metric.get(epoch))
+                    .map_or(Value::Null, |&error| Value::from(error))
+            }));
+            table.rows.push(row);
+        }
+    }
+
+    Ok(table)
+}
+
+impl SweepAxis {
+    pub fn values(&self) -> Result<Vec<Value>, String> {
+        let mut values = self.values.clone();
+
+        if let Some(range) = &self.range {
+            match range.steps {
+                0 => {}
+                1 => values.push(Value::from(range.start)),
+                steps => {
+                    let increment = (range.stop - range.start) / (steps - 1) as f64;
+                    values.extend(
+                        (0..steps).map(|i| Value::from(range.start + increment * i as f64)),
+                    );
+                }
+            }
+        }
+
+        if values.is_empty() {
+            return Err(format!("sweep axis {} has no values", self.field));
+        }
+        Ok(values)
+    }
+}
+
+impl SweepTable {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+        csv.push_str(&header.join(","));
+        csv.push('\n');
+
+        for row in &self.rows {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => String::new(),
+                    Value::String(s) => csv_field(s),
+                    other => csv_field(&other.to_string()),
+                })
+                .collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+
+    // one JSON object per row, keyed by column name
+    pub fn to_records(&self) -> Vec<Map<String, Value>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().cloned())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn cartesian_product(axis_values: &[Vec<Value>]) -> Vec<Vec<Value>> {
+    axis_values.iter().fold(vec![Vec::new()], |points, values| {
+        points
+            .iter()
+            .flat_map(|point| {
+                values.iter().map(move |value| {
+                    let mut point = point.clone();
+                    point.push(value.clone());
+                    point
+                })
+            })
+            .collect()
+    })
+}
+
+// build the config for one combination of swept values by editing its JSON representation.
+// Returns the config and the values as applied (see `coerce`).
+fn apply_point(
+    base: &SimulationConfig,
+    axes: &[SweepAxis],
+    point: &[Value],
+) -> Result<(SimulationConfig, Vec<Value>), Box<dyn Error>> {
+    let mut config = serde_json::to_value(base)?;
+    let mut applied_point = Vec::with_capacity(point.len());
+
+    for (axis, value) in axes.iter().zip(point) {
+        let mut target = &mut config;
+        for key in axis.field.split('.') {
+            target = target
+                .get_mut(key)
+                .ok_or_else(|| format!("unknown config field {}", axis.field))?;
+        }
+        *target = coerce(target, value);
+        applied_point.push(target.clone());
+    }
+
+    Ok((serde_json::from_value(config)?, applied_point))
+}
+
+// ranges produce floats, so keep integer fields (e.g. n_nodes) integers when the value is integral
+fn coerce(current: &Value, value: &Value) -> Value {
+    match (current, value.as_f64()) {
+        (Value::Number(n), Some(v)) if !n.is_f64() && v.fract() == 0.0 => {
+            if v >= 0.0 {
+                Value::from(v as u64)
+            } else {
+                Value::from(v as i64)
+            }
+        }
+        _ => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::PropagationModel;
+    use serde_json::json;
+
+    #[test]
+    fn coerce_keeps_integer_fields_integral() {
+        assert_eq!(coerce(&json!(100), &json!(50.0)), json!(50));
+        assert_eq!(coerce(&json!(-1), &json!(-2.0)), json!(-2));
+        assert_eq!(coerce(&json!(100), &json!(50.5)), json!(50.5));
+        assert_eq!(coerce(&json!(0.5), &json!(1.0)), json!(1.0));
+        assert_eq!(coerce(&json!("ekf"), &json!("ukf")), json!("ukf"));
+    }
+
+    #[test]
+    fn apply_point_replaces_config_fields() {
+        let base = example_config();
+        let axis = |field: &str| SweepAxis {
+            field: field.to_string(),
+            values: Vec::new(),
+            range: None,
+        };
+        let axes = [axis("n_nodes"), axis("propagation")];
+        let point = [json!(40.0), json!({"type": "great_circle"})];
+        let (config, applied) = apply_point(&base, &axes, &point).unwrap();
+        assert_eq!(config.n_nodes, 40);
+        assert_eq!(config.propagation, PropagationModel::GreatCircle);
+        assert_eq!(applied[0], json!(40));
+
+        assert!(apply_point(&base, &[axis("no_such_field")], &[json!(1)]).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+use crate::types::SimulationConfig;
+
+// the documented example configuration, which tests start from and adjust
+pub fn example_config() -> SimulationConfig {
+    toml::from_str(include_str!("../simulation.example.toml")).unwrap()
+}
@@ -1,10 +1,10 @@
-use crate::kalman::{NonlinearObservationModel, StationaryStateModel, SS};
-use adskalman::StateAndCovariance;
+use crate::estimator::PositionEstimator;
 use h3o::CellIndex;
-use rand::rngs::ThreadRng;
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 extern crate nav_types;
-use nalgebra::{Const, OVector};
+use nalgebra::{Const, Matrix4, OVector, Vector3, Vector4};
 use nav_types::{ECEF, WGS84};
 
 mod serialize_ecef {
@@ -33,10 +33,8 @@ mod serialize_h3_index {
     }
 }
 
-#[derive(Serialize)]
-pub struct CompilerParams {
-    pub n_measurements: usize,
-}
+// All random draws in a simulation come from a single seedable generator so runs are reproducible
+pub type SimulationRng = ChaCha8Rng;
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Node {
@@ -53,36 +51,128 @@ pub struct Node {
     #[serde(with = "serialize_ecef")]
     pub asserted_position: ECEF<f64>,
     pub asserted_wgs84: WGS84<f64>,
-    // Least-squares estimates
-    #[serde(with = "serialize_h3_index")]
-    pub ls_estimated_index: CellIndex,
+    // false if the node lies about its position (see `SimulationConfig::dishonest_fraction`)
+    pub honest: bool,
+    // trust (0 to 1] in the consistency of this node's measurements (see `SimulationConfig::reputation`)
+    pub trust: f64,
+    // sequential test of the node's assertion (see `SimulationConfig::sprt`): the log-likelihood ratio of its
+    // measurements so far between "detection_distance away" and "at the asserted position", and the decision once one
+    // is reached
+    pub sprt_log_likelihood_ratio: f64,
+    pub sprt_decision: Option<SprtDecision>,
+    // delays this node injects into its responses, if it is a delay attacker
+    pub delay_attack: Option<DelayAttack>,
+    // offset (s) of this node's clock from true time (see `SimulationConfig::clock_offset_variance`)
+    pub clock_offset: f64,
+    // position the estimators start from (see `SimulationConfig::init_strategy`)
     #[serde(with = "serialize_ecef")]
-    pub ls_estimated_position: ECEF<f64>,
-    pub ls_estimated_wgs84: WGS84<f64>,
-    // extended Kalman filter estimates
+    pub initial_position: ECEF<f64>,
+    // position estimates keyed by estimator name
+    pub estimates: BTreeMap<String, Estimate>,
+}
+
+// A position estimate reported by one estimator for one node
+#[derive(Serialize, Debug, Clone)]
+pub struct Estimate {
     #[serde(with = "serialize_h3_index")]
-    pub kf_estimated_index: CellIndex,
+    pub index: CellIndex,
     #[serde(with = "serialize_ecef")]
-    pub kf_estimated_position: ECEF<f64>,
-    pub kf_estimated_wgs84: WGS84<f64>,
-    pub kf_estimated_beta: f64,
-    pub kf_estimated_tau: f64,
-    pub kf_en_variance_semimajor_axis: OVector<f64, Const<2>>,
-    pub kf_en_variance_semimajor_axis_length: f64,
-    pub kf_en_variance_semiminor_axis_length: f64,
-    #[serde(skip)]
-    pub kf_state_and_covariance: StateAndCovariance<f64, SS>,
+    pub position: ECEF<f64>,
+    pub wgs84: WGS84<f64>,
+    // estimated message speed and latency, if the estimator models them
+    pub beta: Option<f64>,
+    pub tau: Option<f64>,
+    // 1σ confidence ellipse projected onto the East-North plane (zero if the estimator has no covariance)
+    pub en_variance_semimajor_axis: OVector<f64, Const<2>>,
+    pub en_variance_semimajor_axis_length: f64,
+    pub en_variance_semiminor_axis_length: f64,
+    // effective number of particles behind the estimate, if the estimator is sample based
+    pub effective_sample_size: Option<f64>,
+    // weight given to each peer's measurement in the last update, if the estimator reweights measurements
+    pub measurement_weights: Option<Vec<MeasurementWeight>>,
+    // number of times the node rejected each peer's measurement (by peer id), if the estimator rejects outliers
+    pub rejected_peers: Option<BTreeMap<usize, usize>>,
 }
 
-#[derive(Serialize, Clone, Debug)]
+// The weight (0 to 1) an estimator gave to the measurement to one peer
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct MeasurementWeight {
+    pub peer: usize,
+    pub weight: f64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
 pub struct Stats {
     // simulation stats for each epoch
-    // meters
-    pub kf_estimation_rms_error: Vec<f64>,
-    // meters
-    pub ls_estimation_rms_error: Vec<f64>,
+    // meters, keyed by estimator name
+    pub estimation_rms_error: BTreeMap<String, Vec<f64>>,
     // meters
     pub assertion_rms_error: Vec<f64>,
+    // the same errors restricted to honest and dishonest nodes, when the simulation has dishonest nodes
+    pub honest_estimation_rms_error: BTreeMap<String, Vec<f64>>,
+    pub dishonest_estimation_rms_error: BTreeMap<String, Vec<f64>>,
+    pub honest_assertion_rms_error: Vec<f64>,
+    pub dishonest_assertion_rms_error: Vec<f64>,
+    // fraction of dishonest nodes whose estimate is within `SimulationConfig::detection_distance` of their assertion,
+    // keyed by estimator name
+    pub dishonest_undetected_fraction: BTreeMap<String, Vec<f64>>,
+    // cumulative count of filter re-initializations after divergence, keyed by estimator name
+    pub estimator_resets: BTreeMap<String, Vec<f64>>,
+    // false assertion detection (chi-square test of each assertion against the estimate), keyed by estimator name,
+    // for estimators with a covariance: the fraction of honest nodes flagged (false positive, also without dishonest
+    // nodes to check calibration) and, when the simulation has dishonest nodes, the fraction of them flagged (true
+    // positive) and the area under the ROC curve
+    pub detection_true_positive_rate: BTreeMap<String, Vec<f64>>,
+    pub detection_false_positive_rate: BTreeMap<String, Vec<f64>>,
+    pub detection_auc: BTreeMap<String, Vec<f64>>,
+    // ROC curve over test thresholds at the latest epoch
+    pub detection_roc: BTreeMap<String, Vec<RocPoint>>,
+    // first epoch (counting from 1) at which each node was flagged, by node id
+    pub first_detection_epoch: BTreeMap<String, Vec<Option<usize>>>,
+    // mean epochs until a dishonest node is first flagged, over the dishonest nodes flagged so far
+    pub detection_latency: BTreeMap<String, Option<f64>>,
+    // sequential tests, when enabled: the fraction of honest (false accusation, also without dishonest nodes to check
+    // it against `sprt_false_accusation_rate`) and, when there are dishonest nodes, dishonest (true positive) nodes
+    // rejected so far, and the fraction of all nodes decided
+    pub sprt_true_positive_rate: Vec<f64>,
+    pub sprt_false_positive_rate: Vec<f64>,
+    pub sprt_decided_fraction: Vec<f64>,
+}
+
+// Outcome of a node's sequential test, and the epoch (counting from 1) at which it was reached
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct SprtDecision {
+    pub epoch: usize,
+    pub outcome: SprtOutcome,
+}
+
+// One hypothesis of a node's sequential test on where it is: at an ECEF center (m), up to an East-North offset (m)
+// with the assertion's gaussian spread a priori. The node's own message speed and latency are unknown too, so the
+// state is (east, north, inverse message speed (s/m), latency (s)), refined by the measurements so far, along with
+// their log-likelihood under the hypothesis.
+#[derive(Clone, Debug)]
+pub struct SprtHypothesis {
+    pub center: Vector3<f64>,
+    pub state: Vector4<f64>,
+    pub covariance: Matrix4<f64>,
+    pub log_likelihood: f64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SprtOutcome {
+    // the measurements are consistent with the asserted position
+    Accepted,
+    // the measurements place the node elsewhere
+    Rejected,
+}
+
+// One operating point of a detector: flagging every node whose test statistic is at least `threshold`
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct RocPoint {
+    pub threshold: f64,
+    pub true_positive_rate: f64,
+    pub false_positive_rate: f64,
 }
 
 #[derive(Serialize)]
@@ -92,19 +182,60 @@ pub struct Simulation {
     pub nodes: Vec<Node>,
     pub stats: Stats,
     #[serde(skip)]
-    pub rng: ThreadRng,
+    pub rng: SimulationRng,
+    // estimators selected by `SimulationConfig::estimators`, each holding its state for every node
+    #[serde(skip)]
+    pub estimators: Vec<Box<dyn PositionEstimator>>,
     #[serde(skip)]
-    pub kf_state_model: StationaryStateModel<f64>,
+    pub reputation: Reputation,
+    // every node's sequential test hypotheses, the assertion first (empty unless `SimulationConfig::sprt`)
     #[serde(skip)]
-    pub kf_observation_model_generator: NonlinearObservationModel,
+    pub sprt_hypotheses: Vec<Vec<SprtHypothesis>>,
+    #[serde(skip)]
+    pub rtt_replay: Option<RttReplay>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+// Residual statistics of the measurements to each node, from which its trust is derived
+#[derive(Debug, Clone, Default)]
+pub struct Reputation {
+    // exponentially weighted mean squared time residual (s^2) of the measurements to each node, None until it is
+    // first measured
+    pub mean_squared_residual: Vec<Option<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SimulationConfig {
-    // simulation parameters (note that the numbers of measurements per update is a compiler flag)
+    // simulation parameters
     pub n_nodes: usize,
+    // number of time-of-flight measurements per position update
+    pub n_measurements: usize,
     pub n_epochs: usize,
     pub h3_resolution: i32,
+    // random seed: runs with the same config and seed are identical. Drawn at random (at most `MAX_SEED`) if not set.
+    pub seed: Option<u64>,
+    // position estimators to run
+    #[serde(default = "default_estimators")]
+    pub estimators: Vec<EstimatorKind>,
+    // where the estimators start each node, and for MDS how many epochs of measurements to collect first
+    #[serde(default)]
+    pub init_strategy: InitStrategy,
+    #[serde(default = "default_mds_bootstrap_epochs")]
+    pub mds_bootstrap_epochs: usize,
+    // where nodes are truly located
+    #[serde(default)]
+    pub placement: PlacementStrategy,
+    // restrict the simulation to a region: nodes are placed and assert positions inside it, and estimates that leave it
+    // are optionally moved back to its nearest point
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub region_constrain_estimates: bool,
+    // imported node locations (e.g. an actual deployment), used instead of `placement`; sets `n_nodes`
+    #[serde(default)]
+    pub node_locations: Option<Vec<NodeLocation>>,
+    // measured round trip times between the imported nodes, replayed instead of simulating measurements
+    #[serde(default)]
+    pub rtt_dataset: Option<Vec<RttSample>>,
     // physical parameters
     pub asserted_position_variance: f64,
     pub beta_min: f64,
@@ -114,11 +245,83 @@ pub struct SimulationConfig {
     pub tau_max: f64,
     pub tau_variance: f64,
     pub message_distance_max: f64,
+    // path the messages truly travel, and the path the estimators' observation models assume
+    #[serde(default)]
+    pub propagation: PropagationModel,
+    #[serde(default)]
+    pub model_propagation: PropagationModel,
+    // ping and pong take different paths, with independent route inflation and queueing (symmetric if None)
+    #[serde(default)]
+    pub asymmetric_paths: Option<AsymmetricPaths>,
+    // variance (s^2) of the node clocks' offsets from true time, which skew one-way times
+    #[serde(default)]
+    pub clock_offset_variance: f64,
+    // expose per-direction timestamps so estimators can use one-way times instead of only round trip times
+    #[serde(default)]
+    pub one_way_timestamps: bool,
+    // adversarial nodes: the fraction of nodes asserting a false position, and how they choose it
+    #[serde(default)]
+    pub dishonest_fraction: f64,
+    #[serde(default)]
+    pub lie_strategy: LieStrategy,
+    // delays the dishonest nodes add to their responses (none if unset), and the fraction of peers they delay
+    #[serde(default)]
+    pub delay_strategy: Option<DelayStrategy>,
+    #[serde(default = "default_delay_victim_fraction")]
+    pub delay_victim_fraction: f64,
+    // Sybil attack: all dishonest nodes are run by one operator at a single location (overrides `lie_strategy`)
+    #[serde(default)]
+    pub sybil: Option<SybilAttack>,
+    // dishonest nodes report fabricated round trip times between each other, consistent with their asserted positions
+    #[serde(default)]
+    pub colluding: bool,
+    // sequential probability ratio test of every node's assertion, with its target false accusation and missed
+    // detection rates. The alternative hypothesis is the node being `detection_distance` away from its assertion, in
+    // any of a fixed set of directions. The standard deviation (s) of the round trip time residuals defaults to their
+    // spread at the first estimator's estimates each epoch.
+    #[serde(default)]
+    pub sprt: bool,
+    #[serde(default = "default_sprt_error_rate")]
+    pub sprt_false_accusation_rate: f64,
+    #[serde(default = "default_sprt_error_rate")]
+    pub sprt_missed_detection_rate: f64,
+    #[serde(default)]
+    pub sprt_residual_sigma: Option<f64>,
+    // false alarm rate of the chi-square test flagging assertions that are inconsistent with the estimates
+    #[serde(default = "default_detection_false_alarm_rate")]
+    pub detection_false_alarm_rate: f64,
+    // a dishonest node escapes detection while its estimate stays within this distance (m) of its assertion. Defaults
+    // to 3 standard deviations of the asserted position.
+    #[serde(default)]
+    pub detection_distance: Option<f64>,
     // least squares model parameters
     pub ls_model_beta: f64,
     pub ls_model_tau: f64,
     pub ls_tolerance: f64,
     pub ls_iterations: usize,
+    // robust loss on the time residuals and its scale (s): residuals beyond the scale are down-weighted
+    #[serde(default)]
+    pub ls_loss: RobustLoss,
+    #[serde(default = "default_ls_loss_scale")]
+    pub ls_loss_scale: f64,
+    // reputation: track how consistent the measurements to each node are, and down-weight and stop measuring nodes
+    // whose trust falls below the threshold
+    #[serde(default)]
+    pub reputation: bool,
+    // weight of a node's residual history against each new residual
+    #[serde(default = "default_reputation_decay")]
+    pub reputation_decay: f64,
+    #[serde(default = "default_reputation_threshold")]
+    pub reputation_threshold: f64,
+    // variance (s^2) of an honest node's time residuals: measurement noise plus the error of the estimates. Defaults to
+    // `kf_model_tof_observation_variance`.
+    #[serde(default)]
+    pub reputation_residual_variance: Option<f64>,
+    // RANSAC: minimal subsets tried per update, and the largest time residual (s) of an inlier
+    #[serde(default = "default_ransac_iterations")]
+    pub ransac_iterations: usize,
+    #[serde(default = "default_ransac_threshold")]
+    pub ransac_threshold: f64,
     // kalman filter model parameters
     pub kf_model_position_variance: f64,
     pub kf_model_beta: f64,
@@ -126,12 +329,348 @@ pub struct SimulationConfig {
     pub kf_model_tau: f64,
     pub kf_model_tau_variance: f64,
     pub kf_model_tof_observation_variance: f64,
+    // assumed variance (s^2) of the node clock offsets, when using one-way times
+    #[serde(default)]
+    pub kf_model_clock_offset_variance: f64,
+    // kalman filter divergence limit (m^2): a filter whose position variance grows beyond it is re-initialized
+    #[serde(default = "default_kf_max_position_variance")]
+    pub kf_max_position_variance: f64,
+    // kalman filter consistency limit: a filter whose moving average of normalized innovation squared per measurement
+    // (~1 when consistent) exceeds it is re-initialized
+    #[serde(default = "default_kf_max_normalized_innovation")]
+    pub kf_max_normalized_innovation: f64,
+    // particle filter model parameters (process noise is shared with the kalman filter model)
+    #[serde(default = "default_pf_n_particles")]
+    pub pf_n_particles: usize,
+    // centralized joint solver parameters: epochs of measurements per solve, iterations per solve, and the
+    // convergence tolerance on the largest position step (m)
+    #[serde(default = "default_joint_window_epochs")]
+    pub joint_window_epochs: usize,
+    #[serde(default = "default_joint_iterations")]
+    pub joint_iterations: usize,
+    #[serde(default = "default_joint_tolerance")]
+    pub joint_tolerance: f64,
+}
+
+fn default_estimators() -> Vec<EstimatorKind> {
+    vec![EstimatorKind::Ls, EstimatorKind::Ekf]
+}
+
+fn default_ls_loss_scale() -> f64 {
+    0.01
+}
+
+fn default_sprt_error_rate() -> f64 {
+    0.01
+}
+
+fn default_detection_false_alarm_rate() -> f64 {
+    0.01
+}
+
+fn default_reputation_decay() -> f64 {
+    0.9
+}
+
+fn default_reputation_threshold() -> f64 {
+    0.2
+}
+
+fn default_ransac_iterations() -> usize {
+    50
+}
+
+fn default_ransac_threshold() -> f64 {
+    0.01
+}
+
+fn default_delay_victim_fraction() -> f64 {
+    1.0
+}
+
+fn default_mds_bootstrap_epochs() -> usize {
+    3
+}
+
+fn default_pf_n_particles() -> usize {
+    500
+}
+
+fn default_joint_window_epochs() -> usize {
+    10
+}
+
+fn default_joint_iterations() -> usize {
+    10
+}
+
+fn default_joint_tolerance() -> f64 {
+    1.0
+}
+
+fn default_kf_max_normalized_innovation() -> f64 {
+    5.0
+}
+
+// an uncertainty on the order of the earth's radius means the filter no longer knows anything
+fn default_kf_max_position_variance() -> f64 {
+    6_371_000.0f64.powi(2)
+}
+
+// How the true node locations are drawn
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlacementStrategy {
+    // anywhere on the earth's surface, oceans included
+    #[default]
+    Uniform,
+    // around a list of cities, picked in proportion to their weights
+    Cities {
+        cities: Vec<City>,
+    },
+    // around the built-in major metro areas, picked in proportion to their population, with the same spread (m)
+    Metros {
+        spread: f64,
+    },
+    // uniform over the area of a latitude / longitude box (degrees); the box crosses the antimeridian if
+    // `min_longitude` > `max_longitude`
+    Bounds {
+        min_latitude: f64,
+        max_latitude: f64,
+        min_longitude: f64,
+        max_longitude: f64,
+    },
+}
+
+// A geographic region: H3 cells of any resolutions (e.g. a compacted cell set, as numbers or hex strings), or a GeoJSON
+// Polygon / MultiPolygon geometry with [longitude, latitude] (degrees) rings, the first ring of each polygon being its
+// exterior and the others holes. Polygons crossing the antimeridian should be split as the GeoJSON spec recommends.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Region {
+    #[serde(rename = "cells")]
+    Cells {
+        #[serde(deserialize_with = "deserialize_cells")]
+        cells: Vec<CellIndex>,
+    },
+    #[serde(rename = "Polygon", alias = "polygon")]
+    Polygon { coordinates: Vec<Vec<Vec<f64>>> },
+    #[serde(rename = "MultiPolygon", alias = "multi_polygon")]
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Vec<f64>>>>,
+    },
+}
+
+// H3 cells either as their integer index or their usual hex string
+fn deserialize_cells<'de, D>(deserializer: D) -> Result<Vec<CellIndex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Cell {
+        Index(CellIndex),
+        Hex(String),
+    }
+
+    Vec::<Cell>::deserialize(deserializer)?
+        .into_iter()
+        .map(|cell| match cell {
+            Cell::Index(index) => Ok(index),
+            Cell::Hex(hex) => hex.parse().map_err(serde::de::Error::custom),
+        })
+        .collect()
+}
+
+// An imported node: its true location (degrees, m above the WGS84 ellipsoid) and optionally an identifier (e.g. a probe
+// id, referenced by RTT datasets), its asserted location and message speed and latency. Missing values are simulated as
+// for generated nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeLocation {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default)]
+    pub altitude: f64,
+    #[serde(default)]
+    pub asserted_latitude: Option<f64>,
+    #[serde(default)]
+    pub asserted_longitude: Option<f64>,
+    #[serde(default)]
+    pub asserted_altitude: Option<f64>,
+    #[serde(default)]
+    pub beta: Option<f64>,
+    #[serde(default)]
+    pub tau: Option<f64>,
+}
+
+// One measured round trip time (s) between two imported nodes (indices into `node_locations`), optionally tagged with
+// the dataset epoch it was measured in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RttSample {
+    pub source: usize,
+    pub target: usize,
+    pub rtt: f64,
+    #[serde(default)]
+    pub epoch: Option<usize>,
+}
+
+// The RTT samples of a dataset involving each node, both directions of a pair as round trip times are symmetric, and
+// the number of epochs the dataset is tagged with (samples replay cyclically over them)
+#[derive(Debug, Clone, Default)]
+pub struct RttReplay {
+    pub samples: Vec<Vec<RttSample>>,
+    pub n_epochs: Option<usize>,
+}
+
+// A cluster of nodes: gaussian around (`latitude`, `longitude`) (degrees) with standard deviation `spread` (m)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct City {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default = "default_city_weight")]
+    pub weight: f64,
+    pub spread: f64,
+}
+
+fn default_city_weight() -> f64 {
+    1.0
+}
+
+// Per-direction path differences: each leg's path length is multiplied by its own log-normal detour factor (at least 1)
+// with log standard deviation `inflation_spread`, and delayed by exponential queueing with mean `queueing_delay` (s)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AsymmetricPaths {
+    #[serde(default)]
+    pub inflation_spread: f64,
+    #[serde(default)]
+    pub queueing_delay: f64,
+}
+
+// Path a message travels between two nodes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PropagationModel {
+    // straight line through the earth
+    #[default]
+    Chord,
+    // geodesic along the WGS84 ellipsoid's surface
+    GreatCircle,
+    // geodesic times a route inflation factor `inflation + short_route_inflation * exp(-d / short_route_distance)`
+    // for a geodesic distance d (m), as routing detours are relatively longer on short paths. Each simulated message
+    // draws its factor with log-normal noise of standard deviation `noise` (never shorter than the geodesic), while the
+    // estimators' models use the mean factor.
+    RouteInflated {
+        inflation: f64,
+        #[serde(default)]
+        short_route_inflation: f64,
+        #[serde(default = "default_short_route_distance")]
+        short_route_distance: f64,
+        #[serde(default)]
+        noise: f64,
+    },
+}
+
+fn default_short_route_distance() -> f64 {
+    1e6
+}
+
+// How dishonest nodes choose their asserted position
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LieStrategy {
+    // anywhere on the earth's surface
+    #[default]
+    Random,
+    // all liars claim the same location (degrees)
+    TargetCity {
+        latitude: f64,
+        longitude: f64,
+    },
+    // a fixed distance (m) along a fixed bearing (degrees clockwise from north) from the true location
+    Offset {
+        distance: f64,
+        bearing: f64,
+    },
+}
+
+// One operator controlling all dishonest nodes from the true location of one of them, asserting them uniformly over
+// a disk of `radius` (m) around (`latitude`, `longitude`) (degrees)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SybilAttack {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: f64,
+}
+
+// How a delay attacker delays its responses. A response can only be made slower, never faster than its true time of
+// flight.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DelayStrategy {
+    // the same delay (s) on every response
+    Constant { delay: f64 },
+    // the delay that makes the round trip look like it came from the attacker's asserted position
+    Targeted,
+    // a delay (s) drawn uniformly per response
+    Random { delay_min: f64, delay_max: f64 },
+}
+
+// A node's delay attack: how it delays responses and to which peers
+#[derive(Serialize, Debug, Clone)]
+pub struct DelayAttack {
+    pub strategy: DelayStrategy,
+    pub victims: BTreeSet<usize>,
+}
+
+// Loss functions for the least squares residuals, applied by iteratively reweighted least squares
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RobustLoss {
+    // plain least squares
+    #[default]
+    Squared,
+    // quadratic up to the scale, linear beyond it
+    Huber,
+    // logarithmic: large residuals keep a small, decaying weight
+    Cauchy,
+    // Tukey's biweight: residuals beyond the scale are ignored
+    Tukey,
+}
+
+// Initial position strategies for the estimators
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InitStrategy {
+    // the node's asserted position
+    #[default]
+    Asserted,
+    // classical multidimensional scaling of bootstrap measurements, independent of the node's own assertion
+    Mds,
+}
+
+// Registry of available position estimators
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimatorKind {
+    // Levenberg-Marquardt least squares
+    Ls,
+    // extended Kalman filter
+    Ekf,
+    // unscented Kalman filter
+    Ukf,
+    // particle filter
+    Pf,
+    // centralized joint solver over all nodes' measurements
+    Joint,
+    // least squares with RANSAC outlier rejection
+    Ransac,
 }
 
 #[derive(PartialEq)]
-pub enum PositionType {
-    KfEstimated,
-    LsEstimated,
+pub enum PositionType<'a> {
+    Estimated(&'a str),
     Asserted,
 }
 
@@ -139,4 +678,87 @@ pub enum PositionType {
 pub struct ChunkResult {
     pub nodes: Vec<Node>,
     pub stats: Stats,
+    // seed of the run, to reproduce it
+    pub seed: u64,
+}
+
+// per-epoch distribution of one error metric across Monte Carlo replicas
+#[derive(Serialize, Clone, Debug)]
+pub struct ErrorBands {
+    // meters
+    pub mean: Vec<f64>,
+    // meters: standard error of the mean (mean ± 1.96 * standard_error is a ~95% confidence interval)
+    pub standard_error: Vec<f64>,
+    // meters
+    pub median: Vec<f64>,
+    pub percentiles: Vec<PercentileBand>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PercentileBand {
+    // percentile in [0, 100]
+    pub percentile: f64,
+    // meters
+    pub values: Vec<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchResult {
+    pub config: SimulationConfig,
+    // one seed per replica
+    pub seeds: Vec<u64>,
+    // keyed by estimator name
+    pub estimation_rms_error: BTreeMap<String, ErrorBands>,
+    pub assertion_rms_error: ErrorBands,
+    // errors restricted to honest and dishonest nodes (empty without dishonest nodes)
+    pub honest_estimation_rms_error: BTreeMap<String, ErrorBands>,
+    pub dishonest_estimation_rms_error: BTreeMap<String, ErrorBands>,
+    pub honest_assertion_rms_error: Option<ErrorBands>,
+    pub dishonest_assertion_rms_error: Option<ErrorBands>,
+    pub dishonest_undetected_fraction: BTreeMap<String, ErrorBands>,
+    pub detection_true_positive_rate: BTreeMap<String, ErrorBands>,
+    pub detection_false_positive_rate: BTreeMap<String, ErrorBands>,
+    pub detection_auc: BTreeMap<String, ErrorBands>,
+    pub sprt_true_positive_rate: Option<ErrorBands>,
+    pub sprt_false_positive_rate: Option<ErrorBands>,
+    pub sprt_decided_fraction: Option<ErrorBands>,
+}
+
+// Sweep over one or more SimulationConfig fields: every combination (cartesian product) of axis values is simulated
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SweepSpec {
+    pub axes: Vec<SweepAxis>,
+    // replicas per combination: replica i uses seed + i for every combination
+    #[serde(default = "default_replicas")]
+    pub replicas: usize,
+}
+
+fn default_replicas() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SweepAxis {
+    // config field name, with nested fields separated by dots
+    pub field: String,
+    // explicit values
+    #[serde(default)]
+    pub values: Vec<serde_json::Value>,
+    // evenly spaced values, appended to the explicit values
+    pub range: Option<SweepRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SweepRange {
+    pub start: f64,
+    // inclusive
+    pub stop: f64,
+    pub steps: usize,
+}
+
+// a tidy table: one column per swept field followed by replica, seed, epoch and the error metrics (meters)
+#[derive(Serialize, Debug, Default)]
+pub struct SweepTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
 }
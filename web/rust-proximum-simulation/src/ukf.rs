@@ -0,0 +1,352 @@
+use crate::estimator::PositionEstimator;
+use crate::kalman::{
+    finish_step, kf_position, kf_position_covariance, kf_set_position, kf_state_model,
+    normalize_state, one_way_observation, InnovationStatistics, KalmanFilters, KalmanStep,
+    LinearizedObservationModel, NonlinearObservationModel, StationaryStateModel, OS, SS,
+};
+use crate::physics::Measurements;
+use crate::types::{Node, SimulationConfig};
+use adskalman::{StateAndCovariance, TransitionModelLinearNoControl};
+use log::trace;
+use nalgebra::{Cholesky, DimName, Dyn, Matrix3, OMatrix, OVector};
+use nav_types::ECEF;
+use std::error::Error;
+
+// Sigma point spread parameters (Van der Merwe's scaled unscented transform). With alpha = 1 and kappa = 0 all
+// mean weights are non-negative, which keeps the predicted measurement covariance positive definite.
+const ALPHA: f64 = 1.0;
+const BETA: f64 = 2.0;
+const KAPPA: f64 = 0.0;
+
+// number of sigma points for the 5-dimensional state
+const N_SIGMA_POINTS: usize = 2 * SS::USIZE + 1;
+
+struct SigmaWeights {
+    mean: [f64; N_SIGMA_POINTS],
+    covariance: [f64; N_SIGMA_POINTS],
+    // scale of the covariance square root used to spread the sigma points
+    spread: f64,
+}
+
+impl SigmaWeights {
+    fn new() -> Self {
+        let n = SS::USIZE as f64;
+        let lambda = ALPHA.powi(2) * (n + KAPPA) - n;
+
+        let mut mean = [1.0 / (2.0 * (n + lambda)); N_SIGMA_POINTS];
+        let mut covariance = mean;
+        mean[0] = lambda / (n + lambda);
+        covariance[0] = mean[0] + (1.0 - ALPHA.powi(2) + BETA);
+
+        Self {
+            mean,
+            covariance,
+            spread: n + lambda,
+        }
+    }
+}
+
+// Update the estimated position of a specific node based on new measurements using the unscented Kalman filter.
+// Same state, process noise and measurement model as `kf_step`, but the measurement is predicted by propagating sigma
+// points through the nonlinear time-of-flight model instead of through its Jacobian.
+pub fn ukf_step(
+    index: usize,
+    measurements: &Measurements,
+    filters: &KalmanFilters,
+    nodes: &[Node],
+    observation_model_generator: &NonlinearObservationModel,
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let (their_indices, times) = measurements;
+    // the linearization is only used for its measurement noise, which includes the uncertainty of the peer states
+    let observation_model = observation_model_generator.linearize_at(
+        &filters.states,
+        index,
+        their_indices,
+        filters.observation_variance(config),
+        nodes,
+        config.model_propagation,
+    );
+    ukf_observe(
+        index,
+        &observation_model,
+        times,
+        &filters.states,
+        nodes,
+        state_model,
+        config,
+    )
+}
+
+// Like `ukf_step`, for one-way times (see `kalman::kf_timed_step`)
+#[allow(clippy::too_many_arguments)]
+pub fn ukf_timed_step(
+    index: usize,
+    measurements: &Measurements,
+    ping_times: &OVector<f64, OS>,
+    filters: &KalmanFilters,
+    nodes: &[Node],
+    observation_model_generator: &NonlinearObservationModel,
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let observation_model = observation_model_generator.linearize_one_way_at(
+        &filters.states,
+        index,
+        &measurements.0,
+        filters.observation_variance(config),
+        config.kf_model_clock_offset_variance,
+        nodes,
+        config.model_propagation,
+    );
+    ukf_observe(
+        index,
+        &observation_model,
+        &one_way_observation(measurements, ping_times),
+        &filters.states,
+        nodes,
+        state_model,
+        config,
+    )
+    .map(KalmanStep::one_way)
+}
+
+// Predict and update a node's filter with the unscented transform of an observation model
+fn ukf_observe(
+    index: usize,
+    observation_model: &LinearizedObservationModel,
+    observation: &OVector<f64, OS>,
+    states: &[StateAndCovariance<f64, SS>],
+    nodes: &[Node],
+    state_model: &StationaryStateModel<f64>,
+    config: &SimulationConfig,
+) -> Result<KalmanStep, Box<dyn Error>> {
+    let n_measurements = observation.len();
+    let weights = SigmaWeights::new();
+
+    let prior = state_model.predict(&states[index]);
+    let prior_state = prior.state();
+
+    // sigma points: the prior mean and the mean +/- each column of the scaled covariance square root
+    let covariance_sqrt = Cholesky::new(prior.covariance() * weights.spread)
+        .ok_or("prior covariance is not positive definite")?
+        .l();
+
+    let mut sigma_points = OMatrix::<f64, SS, Dyn>::zeros(N_SIGMA_POINTS);
+    sigma_points.set_column(0, prior_state);
+    for i in 0..SS::USIZE {
+        let column = covariance_sqrt.column(i);
+        sigma_points.set_column(1 + i, &(prior_state + column));
+        sigma_points.set_column(1 + SS::USIZE + i, &(prior_state - column));
+    }
+
+    // propagate every sigma point through the time-of-flight model
+    let mut predicted_observations = OMatrix::<f64, OS, Dyn>::zeros(n_measurements, N_SIGMA_POINTS);
+    for i in 0..N_SIGMA_POINTS {
+        let sigma_point: OVector<f64, SS> = sigma_points.column(i).into();
+        predicted_observations.set_column(i, &observation_model.predict_observation(&sigma_point));
+    }
+
+    let mut predicted_observation = OVector::<f64, OS>::zeros(n_measurements);
+    for i in 0..N_SIGMA_POINTS {
+        predicted_observation += predicted_observations.column(i) * weights.mean[i];
+    }
+
+    // innovation covariance and state-measurement cross covariance
+    let mut s = observation_model.observation_noise_covariance().clone();
+    let mut cross_covariance = OMatrix::<f64, SS, OS>::zeros(n_measurements);
+    for i in 0..N_SIGMA_POINTS {
+        let observation_deviation = predicted_observations.column(i) - &predicted_observation;
+        let state_deviation = sigma_points.column(i) - prior_state;
+        s += &observation_deviation * observation_deviation.transpose() * weights.covariance[i];
+        cross_covariance +=
+            state_deviation * observation_deviation.transpose() * weights.covariance[i];
+    }
+
+    let s_inv = Cholesky::new(s.clone())
+        .ok_or("innovation covariance is not positive definite")?
+        .inverse();
+    let k_gain = &cross_covariance * &s_inv;
+
+    let innovation = observation - predicted_observation;
+    trace!("innovation: {:#?}", innovation);
+    let statistics = InnovationStatistics::new(&innovation, &s, &s_inv);
+    let state = prior_state + &k_gain * innovation;
+
+    let covariance = prior.covariance() - &k_gain * s * k_gain.transpose();
+    // keep the covariance symmetric against round-off
+    let covariance_transpose = covariance.transpose();
+    let covariance = (covariance + covariance_transpose) / 2.0;
+
+    let mut ukf_state_and_covariance = StateAndCovariance::new(state, covariance);
+
+    trace!("state after: {:#?}", ukf_state_and_covariance);
+
+    finish_step(&mut ukf_state_and_covariance, &nodes[index], config)?;
+
+    Ok(KalmanStep {
+        state_and_covariance: ukf_state_and_covariance,
+        prior_covariance: *prior.covariance(),
+        statistics,
+    })
+}
+
+// Unscented Kalman filter estimator: one normalized state and covariance per node
+pub struct UnscentedKalmanFilter {
+    state_model: StationaryStateModel<f64>,
+    observation_model_generator: NonlinearObservationModel,
+    // shares the EKF's per-node states, noise adaptation and divergence handling
+    filters: KalmanFilters,
+}
+
+impl UnscentedKalmanFilter {
+    pub fn new(config: &SimulationConfig) -> Self {
+        Self {
+            state_model: kf_state_model(config),
+            observation_model_generator: NonlinearObservationModel::new(),
+            filters: KalmanFilters::new(),
+        }
+    }
+}
+
+impl PositionEstimator for UnscentedKalmanFilter {
+    fn name(&self) -> &'static str {
+        "ukf"
+    }
+
+    fn init(&mut self, node: &Node, config: &SimulationConfig) {
+        self.filters.init(node, config);
+    }
+
+    fn update(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let step = ukf_step(
+            index,
+            measurements,
+            &self.filters,
+            nodes,
+            &self.observation_model_generator,
+            &self.state_model,
+            config,
+        );
+        self.filters.apply_step(index, step, nodes, config);
+        Ok(())
+    }
+
+    fn update_timed(
+        &mut self,
+        index: usize,
+        measurements: &Measurements,
+        ping_times: &OVector<f64, OS>,
+        nodes: &[Node],
+        config: &SimulationConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let step = ukf_timed_step(
+            index,
+            measurements,
+            ping_times,
+            &self.filters,
+            nodes,
+            &self.observation_model_generator,
+            &self.state_model,
+            config,
+        );
+        self.filters.apply_step(index, step, nodes, config);
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> ECEF<f64> {
+        kf_position(&self.filters.states[index])
+    }
+
+    fn set_position(&mut self, index: usize, position: ECEF<f64>) {
+        kf_set_position(&mut self.filters.states[index], position);
+    }
+
+    fn covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(kf_position_covariance(&self.filters.states[index]))
+    }
+
+    fn assertion_cross_covariance(&self, index: usize) -> Option<Matrix3<f64>> {
+        Some(self.filters.assertion_cross_covariance(index))
+    }
+
+    fn beta(&self, index: usize) -> Option<f64> {
+        Some(normalize_state(self.filters.states[index].state())[3])
+    }
+
+    fn tau(&self, index: usize) -> Option<f64> {
+        Some(normalize_state(self.filters.states[index].state())[4])
+    }
+
+    fn resets(&self) -> Option<usize> {
+        Some(self.filters.resets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::example_config;
+    use crate::types::{EstimatorKind, Simulation};
+    use nalgebra::{OVector, U5};
+
+    // the weighted sigma points have the mean and covariance they were spread from
+    #[test]
+    fn sigma_points_recover_the_prior() {
+        let weights = SigmaWeights::new();
+        assert!(weights.mean.iter().all(|&w| w >= 0.0));
+        assert!((weights.mean.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+
+        let mean = OVector::<f64, U5>::new(1.0, -2.0, 3.0, 0.7, 1e-3);
+        let factor =
+            OMatrix::<f64, U5, U5>::from_fn(|i, j| (i + 2 * j + 1) as f64).lower_triangle();
+        let covariance = factor * factor.transpose();
+        let covariance_sqrt = Cholesky::new(covariance * weights.spread).unwrap().l();
+
+        let mut points = vec![mean];
+        for i in 0..SS::USIZE {
+            points.push(mean + covariance_sqrt.column(i));
+            points.push(mean - covariance_sqrt.column(i));
+        }
+        let recovered_mean: OVector<f64, U5> = points
+            .iter()
+            .zip(weights.mean)
+            .map(|(point, w)| point * w)
+            .sum();
+        assert!((recovered_mean - mean).norm() < 1e-12);
+        let recovered_covariance: OMatrix<f64, U5, U5> = points
+            .iter()
+            .zip(weights.mean)
+            .map(|(point, w)| (point - mean) * (point - mean).transpose() * w)
+            .sum();
+        assert!((recovered_covariance - covariance).norm() < 1e-9 * covariance.norm());
+    }
+
+    // same model as the EKF, so it improves on the assertions about as much
+    #[test]
+    fn ukf_tracks_the_ekf() {
+        let mut config = example_config();
+        config.n_nodes = 30;
+        config.n_epochs = 10;
+        config.estimators = vec![EstimatorKind::Ekf, EstimatorKind::Ukf];
+        let mut simulation = Simulation::new(config).unwrap();
+        simulation.run().unwrap();
+
+        let stats = &simulation.stats;
+        let ekf = stats.estimation_rms_error["ekf"].last().unwrap();
+        let ukf = stats.estimation_rms_error["ukf"].last().unwrap();
+        assert!(
+            ukf < stats.assertion_rms_error.last().unwrap(),
+            "ukf error {}",
+            ukf
+        );
+        assert!(*ukf < 1.5 * ekf, "ukf error {}, ekf error {}", ukf, ekf);
+    }
+}
@@ -41,13 +41,13 @@ export enum POSITION_TYPE {
 
 const formatState = (node: Node, positionType: POSITION_TYPE) => (
   `
-Latitude: ${rad2deg(node.true_wgs84.latitude).toFixed(2)}° ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${rad2deg(node.kf_estimated_wgs84.latitude).toFixed(2)}°)` : ''}
+Latitude: ${rad2deg(node.true_wgs84.latitude).toFixed(2)}° ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${rad2deg(node.estimates.ekf?.wgs84.latitude ?? NaN).toFixed(2)}°)` : ''}
 
-Longitude: ${rad2deg(node.true_wgs84.longitude).toFixed(2)}° ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${rad2deg(node.kf_estimated_wgs84.longitude).toFixed(2)}°)` : ''}
+Longitude: ${rad2deg(node.true_wgs84.longitude).toFixed(2)}° ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${rad2deg(node.estimates.ekf?.wgs84.longitude ?? NaN).toFixed(2)}°)` : ''}
 
-β: ${node.true_beta.toFixed(2)} c ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${node.kf_estimated_beta.toFixed(2)} c)` : ''}
+β: ${node.true_beta.toFixed(2)} c ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${(node.estimates.ekf?.beta ?? NaN).toFixed(2)} c)` : ''}
 
-τ: ${(node.true_tau * 1000).toFixed(2)} ms ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${(node.kf_estimated_tau * 1000).toFixed(2)} ms)` : ''}
+τ: ${(node.true_tau * 1000).toFixed(2)} ms ${positionType === POSITION_TYPE.kfEstimated ? `(Est: ${((node.estimates.ekf?.tau ?? NaN) * 1000).toFixed(2)} ms)` : ''}
 `
 )
 
@@ -65,7 +65,7 @@ ${formatState(node, positionType)}
       break;
     }
     case POSITION_TYPE.asserted: {
-      title = `Node ${node.id}: asserted position`
+      title = `Node ${node.id}: asserted position${node.honest ? '' : ' (dishonest)'}`
       body = `
 Asserted Position Error: ${distanceKm(node.true_position, node.asserted_position)} km
 `;
@@ -83,7 +83,7 @@ Asserted Position Error: ${distanceKm(node.true_position, node.asserted_position
       body = `
 ${formatState(node, positionType)}
 
-Position Error: ${distanceKm(node.true_position, node.ls_estimated_position)} km
+Position Error: ${distanceKm(node.true_position, node.estimates.ls.position)} km
 `;
       break;
     }
@@ -92,7 +92,7 @@ Position Error: ${distanceKm(node.true_position, node.ls_estimated_position)} km
       body = `
 ${formatState(node, positionType)}
 
-Position Error: ${distanceKm(node.true_position, node.kf_estimated_position)} km
+Position Error: ${distanceKm(node.true_position, node.estimates.ekf.position)} km
 `;
       break;
     }
@@ -101,7 +101,7 @@ Position Error: ${distanceKm(node.true_position, node.kf_estimated_position)} km
       body = `
 ${formatState(node, positionType)}
 
-Position Error: ${distanceKm(node.true_position, node.kf_estimated_position)} km
+Position Error: ${distanceKm(node.true_position, node.estimates.ekf.position)} km
 `;
       break;
     }
@@ -1,6 +1,6 @@
 import React, { useState } from 'react';
 import { useForm } from 'react-hook-form';
-import { CompilerParams, SimulationParamFields, SimulationConfig } from '../types';
+import { SimulationParamFields, SimulationConfig } from '../types';
 import { FormField, HelpTextPopup, titleTexts, helpTexts } from './SimulationFormComponents';
 
 const defaultSimulationConfig = {
@@ -40,20 +40,17 @@ const defaultSimulationConfig = {
 interface SimulationFormProps {
   runSimulation: (params: SimulationConfig) => void;
   resetSimulation: (params: SimulationConfig) => void;
-  compilerParams: CompilerParams;
   progress: number;
 }
 
 const SimulationForm: React.FC<SimulationFormProps> = ({
   runSimulation,
   resetSimulation,
-  compilerParams,
   progress
 }) => {
   // set up the form including URL params if present 
   const { control, handleSubmit, watch } = useForm<SimulationParamFields>({
-    // Compiler parameters cannot be changed in the form.
-    defaultValues: { ...defaultSimulationConfig as any, nMeasurements: compilerParams.n_measurements },
+    defaultValues: defaultSimulationConfig as any,
   });
 
   const [canEditForm, setCanEditForm] = useState(true);
@@ -70,7 +67,8 @@ const SimulationForm: React.FC<SimulationFormProps> = ({
 
     const parsedParams: SimulationConfig = {
       n_nodes: parseInt(params.nNodes),
-      // n_measurements: parseInt(params.nMeasurements),
+      n_measurements: parseInt(params.nMeasurements),
+      estimators: ['ls', 'ekf'],
       n_epochs: parseInt(params.nEpochs),
       h3_resolution: parseInt(params.h3Resolution),
       // accuracy of position assertions: convert km stddev to meters^2 variance
@@ -147,10 +145,10 @@ const SimulationForm: React.FC<SimulationFormProps> = ({
       <h2 className="text-xl font-bold mb-2">Proximum Simulation</h2>
       <form onSubmit={handleSubmit(onSubmit)} className="space-y-2">
         <div className="grid grid-cols-2 gap-2">
-          <FormField name='nNodes' control={control} watch={watch} setShowHelp={setShowHelp} disabled={!canEditForm} options={{ min: parseInt(compilerParams.n_measurements) + 1 }} />
+          <FormField name='nNodes' control={control} watch={watch} setShowHelp={setShowHelp} disabled={!canEditForm} options={{ min: parseInt(watch('nMeasurements')) + 1 }} />
           <FormField name='nEpochs' control={control} watch={watch} setShowHelp={setShowHelp} disabled={!canEditForm} options={{ min: 1, max: 10000 }} />
         </div>
-        {/* <FormField name='nMeasurements' control={control} watch={watch} setShowHelp={setShowHelp} options={{ readonly: true }} disabled={!canEditForm} /> */}
+        <FormField name='nMeasurements' control={control} watch={watch} setShowHelp={setShowHelp} options={{ min: 4, max: 100 }} disabled={!canEditForm} />
 
 
         <button
@@ -181,9 +181,7 @@ export const helpTexts: FormDescriptor = {
   nMeasurements: `
   The number of distance measurements between node pairs used within each position estimation. For each measurement, one node pings another node and waits for a signed pong response. The response time puts an upper bound on the distance to the other node.
   
-  Increasing the number of measurements improves position accuracy (and computational difficulty).
-
-  This parameter is set during compilation and cannot be changed here.
+  Increasing the number of measurements improves position accuracy (and computational difficulty). At least 4 are needed to estimate a position.
   `,
   nEpochs: `
   The number of times to estimate the position of each node in the simulation.
@@ -20,12 +20,12 @@ export const SimulationMapContent = ({ nodes }: { nodes: Node[] | undefined }) =
     const assertedPolygonBoundary = cellToBoundary(node.asserted_index);
     const trueLatLngDeg = [node.true_wgs84.latitude, node.true_wgs84.longitude].map(rad2deg) as [number, number];
     const assertedLatLngDeg = [node.asserted_wgs84.latitude, node.asserted_wgs84.longitude].map(rad2deg) as [number, number];
-    // const kfEstLatLngDeg = [node.kf_estimated_wgs84.latitude, node.kf_estimated_wgs84.longitude].map(rad2deg) as [number, number];
-    const lsEstLatLngDeg = [node.ls_estimated_wgs84.latitude, node.ls_estimated_wgs84.longitude].map(rad2deg) as [number, number];
+    const kfEstLatLngDeg = [node.estimates.ekf.wgs84.latitude, node.estimates.ekf.wgs84.longitude].map(rad2deg) as [number, number];
+    const lsEstLatLngDeg = [node.estimates.ls.wgs84.latitude, node.estimates.ls.wgs84.longitude].map(rad2deg) as [number, number];
 
     // Convert covariances to standard deviations: the ellipse represents the 1 Std. Dev. confidence interval.
-    // const ellipseRadii1StdDev = [node.kf_en_variance_semimajor_axis_length, node.kf_en_variance_semiminor_axis_length].map(Math.sqrt) as [number, number];
-    // const ellipseTilt = rad2deg(Math.atan2(node.kf_en_variance_semimajor_axis[1], node.kf_en_variance_semimajor_axis[0]));
+    // const ellipseRadii1StdDev = [node.estimates.ekf.en_variance_semimajor_axis_length, node.estimates.ekf.en_variance_semiminor_axis_length].map(Math.sqrt) as [number, number];
+    // const ellipseTilt = rad2deg(Math.atan2(node.estimates.ekf.en_variance_semimajor_axis[1], node.estimates.ekf.en_variance_semimajor_axis[0]));
     // const ellipseConfig: EllipseProps = {
     //   center: kfEstLatLngDeg,
     //   radii: ellipseRadii1StdDev,
@@ -41,9 +41,9 @@ export const SimulationMapContent = ({ nodes }: { nodes: Node[] | undefined }) =
         <Polygon positions={assertedPolygonBoundary} color={COLORS.pink} fillColor={COLORS.pink} fillOpacity={0.2} weight={1}>
           <NodeDescriptionPopup node={node} positionType={POSITION_TYPE.assertedCell} />
         </Polygon>
-        {/* <CircleMarker center={kfEstLatLngDeg} color={COLORS.blue} fill fillColor={COLORS.blue} radius={3}>
+        <CircleMarker center={kfEstLatLngDeg} color={COLORS.blue} fill fillColor={COLORS.blue} radius={3}>
           <NodeDescriptionPopup node={node} positionType={POSITION_TYPE.kfEstimated} />
-        </CircleMarker> */}
+        </CircleMarker>
         <CircleMarker center={lsEstLatLngDeg} color={COLORS.green} fill fillColor={COLORS.green} radius={3}>
           <NodeDescriptionPopup node={node} positionType={POSITION_TYPE.lsEstimated} />
         </CircleMarker>
@@ -55,7 +55,7 @@ export const SimulationMapContent = ({ nodes }: { nodes: Node[] | undefined }) =
         </Ellipse> */}
 
         <GeodesicLine points={[trueLatLngDeg, assertedLatLngDeg]} options={{ color: COLORS.grey, weight: 0.4 }} />
-        {/* <GeodesicLine points={[trueLatLngDeg, kfEstLatLngDeg]} options={{ color: COLORS.grey, weight: 0.1 }} /> */}
+        <GeodesicLine points={[trueLatLngDeg, kfEstLatLngDeg]} options={{ color: COLORS.grey, weight: 0.1 }} />
         <GeodesicLine points={[trueLatLngDeg, lsEstLatLngDeg]} options={{ color: COLORS.grey, weight: 0.4 }} />
         {/* <Marker position={trueLatLngDeg} icon={L.divIcon({
           className: 'leaflet-custom-marker',
@@ -1,8 +1,8 @@
-import React, { useEffect, useState } from 'react';
+import React, { useState } from 'react';
 import SimulationForm from './SimulationForm';
 import StatsView from './StatsView';
-import { CompilerParams, PATHS, SimulationConfig, Stats } from '../types';
-import { InitOutput, get_compile_parameters } from 'rust-proximum-simulation';
+import { PATHS, SimulationConfig, Stats } from '../types';
+import { InitOutput } from 'rust-proximum-simulation';
 import { useNavigate } from 'react-router-dom';
 
 interface SimulationOverlayProps {
@@ -22,14 +22,8 @@ const SimulationOverlay = ({
 }: SimulationOverlayProps) => {
   const [isFormCollapsed, setIsFormCollapsed] = useState(false);
   const [isStatsCollapsed, setIsStatsCollapsed] = useState(false);
-  const [compilerParams, setCompilerParams] = useState<CompilerParams>();
   const navigate = useNavigate();
 
-  useEffect(() => {
-    const paramString = get_compile_parameters();
-    setCompilerParams(JSON.parse(paramString));
-  }, []);
-
   const toggleForm = () => setIsFormCollapsed(!isFormCollapsed);
   const toggleStats = () => setIsStatsCollapsed(!isStatsCollapsed);
 
@@ -50,11 +44,10 @@ const SimulationOverlay = ({
             {isFormCollapsed ? '▼ Show Controls' : '▲ Hide Controls'}
           </button>
 
-          {!isFormCollapsed && compilerParams && (
+          {!isFormCollapsed && (
             <SimulationForm
               runSimulation={runSimulation}
               resetSimulation={resetSimulation}
-              compilerParams={compilerParams}
               progress={progress}
             />
           )}
@@ -1,7 +1,7 @@
 import React, { useState, useEffect } from 'react';
 import 'leaflet/dist/leaflet.css';
 import rustWasmInit, { initialize_simulation, InitOutput, run_simulation_chunk } from 'rust-proximum-simulation';
-import { Simulation, SimulationConfig } from '../types';
+import { ChunkResult, Simulation, SimulationConfig } from '../types';
 import SimulationOverlay from './SimulationOverlay';
 import { SimulationMapContent } from './SimulationMapContent';
 import Map from '../Map';
@@ -47,10 +47,14 @@ const SimulationView = () => {
       const remainingEpochs = totalEpochs - i * CHUNK_SIZE;
       const chunkSize = Math.min(CHUNK_SIZE, remainingEpochs);
 
-      const { nodes, stats } = await run_simulation_chunk(chunkSize);
+      const { nodes, stats, seed }: ChunkResult = await run_simulation_chunk(chunkSize);
 
       setNodes(nodes);
       setStats(stats);
+      if (i === 0) {
+        // set `seed` in the config to reproduce this run
+        console.log('simulation seed:', seed);
+      }
 
       setProgress((i + 1) / chunks * 100);
 
@@ -12,11 +12,13 @@ import {
 import { COLORS, Simulation } from '../types';
 
 const StatsView = ({ stats }: { stats: Simulation['stats'] }) => {
-  const data = stats.kf_estimation_rms_error.map((_, i) => ({
+  const data = stats.assertion_rms_error.map((_, i) => ({
     epoch: i + 1,
     // convert from m to km
-    kfError: stats.kf_estimation_rms_error[i] / 1000,
-    lsError: stats.ls_estimation_rms_error[i] / 1000,
+    kfError: stats.estimation_rms_error.ekf?.[i] / 1000,
+    ukfError: stats.estimation_rms_error.ukf?.[i] / 1000,
+    pfError: stats.estimation_rms_error.pf?.[i] / 1000,
+    lsError: stats.estimation_rms_error.ls?.[i] / 1000,
     assertedError: stats.assertion_rms_error[i] / 1000,
   }));
 
@@ -39,6 +41,27 @@ const StatsView = ({ stats }: { stats: Simulation['stats'] }) => {
               contentStyle={{ backgroundColor: '#1d1d1d', color: '#fff' }}
               formatter={formatTooltipValue}
             />
+            <Line
+              type="monotone"
+              dataKey="kfError"
+              stroke={COLORS.blue}
+              name="Kalman Filter Err."
+              dot={{ fill: '#00000000', radius: 0, stroke: "#00000000" }}
+            />
+            <Line
+              type="monotone"
+              dataKey="ukfError"
+              stroke={COLORS.purple}
+              name="Unscented Kalman Filter Err."
+              dot={{ fill: '#00000000', radius: 0, stroke: "#00000000" }}
+            />
+            <Line
+              type="monotone"
+              dataKey="pfError"
+              stroke={COLORS.orange}
+              name="Particle Filter Err."
+              dot={{ fill: '#00000000', radius: 0, stroke: "#00000000" }}
+            />
             <Line
               type="monotone"
               dataKey="lsError"
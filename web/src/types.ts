@@ -20,10 +20,6 @@ export enum COLORS {
   white = "#ffffff"
 }
 
-export interface CompilerParams {
-  n_measurements: string;
-}
-
 export interface Node {
   id: number;
   true_index: number;
@@ -34,36 +30,177 @@ export interface Node {
   asserted_index: string;
   asserted_position: [number, number, number];
   asserted_wgs84: WGS84;
-  ls_estimated_index: string;
-  ls_estimated_position: [number, number, number];
-  ls_estimated_wgs84: WGS84;
-  kf_estimated_index: string;
-  kf_estimated_position: [number, number, number];
-  kf_estimated_wgs84: WGS84;
-  kf_estimated_beta: number,
-  kf_estimated_tau: number,
-  kf_estimation_variance: [number, number, number];
-  kf_en_variance_semimajor_axis: [number, number],
-  kf_en_variance_semiminor_axis: [number, number],
-  kf_en_variance_semimajor_axis_length: number,
-  kf_en_variance_semiminor_axis_length: number,
+  // false if the node lies about its position
+  honest: boolean;
+  // trust (0 to 1] in the consistency of this node's measurements
+  trust: number;
+  // delays this node adds to its responses to the victim nodes, if it is a delay attacker
+  delay_attack: { strategy: DelayStrategy; victims: number[] } | null;
+  // offset (s) of this node's clock from true time
+  clock_offset: number;
+  // sequential test of this node's assertion: accumulated log-likelihood ratio of "elsewhere" against "at its
+  // assertion", and the epoch and outcome once decided
+  sprt_log_likelihood_ratio: number;
+  sprt_decision: { epoch: number; outcome: 'accepted' | 'rejected' } | null;
+  // position the estimators started from
+  initial_position: [number, number, number];
+  // position estimates keyed by estimator name (e.g. 'ls', 'ekf')
+  estimates: Record<string, Estimate>;
+}
+
+export interface Estimate {
+  index: string;
+  position: [number, number, number];
+  wgs84: WGS84;
+  beta: number | null;
+  tau: number | null;
+  en_variance_semimajor_axis: [number, number],
+  en_variance_semimajor_axis_length: number,
+  en_variance_semiminor_axis_length: number,
+  // effective number of particles, for sample based estimators
+  effective_sample_size: number | null;
+  // weight (0 to 1) given to each peer's measurement in the last update, for estimators that reweight measurements
+  measurement_weights: { peer: number; weight: number }[] | null;
+  // number of times the node rejected each peer's measurement, keyed by peer id, for outlier rejecting estimators
+  rejected_peers: Record<string, number> | null;
 }
 
 export interface Stats {
-  ls_estimation_rms_error: number[];
-  kf_estimation_rms_error: number[];
+  // keyed by estimator name
+  estimation_rms_error: Record<string, number[]>;
   assertion_rms_error: number[];
+  // cumulative filter re-initializations after divergence, keyed by estimator name
+  estimator_resets: Record<string, number[]>;
+  // the same errors restricted to honest / dishonest nodes (empty without dishonest nodes)
+  honest_estimation_rms_error: Record<string, number[]>;
+  dishonest_estimation_rms_error: Record<string, number[]>;
+  honest_assertion_rms_error: number[];
+  dishonest_assertion_rms_error: number[];
+  // fraction of dishonest nodes whose estimate stays within the detection distance of their assertion, keyed by
+  // estimator name
+  dishonest_undetected_fraction: Record<string, number[]>;
+  // chi-square false assertion detection, keyed by the name of each estimator with a covariance: per-epoch fraction of
+  // honest (false positive, also in all-honest runs) and dishonest (true positive) nodes flagged and area under the ROC
+  // curve
+  detection_true_positive_rate: Record<string, number[]>;
+  detection_false_positive_rate: Record<string, number[]>;
+  detection_auc: Record<string, number[]>;
+  // sequential test of assertions: cumulative fraction of honest (false positive, also in all-honest runs) and
+  // dishonest (true positive, empty without dishonest nodes) nodes rejected, and of all nodes decided (empty unless sprt
+  // is on)
+  sprt_true_positive_rate: number[];
+  sprt_false_positive_rate: number[];
+  sprt_decided_fraction: number[];
+  // ROC curve at the latest epoch
+  detection_roc: Record<string, { threshold: number; true_positive_rate: number; false_positive_rate: number }[]>;
+  // first epoch each node was flagged (by node id), and mean epochs until dishonest nodes are first flagged
+  first_detection_epoch: Record<string, (number | null)[]>;
+  detection_latency: Record<string, number | null>;
+}
+
+// How a delay attacker delays its responses: a constant delay (s), the delay that fakes its asserted position, or a
+// uniformly random delay (s)
+export type DelayStrategy =
+  | { type: 'constant'; delay: number }
+  | { type: 'targeted' }
+  | { type: 'random'; delay_min: number; delay_max: number };
+
+// How dishonest nodes choose their asserted position: anywhere, one shared location (degrees), or a fixed offset (m,
+// degrees clockwise from north) from their true position
+export interface City {
+  latitude: number;
+  longitude: number;
+  // relative share of the nodes (default 1)
+  weight?: number;
+  // standard deviation (m) around the city
+  spread: number;
+}
+
+// H3 cells of any resolutions (indices or hex strings), or a GeoJSON Polygon / MultiPolygon geometry
+export type Region =
+  | { type: 'cells'; cells: (number | string)[] }
+  | { type: 'Polygon'; coordinates: number[][][] }
+  | { type: 'MultiPolygon'; coordinates: number[][][][] };
+
+// an imported node: true location (degrees, m) and optionally its asserted location, message speed and latency
+export interface NodeLocation {
+  id?: string | null;
+  latitude: number;
+  longitude: number;
+  altitude?: number;
+  asserted_latitude?: number | null;
+  asserted_longitude?: number | null;
+  asserted_altitude?: number | null;
+  beta?: number | null;
+  tau?: number | null;
+}
+
+// measured round trip time (s) between two imported nodes (indices into node_locations)
+export interface RttSample {
+  source: number;
+  target: number;
+  rtt: number;
+  epoch?: number | null;
+}
+
+export type PlacementStrategy =
+  | { type: 'uniform' }
+  | { type: 'cities'; cities: City[] }
+  | { type: 'metros'; spread: number }
+  | { type: 'bounds'; min_latitude: number; max_latitude: number; min_longitude: number; max_longitude: number };
+
+export type PropagationModel =
+  | { type: 'chord' }
+  | { type: 'great_circle' }
+  | {
+    type: 'route_inflated';
+    inflation: number;
+    short_route_inflation?: number;
+    // m
+    short_route_distance?: number;
+    noise?: number;
+  };
+
+// per-direction detour (log-normal spread of a factor >= 1) and mean exponential queueing delay (s)
+export interface AsymmetricPaths {
+  inflation_spread?: number;
+  queueing_delay?: number;
 }
 
+export type LieStrategy =
+  | { type: 'random' }
+  | { type: 'target_city'; latitude: number; longitude: number }
+  | { type: 'offset'; distance: number; bearing: number };
+
 // These are the parameters we set for a new simulation
 export interface SimulationConfig {
   // we define these
   // number of nodes in the simulation
   n_nodes: number;
+  // number of time-of-flight measurements per position update
+  n_measurements: number;
   // number of times to run the kalman filter step
   n_epochs: number;
   // position resolution at which nodes assert location
   h3_resolution: number;
+  // random seed: identical configs with the same seed give identical runs. Drawn at random (at most
+  // Number.MAX_SAFE_INTEGER) if not set; the seed used is returned with every chunk of results.
+  seed?: number;
+  // position estimators to run: 'ls' (least squares), 'ekf' (extended Kalman filter), 'ukf' (unscented Kalman filter), 'pf' (particle filter),
+  // 'joint' (centralized solver over all measurements), 'ransac' (least squares with RANSAC outlier rejection)
+  estimators?: string[];
+  // estimator starting positions: 'asserted' or 'mds' (multidimensional scaling of bootstrap measurements)
+  init_strategy?: string;
+  mds_bootstrap_epochs?: number;
+  // how true node locations are drawn
+  placement?: PlacementStrategy;
+  // region the nodes are placed and assert positions in, and whether estimates leaving it are moved back to it
+  region?: Region | null;
+  region_constrain_estimates?: boolean;
+  // imported node locations used instead of placement (sets n_nodes); see initialize_simulation_with_nodes
+  node_locations?: NodeLocation[] | null;
+  // measured RTTs between the imported nodes, replayed instead of simulating measurements
+  rtt_dataset?: RttSample[] | null;
   // accuracy at which nodes assert position (m^2)
   asserted_position_variance: number;
   // message speed range [min, max] as a fraction of c, the speed of light 
@@ -78,9 +215,51 @@ export interface SimulationConfig {
   tau_variance: number;
   // max message range (m)
   message_distance_max: number;
+  // path messages truly travel, and the path the estimators assume
+  propagation?: PropagationModel;
+  model_propagation?: PropagationModel;
+  // ping and pong take different paths (symmetric if null)
+  asymmetric_paths?: AsymmetricPaths | null;
+  // variance (s^2) of the node clocks' offsets from true time
+  clock_offset_variance?: number;
+  // give estimators one-way times from per-direction timestamps
+  one_way_timestamps?: boolean;
+  // fraction of nodes asserting a false position, and how they choose it
+  dishonest_fraction?: number;
+  lie_strategy?: LieStrategy;
+  // delays dishonest nodes add to their responses, and the fraction of peers they delay
+  delay_strategy?: DelayStrategy | null;
+  delay_victim_fraction?: number;
+  // one operator running all dishonest nodes from a single location, asserting them over a disk (degrees, m)
+  sybil?: { latitude: number; longitude: number; radius: number } | null;
+  // dishonest nodes fabricate their mutual round trip times to match their assertions
+  colluding?: boolean;
+  // false alarm rate of the chi-square test of assertions against the estimates
+  detection_false_alarm_rate?: number;
+  // distance (m) from its assertion within which a dishonest node's estimate goes undetected
+  detection_distance?: number | null;
+  // sequential test of each node's assertion over epochs, its target error rates and time residual stddev (s; null to
+  // estimate it from the residuals each epoch)
+  sprt?: boolean;
+  sprt_false_accusation_rate?: number;
+  sprt_missed_detection_rate?: number;
+  sprt_residual_sigma?: number | null;
   ls_model_beta: number;
   ls_model_tau: number;
   ls_tolerance: number;
+  // robust loss on the least squares time residuals and its scale (s)
+  ls_loss?: 'squared' | 'huber' | 'cauchy' | 'tukey';
+  ls_loss_scale?: number;
+  // track the consistency of the measurements to each node, down-weighting and no longer measuring untrusted nodes
+  reputation?: boolean;
+  // weight of a node's residual history against each new residual, and the trust below which it is no longer measured
+  reputation_decay?: number;
+  reputation_threshold?: number;
+  // variance (s^2) of an honest node's time residuals (defaults to kf_model_tof_observation_variance)
+  reputation_residual_variance?: number;
+  // RANSAC minimal subsets per update and inlier residual threshold (s)
+  ransac_iterations?: number;
+  ransac_threshold?: number;
   ls_iterations: number;
   // model position state update varians (m^2)
   kf_model_position_variance: number;
@@ -94,6 +273,18 @@ export interface SimulationConfig {
   kf_model_tau_variance: number;
   // model time of flight observation variance (s^2)
   kf_model_tof_observation_variance: number;
+  // assumed variance (s^2) of the node clock offsets, for one-way times
+  kf_model_clock_offset_variance?: number;
+  // position variance (m^2) beyond which the kalman filter is considered diverged and re-initialized
+  kf_max_position_variance?: number;
+  // moving average of the normalized innovation squared per measurement beyond which a kalman filter is re-initialized
+  kf_max_normalized_innovation?: number;
+  // particles per node for the particle filter
+  pf_n_particles?: number;
+  // centralized joint solver: epochs of measurements, iterations and position tolerance (m) per solve
+  joint_window_epochs?: number;
+  joint_iterations?: number;
+  joint_tolerance?: number;
 }
 
 // units are in km, ms, and std deviation
@@ -130,6 +321,14 @@ export type SimulationParamFields = {
   leastSquaresIterations: string
 };
 
+// this is what we get back from each run_simulation_chunk call
+export interface ChunkResult {
+  nodes: Node[];
+  stats: Stats;
+  // seed of the run: set it in the config to reproduce the run
+  seed: number;
+}
+
 // this is what we get back from the Rust simulate call
 export interface Simulation {
   n_nodes: number;